@@ -22,15 +22,20 @@ function libPath() {
 const lib = ffi.Library(libPath(), {
   bstseal_encode: ['int', [u8Ptr, sizeT, ref.refType(u8Ptr), ref.refType(sizeT)]],
   bstseal_decode: ['int', [u8Ptr, sizeT, ref.refType(u8Ptr), ref.refType(sizeT)]],
+  bstseal_encode_keyed: ['int', [u8Ptr, sizeT, u8Ptr, ref.refType(u8Ptr), ref.refType(sizeT)]],
+  bstseal_decode_keyed: ['int', [u8Ptr, sizeT, u8Ptr, ref.refType(u8Ptr), ref.refType(sizeT)]],
+  bstseal_verify_signature: ['int', [u8Ptr, sizeT, u8Ptr]],
   bstseal_free: ['void', [voidPtr]],
   bstseal_set_license_secret: ['int', ['string']],
   bstseal_set_license_key: ['int', ['string']],
+  bstseal_set_revocation_list: ['int', ['string']],
+  bstseal_reload_license: ['int', []],
 });
 
-function callAndReturn(func, inputBuf) {
+function callAndReturn(func, inputBuf, ...extra) {
   const outPtrPtr = ref.alloc(u8Ptr);
   const outLenPtr = ref.alloc(sizeT);
-  const code = lib[func](inputBuf, inputBuf.length, outPtrPtr, outLenPtr);
+  const code = lib[func](inputBuf, inputBuf.length, ...extra, outPtrPtr, outLenPtr);
   if (code !== 0) {
     throw new Error(`${func} failed with code ${code}`);
   }
@@ -47,10 +52,12 @@ function check(code, fn) {
   if (code !== 0) throw new Error(`${fn} failed with code ${code}`);
 }
 
-// Auto-initialize secret/key if env vars present
-if (process.env.LICENSE_SECRET) {
-  check(lib.bstseal_set_license_secret(process.env.LICENSE_SECRET), 'bstseal_set_license_secret');
+function checkKey(key) {
+  if (!Buffer.isBuffer(key) || key.length !== 32) throw new TypeError('key must be a 32-byte Buffer');
 }
+
+// Auto-initialize the license key if present. Legacy `LICENSE_SECRET` is read
+// by the library itself when built with `legacy-hmac`.
 if (process.env.BSTSEAL_LICENSE) {
   check(lib.bstseal_set_license_key(process.env.BSTSEAL_LICENSE), 'bstseal_set_license_key');
 }
@@ -64,10 +71,33 @@ module.exports = {
     if (!Buffer.isBuffer(buffer)) throw new TypeError('buffer must be a Buffer');
     return callAndReturn('bstseal_decode', buffer);
   },
+  encodeKeyed(buffer, key) {
+    if (!Buffer.isBuffer(buffer)) throw new TypeError('buffer must be a Buffer');
+    checkKey(key);
+    return callAndReturn('bstseal_encode_keyed', buffer, key);
+  },
+  decodeKeyed(buffer, key) {
+    if (!Buffer.isBuffer(buffer)) throw new TypeError('buffer must be a Buffer');
+    checkKey(key);
+    return callAndReturn('bstseal_decode_keyed', buffer, key);
+  },
+  verifySignature(buffer, publicKey) {
+    if (!Buffer.isBuffer(buffer)) throw new TypeError('buffer must be a Buffer');
+    checkKey(publicKey);
+    return lib.bstseal_verify_signature(buffer, buffer.length, publicKey) === 0;
+  },
+  // Only succeeds when the native library was built with `legacy-hmac`.
   setLicenseSecret(secret) {
     check(lib.bstseal_set_license_secret(secret), 'bstseal_set_license_secret');
   },
   setLicenseKey(key) {
     check(lib.bstseal_set_license_key(key), 'bstseal_set_license_key');
   },
+  setRevocationList(list) {
+    check(lib.bstseal_set_revocation_list(list), 'bstseal_set_revocation_list');
+  },
+  // Returns true if the license key (re-read now) is valid.
+  reloadLicense() {
+    return lib.bstseal_reload_license() === 0;
+  },
 };
@@ -24,6 +24,9 @@ namespace Bstseal
             DecodeFail = 3,
             IntegrityFail = 4,
             AllocFail = 5,
+            LicenseError = 6,
+            SignatureFail = 7,
+            SecretRequired = 8,
         }
 
         [DllImport(DLL, CallingConvention = CallingConvention.Cdecl)]
@@ -32,9 +35,21 @@ namespace Bstseal
         [DllImport(DLL, CallingConvention = CallingConvention.Cdecl)]
         private static extern int bstseal_decode(byte[] input, UIntPtr len, out IntPtr outPtr, out UIntPtr outLen);
 
+        [DllImport(DLL, CallingConvention = CallingConvention.Cdecl)]
+        private static extern int bstseal_encode_keyed(byte[] input, UIntPtr len, byte[] key, out IntPtr outPtr, out UIntPtr outLen);
+
+        [DllImport(DLL, CallingConvention = CallingConvention.Cdecl)]
+        private static extern int bstseal_decode_keyed(byte[] input, UIntPtr len, byte[] key, out IntPtr outPtr, out UIntPtr outLen);
+
+        [DllImport(DLL, CallingConvention = CallingConvention.Cdecl)]
+        private static extern int bstseal_verify_signature(byte[] input, UIntPtr len, byte[] publicKey);
+
         [DllImport(DLL, CallingConvention = CallingConvention.Cdecl)]
         private static extern void bstseal_free(IntPtr ptr);
 
+        /// <summary>Size in bytes of MAC keys and Ed25519 public keys.</summary>
+        public const int KeySize = 32;
+
         public static byte[] Encode(byte[] data)
         {
             if (data == null) throw new ArgumentNullException(nameof(data));
@@ -55,6 +70,59 @@ namespace Bstseal
             return CopyAndFree(p, l);
         }
 
+        /// <summary>Encodes and seals the output with a keyed MAC.</summary>
+        public static byte[] EncodeKeyed(byte[] data, byte[] key)
+        {
+            if (data == null) throw new ArgumentNullException(nameof(data));
+            CheckKey(key);
+            IntPtr p; UIntPtr l;
+            var code = bstseal_encode_keyed(data, (UIntPtr)data.Length, key, out p, out l);
+            if (code != (int)ErrorCode.Ok)
+                throw new Exception($"BST-SEAL encode failed: {(ErrorCode)code}");
+            return CopyAndFree(p, l);
+        }
+
+        /// <summary>Verifies the keyed MAC and decodes.</summary>
+        public static byte[] DecodeKeyed(byte[] data, byte[] key)
+        {
+            if (data == null) throw new ArgumentNullException(nameof(data));
+            CheckKey(key);
+            IntPtr p; UIntPtr l;
+            var code = bstseal_decode_keyed(data, (UIntPtr)data.Length, key, out p, out l);
+            if (code != (int)ErrorCode.Ok)
+                throw new Exception($"BST-SEAL decode failed: {(ErrorCode)code}");
+            return CopyAndFree(p, l);
+        }
+
+        /// <summary>
+        /// Returns true if <paramref name="data"/> carries a valid Ed25519
+        /// signature by <paramref name="publicKey"/> (32 bytes).
+        /// </summary>
+        public static bool VerifySignature(byte[] data, byte[] publicKey)
+        {
+            if (data == null) throw new ArgumentNullException(nameof(data));
+            CheckKey(publicKey);
+            return bstseal_verify_signature(data, (UIntPtr)data.Length, publicKey) == (int)ErrorCode.Ok;
+        }
+
+        /// <summary>
+        /// Decodes <paramref name="data"/> only if it is signed by
+        /// <paramref name="publicKey"/>; throws otherwise.
+        /// </summary>
+        public static byte[] DecodeSigned(byte[] data, byte[] publicKey)
+        {
+            if (!VerifySignature(data, publicKey))
+                throw new Exception($"BST-SEAL decode failed: {ErrorCode.SignatureFail}");
+            return Decode(data);
+        }
+
+        private static void CheckKey(byte[] key)
+        {
+            if (key == null) throw new ArgumentNullException(nameof(key));
+            if (key.Length != KeySize)
+                throw new ArgumentException($"key must be {KeySize} bytes", nameof(key));
+        }
+
         private static byte[] CopyAndFree(IntPtr ptr, UIntPtr len)
         {
             try
@@ -0,0 +1,181 @@
+//! Extracting archives for `unpack`.
+//!
+//! Entry paths come from an untrusted index. Unless `--unsafe-paths` is
+//! given, the whole index is checked with [`ArchiveReader::safe_paths`]
+//! before anything is written, and no entry is written through a symlink,
+//! whether it came from the archive or was already in the output directory.
+
+use crate::keys::Keys;
+use crate::meta;
+use anyhow::bail;
+use bstseal_core::archive::{ArchiveReader, EntryKind};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, Write};
+use std::path::Path;
+
+pub fn unpack_archive<R: Read + Seek>(
+    archive: &mut ArchiveReader<R>,
+    out_dir: &Path,
+    keys: &Keys,
+    unsafe_paths: bool,
+    same_permissions: bool,
+) -> anyhow::Result<()> {
+    let names = if unsafe_paths {
+        archive.entries().iter().map(|e| e.path.clone()).collect()
+    } else {
+        archive.safe_paths()?
+    };
+    fs::create_dir_all(out_dir)?;
+    let mut dirs = Vec::new();
+    for (e, name) in archive.entries().to_vec().into_iter().zip(names) {
+        let out_path = out_dir.join(&name);
+        if !unsafe_paths {
+            check_parents(out_dir, &name)?;
+        }
+        if let Some(p) = out_path.parent() {
+            fs::create_dir_all(p)?;
+        }
+        match &e.kind {
+            EntryKind::File => {
+                let data = archive.read_with(&e, |sealed| keys.open_options(sealed))?;
+                // Replace a symlink at the target rather than follow it.
+                if is_symlink(&out_path) {
+                    fs::remove_file(&out_path)?;
+                }
+                let mut out = BufWriter::new(File::create(&out_path)?);
+                out.write_all(&data)?;
+                out.flush()?;
+            }
+            EntryKind::Directory => {
+                if !unsafe_paths && is_symlink(&out_path) {
+                    bail!("refusing to extract {name}: it is a symlink on disk");
+                }
+                fs::create_dir_all(&out_path)?;
+                // Applied last, so that extracting the contents does not
+                // change the mtime or fail on a read-only mode.
+                dirs.push((out_path, e.metadata));
+                continue;
+            }
+            #[cfg(unix)]
+            EntryKind::Symlink { target } => {
+                if fs::symlink_metadata(&out_path).is_ok() {
+                    fs::remove_file(&out_path)?;
+                }
+                std::os::unix::fs::symlink(target, &out_path)?;
+            }
+            #[cfg(not(unix))]
+            EntryKind::Symlink { .. } => {
+                eprintln!("warning: skipping symlink {name}");
+                continue;
+            }
+        }
+        meta::restore(&out_path, &e.metadata, same_permissions)?;
+    }
+    for (path, metadata) in dirs.iter().rev() {
+        meta::restore(path, metadata, same_permissions)?;
+    }
+    Ok(())
+}
+
+/// Fails if a directory between `out_dir` and the entry `name` is a symlink.
+fn check_parents(out_dir: &Path, name: &str) -> anyhow::Result<()> {
+    let mut path = out_dir.to_path_buf();
+    let parents = name.split('/').collect::<Vec<_>>();
+    for part in &parents[..parents.len() - 1] {
+        path.push(part);
+        if is_symlink(&path) {
+            bail!(
+                "refusing to extract {name} through symlink {}",
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::keys::KeyArgs;
+    use bstseal_core::archive::{ArchiveWriter, Metadata};
+    use bstseal_core::seal::SealOptions;
+    use std::io::Cursor;
+    use std::os::unix::fs::symlink;
+
+    const TRAVERSAL: &[u8] = include_bytes!("../../bstseal-core/testdata/malicious/traversal.bsa");
+    const SYMLINK_ESCAPE: &[u8] =
+        include_bytes!("../../bstseal-core/testdata/malicious/symlink-escape.bsa");
+    const SYMLINK_CHAIN: &[u8] =
+        include_bytes!("../../bstseal-core/testdata/malicious/symlink-chain.bsa");
+
+    /// Installs a key from the development issuer that test builds trust,
+    /// with usage metered into a throwaway ledger.
+    fn licensed() {
+        #[cfg(feature = "licensing")]
+        {
+            use bstseal_core::license::{self, dev_issuer, Tier};
+            static ONCE: std::sync::Once = std::sync::Once::new();
+            ONCE.call_once(|| {
+                let ledger = tempfile::tempdir().unwrap().keep().join("usage.ledger");
+                std::env::set_var("BSTSEAL_USAGE_LEDGER", ledger);
+                license::set_license_key(dev_issuer::license(Tier::Startup, 1)).unwrap();
+            });
+        }
+    }
+
+    fn unpack(data: &[u8], out_dir: &Path, unsafe_paths: bool) -> anyhow::Result<()> {
+        licensed();
+        let keys = Keys::load(&KeyArgs::default())?;
+        let mut archive = ArchiveReader::new(Cursor::new(data))?;
+        unpack_archive(&mut archive, out_dir, &keys, unsafe_paths, false)
+    }
+
+    #[test]
+    fn malicious_archives_write_nothing_outside() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("a/b/out");
+        assert!(unpack(TRAVERSAL, &out, false).is_err());
+        assert!(unpack(SYMLINK_ESCAPE, &out, false).is_err());
+        assert!(unpack(SYMLINK_CHAIN, &out, false).is_err());
+        assert!(fs::symlink_metadata(out.join("l")).is_err());
+        assert!(!dir.path().join("a/b/evil.txt").exists());
+        assert!(!dir.path().join("a/evil.txt").exists());
+
+        unpack(TRAVERSAL, &out, true).unwrap();
+        assert_eq!(
+            fs::read(dir.path().join("a/b/evil.txt")).unwrap(),
+            b"hello world\n"
+        );
+    }
+
+    #[test]
+    fn existing_symlinks_are_not_written_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let (out, elsewhere) = (dir.path().join("out"), dir.path().join("elsewhere"));
+        fs::create_dir_all(&out).unwrap();
+        fs::create_dir(&elsewhere).unwrap();
+        fs::write(elsewhere.join("keep.txt"), "keep").unwrap();
+        symlink(&elsewhere, out.join("docs")).unwrap();
+        symlink(elsewhere.join("keep.txt"), out.join("keep.txt")).unwrap();
+
+        licensed();
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default());
+        writer
+            .add_file("docs/a.txt", b"a", Metadata::default())
+            .unwrap();
+        let through_dir = writer.finish().unwrap();
+        assert!(unpack(&through_dir, &out, false).is_err());
+        assert!(!elsewhere.join("a.txt").exists());
+
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default());
+        writer.add("keep.txt", b"replaced").unwrap();
+        unpack(&writer.finish().unwrap(), &out, false).unwrap();
+        assert!(!is_symlink(&out.join("keep.txt")));
+        assert_eq!(fs::read(out.join("keep.txt")).unwrap(), b"replaced");
+        assert_eq!(fs::read(elsewhere.join("keep.txt")).unwrap(), b"keep");
+    }
+}
@@ -0,0 +1,185 @@
+//! Loading key material from files given on the command line.
+
+use anyhow::{bail, Context};
+use bstseal_core::crypto::Secret;
+use bstseal_core::frame::{FLAG_ENCRYPTED, FLAG_KEYED, MAGIC};
+use bstseal_core::integrity::KEY_SIZE;
+use bstseal_core::seal::{OpenOptions, SealOptions};
+use clap::Args;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Key options shared by the commands that write or read sealed data.
+#[derive(Args, Debug, Default)]
+pub struct KeyArgs {
+    /// 32-byte key (raw or 64 hex digits): MAC key, or encryption key for
+    /// encrypted files
+    #[clap(long, value_parser)]
+    pub key_file: Option<PathBuf>,
+
+    /// File holding the encryption password (one trailing newline ignored)
+    #[clap(long, value_parser)]
+    pub password_file: Option<PathBuf>,
+}
+
+/// Keys loaded from [`KeyArgs`].
+pub struct Keys {
+    key: Option<[u8; KEY_SIZE]>,
+    key_secret: Option<Secret>,
+    password: Option<Secret>,
+}
+
+impl Keys {
+    pub fn load(args: &KeyArgs) -> anyhow::Result<Self> {
+        let key = args.key_file.as_deref().map(read_key_file).transpose()?;
+        let password = match &args.password_file {
+            Some(path) => Some(Secret::password(read_password_file(path)?)),
+            None => None,
+        };
+        Ok(Self {
+            key,
+            key_secret: key.map(Secret::raw),
+            password,
+        })
+    }
+
+    /// Options for writing: with `encrypt`, the password (or else the key)
+    /// encrypts; without it, the key becomes a MAC key.
+    pub fn seal_options(&self, encrypt: bool) -> anyhow::Result<SealOptions<'_>> {
+        if !encrypt {
+            if self.password.is_some() {
+                bail!("--password-file requires --encrypt");
+            }
+            return Ok(SealOptions {
+                mac_key: self.key.as_ref(),
+                ..Default::default()
+            });
+        }
+        match self.password.as_ref().or(self.key_secret.as_ref()) {
+            Some(secret) => Ok(SealOptions {
+                encryption: Some(secret),
+                ..Default::default()
+            }),
+            None => bail!("--encrypt needs --password-file or --key-file"),
+        }
+    }
+
+    /// Options for reading `data`. The key is a decryption key for encrypted
+    /// frames and a MAC key otherwise.
+    pub fn open_options(&self, data: &[u8]) -> OpenOptions<'_> {
+        let encrypted = header_flags(data) & FLAG_ENCRYPTED != 0;
+        let encryption = self.password.as_ref().or(match encrypted {
+            true => self.key_secret.as_ref(),
+            false => None,
+        });
+        OpenOptions {
+            mac_key: self.mac_key(data),
+            decryption: encryption,
+            ..Default::default()
+        }
+    }
+
+    /// True if a file starting with `head` has to be opened as a whole
+    /// (encrypted frames, or a password that must be checked against one)
+    /// instead of going through the streaming decoder.
+    pub fn needs_whole_input(&self, head: &[u8]) -> bool {
+        self.password.is_some() || header_flags(head) & FLAG_ENCRYPTED != 0
+    }
+
+    /// MAC key to check the footer of `data` with.
+    pub fn mac_key(&self, data: &[u8]) -> Option<&[u8; KEY_SIZE]> {
+        match header_flags(data) & (FLAG_ENCRYPTED | FLAG_KEYED) {
+            FLAG_ENCRYPTED => None,
+            _ => self.key.as_ref(),
+        }
+    }
+}
+
+/// Flags byte of the frame header, `0` for legacy frames.
+fn header_flags(data: &[u8]) -> u8 {
+    match data.strip_prefix(&MAGIC) {
+        Some([flags, ..]) => *flags,
+        _ => 0,
+    }
+}
+
+/// Reads a 32-byte key (MAC, encryption or Ed25519): either exactly 32 raw
+/// bytes or 64 hex digits (surrounding whitespace ignored).
+pub fn read_key_file(path: &Path) -> anyhow::Result<[u8; KEY_SIZE]> {
+    let bytes = fs::read(path).with_context(|| format!("reading key file {}", path.display()))?;
+    parse_key(&bytes).with_context(|| format!("invalid key file {}", path.display()))
+}
+
+fn read_password_file(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut password =
+        fs::read(path).with_context(|| format!("reading password file {}", path.display()))?;
+    if password.ends_with(b"\n") {
+        password.pop();
+        if password.ends_with(b"\r") {
+            password.pop();
+        }
+    }
+    if password.is_empty() {
+        bail!("password file {} is empty", path.display());
+    }
+    Ok(password)
+}
+
+fn parse_key(bytes: &[u8]) -> anyhow::Result<[u8; KEY_SIZE]> {
+    if let Ok(raw) = <[u8; KEY_SIZE]>::try_from(bytes) {
+        return Ok(raw);
+    }
+    let text = bytes.trim_ascii();
+    if text.len() != KEY_SIZE * 2 {
+        bail!(
+            "expected {KEY_SIZE} raw bytes or {} hex digits",
+            KEY_SIZE * 2
+        );
+    }
+    let mut key = [0u8; KEY_SIZE];
+    for (out, pair) in key.iter_mut().zip(text.chunks_exact(2)) {
+        let hex = std::str::from_utf8(pair).ok();
+        *out = match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(b) => b,
+            None => bail!("key contains non-hex characters"),
+        };
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_raw_and_hex_keys() {
+        assert_eq!(parse_key(&[9u8; KEY_SIZE]).unwrap(), [9u8; KEY_SIZE]);
+        let hex = format!("{}\n", "ab".repeat(KEY_SIZE));
+        assert_eq!(parse_key(hex.as_bytes()).unwrap(), [0xab; KEY_SIZE]);
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert!(parse_key(b"too short").is_err());
+        assert!(parse_key("zz".repeat(KEY_SIZE).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn key_is_mac_or_encryption_key() {
+        let keys = Keys {
+            key: Some([1; KEY_SIZE]),
+            key_secret: Some(Secret::raw([1; KEY_SIZE])),
+            password: None,
+        };
+        assert!(keys.seal_options(false).unwrap().mac_key.is_some());
+        let encrypting = keys.seal_options(true).unwrap();
+        assert!(encrypting.mac_key.is_none() && encrypting.encryption.is_some());
+
+        let encrypted_header = b"BSCF\x02";
+        let plain = b"\x05\x00hello";
+        assert!(keys.mac_key(encrypted_header).is_none());
+        assert!(keys.open_options(encrypted_header).decryption.is_some());
+        assert!(keys.open_options(plain).decryption.is_none());
+        assert!(keys.mac_key(plain).is_some());
+    }
+}
@@ -0,0 +1,293 @@
+//! `login` and `license` commands.
+
+use bstseal_core::license::{self, License, LicenseInfo, LicenseSource};
+use clap::Parser;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+pub enum LicenseCommand {
+    /// Prints tier, expiry, days left and where the key came from
+    Status {
+        /// Machine-readable output
+        #[clap(long)]
+        json: bool,
+    },
+    /// Prints the active license key
+    Show,
+    /// Deletes the key stored by `login`
+    Remove,
+    /// Summarizes the local usage ledger per license
+    #[cfg(feature = "metering")]
+    Usage {
+        /// Machine-readable output
+        #[clap(long)]
+        json: bool,
+        /// Also copy the verified ledger to this file
+        #[clap(long)]
+        export: Option<PathBuf>,
+    },
+}
+
+pub fn run(command: LicenseCommand) -> anyhow::Result<()> {
+    match command {
+        LicenseCommand::Status { json } => status(json),
+        LicenseCommand::Show => {
+            println!("{}", license::license_info()?.key);
+            Ok(())
+        }
+        LicenseCommand::Remove => remove(),
+        #[cfg(feature = "metering")]
+        LicenseCommand::Usage { json, export } => usage(json, export),
+    }
+}
+
+/// Verifies `key` and stores it in `~/.bstseal/license`.
+pub fn login(key: String) -> anyhow::Result<()> {
+    let parsed = match license::check_license(&key) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("Invalid license: {e}");
+            crate::exit(1);
+        }
+    };
+    let file_path =
+        license::license_path().unwrap_or_else(|| PathBuf::from(".bstseal").join("license"));
+    if let Some(dir) = file_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&file_path, &key)?;
+    println!(
+        "License activated: tier={}, expires={} (saved to {})",
+        parsed.tier,
+        parsed.expires.format("%Y-%m-%d"),
+        file_path.display()
+    );
+    Ok(())
+}
+
+/// Prints a reminder on stderr when the license is about to expire.
+pub fn warn_if_expires_soon() {
+    if let Some(warning) = license::license_info()
+        .ok()
+        .and_then(|info| expiry_warning(&info.license))
+    {
+        eprintln!("{warning}");
+    }
+}
+
+fn expiry_warning(license: &License) -> Option<String> {
+    license.expires_soon().then(|| {
+        format!(
+            "warning: bstseal license expires on {} ({} days left)",
+            license.expires.format("%Y-%m-%d"),
+            license.days_left()
+        )
+    })
+}
+
+fn status(json: bool) -> anyhow::Result<()> {
+    let info = license::license_info()?;
+    match json {
+        true => println!("{}", status_json(&info)),
+        false => print!("{}", status_text(&info)),
+    }
+    Ok(())
+}
+
+fn status_json(info: &LicenseInfo) -> serde_json::Value {
+    let license = &info.license;
+    let (source, path) = match &info.source {
+        LicenseSource::Runtime => ("runtime", None),
+        LicenseSource::Env => ("env", None),
+        LicenseSource::File(path) => ("file", Some(path.display().to_string())),
+    };
+    serde_json::json!({
+        "id": license.id,
+        "tier": license.tier.as_str(),
+        "expires": license.expires.to_rfc3339(),
+        "days_left": license.days_left(),
+        "expired": license.is_expired(),
+        "source": source,
+        "path": path,
+    })
+}
+
+fn status_text(info: &LicenseInfo) -> String {
+    let license = &info.license;
+    let mut out = String::new();
+    let _ = writeln!(out, "License:   {}", license.id);
+    let _ = writeln!(out, "Tier:      {}", license.tier);
+    let expires = license.expires.format("%Y-%m-%d %H:%M:%S UTC");
+    let _ = writeln!(out, "Expires:   {expires}");
+    if license.is_expired() {
+        let _ = writeln!(out, "Days left: 0 (expired)");
+    } else {
+        let _ = writeln!(out, "Days left: {}", license.days_left());
+    }
+    let _ = writeln!(out, "Source:    {}", info.source);
+    out
+}
+
+fn remove() -> anyhow::Result<()> {
+    let path = license::license_path()
+        .ok_or_else(|| anyhow::anyhow!("cannot locate the home directory"))?;
+    match remove_key(&path)? {
+        true => println!("Removed {}", path.display()),
+        false => println!("No license key stored at {}", path.display()),
+    }
+    if std::env::var_os("BSTSEAL_LICENSE").is_some() {
+        eprintln!("note: BSTSEAL_LICENSE is still set and takes precedence");
+    }
+    Ok(())
+}
+
+/// Deletes the key file at `path`; returns whether there was one.
+fn remove_key(path: &Path) -> io::Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Prints per-license totals from the usage ledger after checking its hash
+/// chain.
+#[cfg(feature = "metering")]
+fn usage(json: bool, export: Option<PathBuf>) -> anyhow::Result<()> {
+    use bstseal_core::license::usage::Ledger;
+
+    let ledger =
+        Ledger::locate().ok_or_else(|| anyhow::anyhow!("cannot locate the home directory"))?;
+    let records = ledger.records()?;
+    let summary = ledger.summary()?;
+    if let Some(path) = &export {
+        fs::copy(ledger.path(), path)?;
+    }
+    if json {
+        let licenses: serde_json::Map<_, _> = summary
+            .iter()
+            .map(|(id, usage)| {
+                let totals = serde_json::json!({
+                    "operations": usage.operations(),
+                    "encodes": usage.encodes,
+                    "decodes": usage.decodes,
+                    "bytes_encoded": usage.bytes_encoded,
+                    "bytes_decoded": usage.bytes_decoded,
+                });
+                (id.clone(), totals)
+            })
+            .collect();
+        let report = serde_json::json!({
+            "ledger": ledger.path().display().to_string(),
+            "records": records.len(),
+            "first": records.first().map(|r| r.time.to_rfc3339()),
+            "last": records.last().map(|r| r.time.to_rfc3339()),
+            "licenses": licenses,
+        });
+        println!("{report}");
+    } else {
+        println!(
+            "Ledger:  {} ({} records, chain intact)",
+            ledger.path().display(),
+            records.len()
+        );
+        if let (Some(first), Some(last)) = (records.first(), records.last()) {
+            println!(
+                "Period:  {} .. {}",
+                first.time.format("%Y-%m-%d %H:%M:%S UTC"),
+                last.time.format("%Y-%m-%d %H:%M:%S UTC")
+            );
+        }
+        for (id, usage) in &summary {
+            println!();
+            println!("License: {id}");
+            println!(
+                "  Operations: {} ({} encode, {} decode)",
+                usage.operations(),
+                usage.encodes,
+                usage.decodes
+            );
+            println!("  Encoded:    {} bytes", usage.bytes_encoded);
+            println!("  Decoded:    {} bytes", usage.bytes_decoded);
+        }
+    }
+    if let Some(path) = export {
+        eprintln!("Exported ledger to {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bstseal_core::license::dev_issuer::LICENSE_ID;
+    use bstseal_core::license::Tier;
+    use chrono::{Duration, Utc};
+
+    fn info(days: i64, source: LicenseSource) -> LicenseInfo {
+        LicenseInfo {
+            key: "key".into(),
+            source,
+            license: License {
+                id: LICENSE_ID.into(),
+                tier: Tier::Startup,
+                expires: Utc::now() + Duration::days(days) + Duration::hours(1),
+            },
+        }
+    }
+
+    #[test]
+    fn status_names_tier_expiry_and_source() {
+        let text = status_text(&info(30, LicenseSource::Env));
+        assert!(text.starts_with(&format!("License:   {LICENSE_ID}\n")));
+        assert!(text.contains("Tier:      startup\n"), "{text}");
+        assert!(text.contains("Days left: 30\n"), "{text}");
+        assert!(text.ends_with("Source:    env BSTSEAL_LICENSE\n"), "{text}");
+        let text = status_text(&info(-3, LicenseSource::Runtime));
+        assert!(text.contains("Days left: 0 (expired)\n"), "{text}");
+        assert!(text.ends_with("Source:    runtime\n"), "{text}");
+    }
+
+    #[test]
+    fn status_json_reports_the_source() {
+        let path = PathBuf::from("/home/me/.bstseal/license");
+        let json = status_json(&info(30, LicenseSource::File(path)));
+        assert_eq!(json["tier"], "startup");
+        assert_eq!(json["days_left"], 30);
+        assert_eq!(json["expired"], false);
+        assert_eq!(json["source"], "file");
+        assert_eq!(json["path"], "/home/me/.bstseal/license");
+        for (source, name) in [
+            (LicenseSource::Runtime, "runtime"),
+            (LicenseSource::Env, "env"),
+        ] {
+            let json = status_json(&info(-1, source));
+            assert_eq!(
+                (&json["source"], &json["path"]),
+                (&name.into(), &serde_json::Value::Null)
+            );
+            assert_eq!(json["expired"], true);
+        }
+    }
+
+    #[test]
+    fn warns_two_weeks_before_expiry() {
+        assert_eq!(expiry_warning(&info(30, LicenseSource::Env).license), None);
+        let warning = expiry_warning(&info(13, LicenseSource::Env).license).unwrap();
+        assert!(warning.starts_with("warning: bstseal license expires on "));
+        assert!(warning.ends_with("(13 days left)"), "{warning}");
+    }
+
+    #[test]
+    fn remove_deletes_the_stored_key_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("license");
+        fs::write(&path, "key").unwrap();
+        assert!(remove_key(&path).unwrap());
+        assert!(!path.exists());
+        assert!(!remove_key(&path).unwrap());
+    }
+}
@@ -1,11 +1,26 @@
+use bstseal_core::archive::{self, ArchiveReader, ArchiveWriter, EntryKind};
 use bstseal_core::encode::{decode_parallel, encode_parallel};
+use bstseal_core::frame;
+use bstseal_core::integrity::{Checksum, HASH_SIZE};
+use bstseal_core::outboard;
+use bstseal_core::seal::{open, seal, SealOptions};
+use bstseal_core::signature::{self, SigningKey, VerifyingKey};
+use bstseal_core::VerifyingDecoder;
 use clap::Parser;
 use std::fs::{self, File};
-use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use walkdir::WalkDir;
 
+mod extract;
+mod keys;
+#[cfg(feature = "licensing")]
+mod license;
+mod meta;
+
+use keys::{KeyArgs, Keys};
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
@@ -13,6 +28,29 @@ struct Cli {
     command: Commands,
 }
 
+/// How `pack`, `add` and `update` seal entries.
+#[derive(clap::Args, Debug)]
+struct SealArgs {
+    /// Encrypt every entry with --password-file or --key-file
+    #[clap(long)]
+    encrypt: bool,
+    /// Footer checksum of every entry (see `encode --checksum`)
+    #[clap(long, value_parser = parse_checksum, default_value = "blake3")]
+    checksum: Checksum,
+    /// Store a Blake3 hash of every entry's original content
+    #[clap(long)]
+    content_hash: bool,
+}
+
+impl SealArgs {
+    fn options<'a>(&self, keys: &'a Keys) -> anyhow::Result<SealOptions<'a>> {
+        let mut options = keys.seal_options(self.encrypt)?;
+        options.checksum = self.checksum;
+        options.content_hash = self.content_hash;
+        Ok(options)
+    }
+}
+
 #[derive(Parser, Debug)]
 enum Commands {
     /// Encodes a file using bstseal compression
@@ -24,12 +62,53 @@ enum Commands {
         /// Output file to write encoded data
         #[clap(short, long, value_parser)]
         output: PathBuf,
+
+        /// Encrypt with --password-file or --key-file
+        #[clap(long)]
+        encrypt: bool,
+
+        /// Footer checksum: blake3, blake3-128, xxh3-64, crc32c or sha256
+        #[clap(long, value_parser = parse_checksum, default_value = "blake3")]
+        checksum: Checksum,
+
+        /// Store a Blake3 hash of the original content (see `info`)
+        #[clap(long)]
+        content_hash: bool,
+
+        /// Store a block index and write a Bao outboard tree to
+        /// <OUTPUT>.obao for verified range reads
+        #[clap(long)]
+        outboard: bool,
+
+        #[clap(flatten)]
+        keys: KeyArgs,
+    },
+    /// Decodes a byte range of a file written with `encode --outboard`,
+    /// verifying only the blocks it touches
+    Range {
+        input: PathBuf,
+        /// First decoded byte
+        #[clap(long)]
+        offset: u64,
+        /// Number of decoded bytes
+        #[clap(long)]
+        length: u64,
+        /// Outboard tree (default <INPUT>.obao)
+        #[clap(long)]
+        outboard: Option<PathBuf>,
+        #[clap(flatten)]
+        keys: KeyArgs,
     },
+    /// Shows how a sealed file is framed, without decoding it
+    Info { input: PathBuf },
     /// Verifies integrity footer of a bstseal file
     Fsck {
         /// File to check
         #[clap(value_parser)]
         input: PathBuf,
+
+        #[clap(flatten)]
+        keys: KeyArgs,
     },
     /// Decodes a file previously encoded with bstseal
     Decode {
@@ -40,16 +119,61 @@ enum Commands {
         /// Output file to write decoded data
         #[clap(short, long, value_parser)]
         output: PathBuf,
+
+        #[clap(flatten)]
+        keys: KeyArgs,
     },
     /// Packs multiple files into an archive
     Pack {
-        /// Output archive file
+        /// Output archive file, or '-' for a streamed archive on stdout
         #[clap(short, long)]
         output: PathBuf,
         /// Input files/dirs to include
         #[clap(required = true)]
         inputs: Vec<PathBuf>,
+        #[clap(flatten)]
+        seal: SealArgs,
+        /// Write entries as they are compressed and the index at the end,
+        /// reading large files a piece at a time unless --encrypt or
+        /// --content-hash needs them whole (implied by `-o -`)
+        #[clap(long)]
+        streamed: bool,
+        /// Compress files smaller than BYTES together in shared blocks of
+        /// about that size (default 1 MiB); best for many small files
+        #[clap(long, value_name = "BYTES", num_args = 0..=1, default_missing_value = "1048576")]
+        solid: Option<usize>,
+        #[clap(flatten)]
+        keys: KeyArgs,
     },
+    /// Adds files/dirs to an existing archive in place
+    Add {
+        archive: PathBuf,
+        #[clap(required = true)]
+        inputs: Vec<PathBuf>,
+        #[clap(flatten)]
+        seal: SealArgs,
+        #[clap(flatten)]
+        keys: KeyArgs,
+    },
+    /// Replaces entries of an existing archive in place, adding new ones
+    Update {
+        archive: PathBuf,
+        #[clap(required = true)]
+        inputs: Vec<PathBuf>,
+        #[clap(flatten)]
+        seal: SealArgs,
+        #[clap(flatten)]
+        keys: KeyArgs,
+    },
+    /// Removes entries (and everything below directories) from an archive
+    Rm {
+        archive: PathBuf,
+        /// Paths inside archive
+        #[clap(required = true)]
+        paths: Vec<String>,
+    },
+    /// Rewrites an archive without the space left by removed entries
+    Compact { archive: PathBuf },
     /// Unpacks archive to directory
     Unpack {
         /// Archive to unpack
@@ -57,6 +181,17 @@ enum Commands {
         /// Output directory (default '.')
         #[clap(short, long, default_value = ".")]
         out_dir: PathBuf,
+        /// Extract entries with `..`, absolute or duplicate paths as stored
+        /// and follow symlinks on the way (only for trusted archives)
+        #[clap(long)]
+        unsafe_paths: bool,
+        /// Also restore setuid/setgid bits and extended attributes outside
+        /// `user.*` (capabilities, security labels, ACLs) as stored (only for
+        /// trusted archives)
+        #[clap(long)]
+        same_permissions: bool,
+        #[clap(flatten)]
+        keys: KeyArgs,
     },
     /// Lists archive contents
     List { archive: PathBuf },
@@ -65,12 +200,44 @@ enum Commands {
         archive: PathBuf,
         /// Path inside archive
         file: String,
+        #[clap(flatten)]
+        keys: KeyArgs,
+    },
+    /// Generates an Ed25519 key pair for `sign` and `verify`
+    Keygen {
+        /// Writes <OUTPUT>.key (secret) and <OUTPUT>.pub (public)
+        #[clap(short, long)]
+        output: PathBuf,
+    },
+    /// Appends an Ed25519 signature to a sealed file or archive
+    Sign {
+        input: PathBuf,
+        /// Secret key written by `keygen`
+        #[clap(long)]
+        key: PathBuf,
+        /// Output file (default: sign in place)
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Checks the Ed25519 signature of a sealed file or archive
+    Verify {
+        input: PathBuf,
+        /// Public key written by `keygen`
+        #[clap(long)]
+        pubkey: PathBuf,
     },
     /// Activates license key for current user
+    #[cfg(feature = "licensing")]
     Login {
         /// License key string
         key: String,
     },
+    /// Shows or removes the active license key
+    #[cfg(feature = "licensing")]
+    License {
+        #[clap(subcommand)]
+        command: license::LicenseCommand,
+    },
     /// Runs micro-benchmark
     Bench {
         /// Optional sample file
@@ -81,48 +248,88 @@ enum Commands {
 
 fn main() -> Result<(), anyhow::Error> {
     let cli = Cli::parse();
+    #[cfg(feature = "licensing")]
+    license::warn_if_expires_soon();
+    // One ledger record per command, not per file or block coded.
+    #[cfg(feature = "metering")]
+    let _usage = bstseal_core::license::usage::batch();
 
     match cli.command {
-        Commands::Pack { output, inputs } => pack_archive(output, inputs)?,
-        Commands::Unpack { archive, out_dir } => unpack_archive(archive, out_dir)?,
+        Commands::Pack {
+            output,
+            inputs,
+            seal,
+            streamed,
+            solid,
+            keys,
+        } => {
+            let keys = Keys::load(&keys)?;
+            pack_archive(output, inputs, &seal.options(&keys)?, streamed, solid)?
+        }
+        Commands::Add {
+            archive,
+            inputs,
+            seal,
+            keys,
+        } => {
+            let keys = Keys::load(&keys)?;
+            update_archive(archive, inputs, &seal.options(&keys)?, false)?
+        }
+        Commands::Update {
+            archive,
+            inputs,
+            seal,
+            keys,
+        } => {
+            let keys = Keys::load(&keys)?;
+            update_archive(archive, inputs, &seal.options(&keys)?, true)?
+        }
+        Commands::Rm { archive, paths } => remove_from_archive(archive, paths)?,
+        Commands::Compact { archive } => {
+            let (before, after) = archive::compact(&archive)?;
+            println!("{}: {before} -> {after} bytes", archive.display());
+        }
+        Commands::Unpack {
+            archive,
+            out_dir,
+            unsafe_paths,
+            same_permissions,
+            keys,
+        } => extract::unpack_archive(
+            &mut open_archive(&archive)?,
+            &out_dir,
+            &Keys::load(&keys)?,
+            unsafe_paths,
+            same_permissions,
+        )?,
         Commands::List { archive } => list_archive(archive)?,
-        Commands::Cat { archive, file } => cat_file(archive, file)?,
-        Commands::Login { key } => {
-            use chrono::{DateTime, Utc};
-            use std::fs;
-            // verify key first
-            match bstseal_core::license::verify_license(&key) {
-                Ok(tier) => {
-                    // parse expiry for display
-                    let parts: Vec<&str> = key.split('.').collect();
-                    let expires_iso = parts[2..parts.len() - 1].join(".");
-                    let expires: DateTime<Utc> = DateTime::parse_from_rfc3339(&expires_iso)
-                        .map(|dt| dt.with_timezone(&Utc))
-                        .unwrap_or_else(|_| Utc::now());
-
-                    // write to ~/.bstseal/license
-                    let path = dirs::home_dir()
-                        .unwrap_or_else(|| std::path::PathBuf::from("."))
-                        .join(".bstseal");
-                    fs::create_dir_all(&path)?;
-                    let file_path = path.join("license");
-                    fs::write(&file_path, &key)?;
-
-                    println!(
-                        "License activated: tier={}, expires={} (saved to {})",
-                        tier.as_str(),
-                        expires.format("%Y-%m-%d"),
-                        file_path.display()
-                    );
-                }
-                Err(e) => {
-                    eprintln!("Invalid license: {e}");
-                    std::process::exit(1);
-                }
-            }
-        },
+        Commands::Cat {
+            archive,
+            file,
+            keys,
+        } => cat_file(archive, file, &Keys::load(&keys)?)?,
+        Commands::Keygen { output } => keygen(output)?,
+        Commands::Sign { input, key, output } => sign_file(input, key, output)?,
+        Commands::Verify { input, pubkey } => verify_file(input, pubkey)?,
+        #[cfg(feature = "licensing")]
+        Commands::Login { key } => license::login(key)?,
+        #[cfg(feature = "licensing")]
+        Commands::License { command } => license::run(command)?,
         Commands::Bench { file } => run_bench(file)?,
-        Commands::Encode { input, output } => {
+        Commands::Encode {
+            input,
+            output,
+            encrypt,
+            checksum,
+            content_hash,
+            outboard,
+            keys,
+        } => {
+            let keys = Keys::load(&keys)?;
+            let mut options = keys.seal_options(encrypt)?;
+            options.block_index = outboard;
+            options.checksum = checksum;
+            options.content_hash = content_hash;
             println!("Encoding file: {:?} to {:?}", input, output);
 
             let mut input_file = BufReader::new(File::open(&input)?);
@@ -130,12 +337,14 @@ fn main() -> Result<(), anyhow::Error> {
             input_file.read_to_end(&mut input_data)?;
 
             let start_time = Instant::now();
-            let compressed = encode_parallel(&input_data)?;
-            let encoded_data = bstseal_core::integrity::add_footer(&compressed);
+            let encoded_data = seal(&input_data, &options)?;
             let duration = start_time.elapsed();
 
             let mut output_file = BufWriter::new(File::create(&output)?);
             output_file.write_all(&encoded_data)?;
+            if outboard {
+                fs::write(outboard_path(&output), outboard::outboard(&encoded_data)?)?;
+            }
 
             println!("Operation: encode");
             println!("Input file: {:?}", input);
@@ -144,34 +353,25 @@ fn main() -> Result<(), anyhow::Error> {
             println!("Compressed size: {} bytes", encoded_data.len());
             println!("Time taken: {:.2?}", duration);
         }
-        Commands::Decode { input, output } => {
+        Commands::Decode {
+            input,
+            output,
+            keys,
+        } => {
+            let keys = Keys::load(&keys)?;
             println!("Decoding file: {:?} to {:?}", input, output);
 
-            let mut input_file = BufReader::new(File::open(&input)?);
-            let mut input_data = Vec::new();
-            input_file.read_to_end(&mut input_data)?;
-
             let start_time = Instant::now();
-            let payload = match bstseal_core::integrity::verify_footer(&input_data) {
-                Ok(p) => p,
-                Err(e) => {
-                    eprintln!("Integrity check failed: {e}");
-                    std::process::exit(1);
-                }
-            };
-            let decoded_data_result = decode_parallel(payload);
+            let result = decode_file(&input, &output, &keys);
             let duration = start_time.elapsed();
 
-            match decoded_data_result {
-                Ok(decoded_data) => {
-                    let mut output_file = BufWriter::new(File::create(&output)?);
-                    output_file.write_all(&decoded_data)?;
-
+            match result {
+                Ok((compressed_size, original_size)) => {
                     println!("Operation: decode");
                     println!("Input file: {:?}", input);
                     println!("Output file: {:?}", output);
-                    println!("Compressed size: {} bytes", input_data.len());
-                    println!("Original size: {} bytes", decoded_data.len());
+                    println!("Compressed size: {} bytes", compressed_size);
+                    println!("Original size: {} bytes", original_size);
                     println!("Time taken: {:.2?}", duration);
                 }
                 Err(e) => {
@@ -180,17 +380,30 @@ fn main() -> Result<(), anyhow::Error> {
                 }
             }
         }
-        Commands::Fsck { input } => {
+        Commands::Range {
+            input,
+            offset,
+            length,
+            outboard,
+            keys,
+        } => {
+            let outboard = outboard.unwrap_or_else(|| outboard_path(&input));
+            read_range(&input, &outboard, offset, length, &Keys::load(&keys)?)?
+        }
+        Commands::Info { input } => print_info(&input)?,
+        Commands::Fsck { input, keys } => {
+            let keys = Keys::load(&keys)?;
             let mut file = BufReader::new(File::open(&input)?);
             let mut data = Vec::new();
             file.read_to_end(&mut data)?;
-            match bstseal_core::integrity::verify_footer(&data) {
+            let data = signature::strip_signature(&data);
+            match bstseal_core::integrity::verify(data, keys.mac_key(data)) {
                 Ok(_) => {
                     println!("{}: OK", input.display());
                 }
                 Err(e) => {
                     eprintln!("{}: FAILED – {e}", input.display());
-                    std::process::exit(1);
+                    exit(1);
                 }
             }
         }
@@ -199,150 +412,450 @@ fn main() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// Read size for streaming decode.
+const STREAM_CHUNK: usize = 1 << 20;
+
+/// Decodes `input` into `output` in one pass, verifying the footer as the
+/// bytes stream through. Returns (compressed, original) sizes.
+fn decode_file(input: &Path, output: &Path, keys: &Keys) -> anyhow::Result<(u64, u64)> {
+    let mut reader = BufReader::with_capacity(STREAM_CHUNK, File::open(input)?);
+    let head = reader.fill_buf()?.to_vec();
+    if keys.needs_whole_input(&head) {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let decoded = open(&data, &keys.open_options(&data))?;
+        write_atomically(output, |w| Ok(w.write_all(&decoded)?))?;
+        return Ok((data.len() as u64, decoded.len() as u64));
+    }
+
+    #[cfg(feature = "licensing")]
+    bstseal_core::license::ensure_license_valid()?;
+    let mut decoder = match keys.mac_key(&head) {
+        Some(key) => VerifyingDecoder::with_key(key),
+        None => VerifyingDecoder::new(),
+    };
+    let (mut compressed, mut original) = (0u64, 0u64);
+    write_atomically(output, |w| {
+        let mut buf = vec![0u8; STREAM_CHUNK];
+        let mut out = Vec::new();
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            compressed += n as u64;
+            decoder.update(&buf[..n], &mut out)?;
+            original += out.len() as u64;
+            w.write_all(&out)?;
+            out.clear();
+        }
+        decoder.finish(&mut out)?;
+        original += out.len() as u64;
+        Ok(w.write_all(&out)?)
+    })?;
+    #[cfg(feature = "metering")]
+    {
+        use bstseal_core::license::usage;
+        usage::record(usage::Operation::Decode, original);
+    }
+    Ok((compressed, original))
+}
+
+/// Writes through `f` into `<path>.partial` and renames it to `path` only if
+/// `f` succeeds, so a failed verification never leaves output behind.
+fn write_atomically(
+    path: &Path,
+    f: impl FnOnce(&mut BufWriter<File>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".partial");
+    let tmp = PathBuf::from(tmp);
+    let result = (|| {
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        f(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        Ok(())
+    })();
+    match result {
+        Ok(()) => Ok(fs::rename(&tmp, path)?),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
 // ---------------- archive helpers ----------------
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-const MAGIC: &[u8; 8] = b"BSTSEAL\0";
+fn pack_archive(
+    output: PathBuf,
+    inputs: Vec<PathBuf>,
+    options: &SealOptions,
+    streamed: bool,
+    solid: Option<usize>,
+) -> anyhow::Result<()> {
+    let paths = collect_inputs(inputs)?;
+    if output.as_os_str() == "-" {
+        let stdout = BufWriter::new(io::stdout().lock());
+        let writer = ArchiveWriter::streaming(stdout, *options);
+        return add_entries(with_solid(writer, solid), &paths);
+    }
+    let out = BufWriter::new(File::create(output)?);
+    let writer = match streamed {
+        true => ArchiveWriter::streaming(out, *options),
+        false => ArchiveWriter::new(out, *options),
+    };
+    add_entries(with_solid(writer, solid), &paths)
+}
 
-struct IndexEntry {
-    path: String,
-    offset: u64,
-    size: u64,
+fn with_solid<'a, W: Write>(
+    writer: ArchiveWriter<'a, W>,
+    block_size: Option<usize>,
+) -> ArchiveWriter<'a, W> {
+    match block_size {
+        Some(block_size) => writer.solid(block_size),
+        None => writer,
+    }
 }
 
-fn pack_archive(output: PathBuf, inputs: Vec<PathBuf>) -> anyhow::Result<()> {
-    let mut files = Vec::new();
+/// `inputs` with directories expanded to everything below them.
+fn collect_inputs(inputs: Vec<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
     for input in inputs {
         if input.is_dir() {
-            for entry in WalkDir::new(&input)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|e| e.path().is_file())
-            {
-                files.push(entry.path().to_path_buf());
+            for entry in WalkDir::new(&input).into_iter().filter_map(Result::ok) {
+                paths.push(entry.path().to_path_buf());
             }
         } else {
-            files.push(input);
+            paths.push(input);
         }
     }
-    if files.is_empty() {
+    if paths.is_empty() {
         anyhow::bail!("no input files");
     }
+    Ok(paths)
+}
 
-    // Compress all files first to know sizes
-    let mut payloads = Vec::new(); // (path, data)
-    for path in &files {
-        let mut buf = Vec::new();
-        BufReader::new(File::open(path)?).read_to_end(&mut buf)?;
-        let compressed = encode_parallel(&buf)?;
-        let with_footer = bstseal_core::integrity::add_footer(&compressed);
-        payloads.push((
-            path.strip_prefix(&std::env::current_dir()?)?
-                .to_string_lossy()
-                .to_string(),
-            with_footer,
-        ));
+/// Adds `inputs` to `archive` in place. Existing entries are replaced with
+/// `replace`; otherwise only directories may already be present.
+fn update_archive(
+    archive: PathBuf,
+    inputs: Vec<PathBuf>,
+    options: &SealOptions,
+    replace: bool,
+) -> anyhow::Result<()> {
+    let paths = collect_inputs(inputs)?;
+    let cwd = std::env::current_dir()?;
+    let mut writer = ArchiveWriter::open(&archive, *options)?;
+    for path in &paths {
+        let name = archive_path(path, &cwd)?;
+        let Some(existing) = writer.entries().find(|e| e.path == name) else {
+            continue;
+        };
+        let same_dir = existing.kind == EntryKind::Directory && path.is_dir();
+        if !replace && !same_dir {
+            anyhow::bail!("{name} is already in the archive (use `bstseal update` to replace it)");
+        }
+        writer.remove(&name)?;
     }
+    add_entries(writer, &paths)
+}
 
-    // Compute header length
-    let entry_count = payloads.len() as u32;
-    let mut header_len: usize = 8 + 4; // MAGIC + count
-    for (path_str, _data) in &payloads {
-        header_len += 2 + path_str.len() + 8 + 8;
+fn remove_from_archive(archive: PathBuf, paths: Vec<String>) -> anyhow::Result<()> {
+    let mut writer = ArchiveWriter::open(&archive, SealOptions::default())?;
+    for path in paths {
+        let path = path.trim_end_matches('/');
+        let below = format!("{path}/");
+        let names: Vec<String> = writer
+            .entries()
+            .filter(|e| e.path == path || e.path.starts_with(&below))
+            .map(|e| e.path.clone())
+            .collect();
+        if names.is_empty() {
+            anyhow::bail!("{path} not found in archive");
+        }
+        for name in names {
+            writer.remove(&name)?;
+        }
     }
+    writer.finish()?;
+    Ok(())
+}
 
-    // Prepare header in memory
-    let mut header = Vec::with_capacity(header_len);
-    header.extend_from_slice(MAGIC);
-    header.write_u32::<LittleEndian>(entry_count)?;
-    let mut offset_acc = header_len as u64;
-    for (path_str, _data) in &payloads {
-        let path_bytes = path_str.as_bytes();
-        header.write_u16::<LittleEndian>(path_bytes.len() as u16)?;
-        header.extend_from_slice(path_bytes);
-        header.write_u64::<LittleEndian>(offset_acc)?;
-        header.write_u64::<LittleEndian>(_data.len() as u64)?;
-        offset_acc += _data.len() as u64;
+/// Adds `paths` with their metadata to `writer` and finishes the archive.
+fn add_entries<W: Write>(mut writer: ArchiveWriter<W>, paths: &[PathBuf]) -> anyhow::Result<()> {
+    let cwd = std::env::current_dir()?;
+    for path in paths {
+        let name = archive_path(path, &cwd)?;
+        let metadata = meta::capture(path)?;
+        let file_type = fs::symlink_metadata(path)?.file_type();
+        if file_type.is_symlink() {
+            let target = fs::read_link(path)?;
+            writer.add_symlink(&name, &target.to_string_lossy(), metadata)?;
+        } else if file_type.is_dir() {
+            if !name.is_empty() {
+                writer.add_dir(&name, metadata)?;
+            }
+        } else {
+            writer.add_reader(&name, BufReader::new(File::open(path)?), metadata)?;
+        }
     }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Name of `path` inside an archive: relative to the current directory,
+/// `/`-separated.
+fn archive_path(path: &Path, cwd: &Path) -> anyhow::Result<String> {
+    let relative = if path.is_absolute() {
+        path.strip_prefix(cwd)
+            .map_err(|_| anyhow::anyhow!("{} is outside the current directory", path.display()))?
+    } else {
+        path
+    };
+    let parts: Vec<_> = relative
+        .components()
+        .filter(|c| !matches!(c, std::path::Component::CurDir))
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    Ok(parts.join("/"))
+}
+
+fn open_archive(archive: &Path) -> anyhow::Result<ArchiveReader<BufReader<File>>> {
+    // Roll back a change that `add`, `update` or `rm` did not finish.
+    archive::recover(archive)?;
+    Ok(ArchiveReader::new(BufReader::new(File::open(archive)?))?)
+}
 
-    // Write archive file
-    let mut out = BufWriter::new(File::create(output)?);
-    out.write_all(&header)?;
-    for (_, data) in payloads {
-        out.write_all(&data)?;
+fn list_archive(archive: PathBuf) -> anyhow::Result<()> {
+    let archive = open_archive(&archive)?;
+    println!("{:<10} {:<14} {:<12} Path", "Mode", "Offset", "Size");
+    for e in archive.entries() {
+        let (kind, link) = match &e.kind {
+            EntryKind::File => ('-', String::new()),
+            EntryKind::Directory => ('d', String::new()),
+            EntryKind::Symlink { target } => ('l', format!(" -> {target}")),
+        };
+        let mode = match e.metadata.mode {
+            Some(mode) => format!("{kind}{mode:04o}"),
+            None => kind.to_string(),
+        };
+        // Files of a solid block show the block's offset and their own
+        // length rather than the sealed size of the whole block.
+        let (offset, size) = match e.block {
+            Some(slice) => (format!("solid@{}", e.offset), slice.len),
+            None => (e.offset.to_string(), e.size),
+        };
+        println!("{:<10} {:<14} {:<12} {}{link}", mode, offset, size, e.path);
     }
-    out.flush()?;
     Ok(())
 }
 
-fn read_index(reader: &mut (impl Read + Seek)) -> anyhow::Result<Vec<IndexEntry>> {
-    let mut magic = [0u8; 8];
-    reader.read_exact(&mut magic)?;
-    if &magic != MAGIC {
-        anyhow::bail!("invalid archive magic");
+fn cat_file(archive: PathBuf, file_path: String, keys: &Keys) -> anyhow::Result<()> {
+    let mut archive = open_archive(&archive)?;
+    let target = archive
+        .find(&file_path)
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("path not found in archive"))?;
+    let data = archive.read_with(&target, |sealed| keys.open_options(sealed))?;
+    io::stdout().write_all(&data)?;
+    Ok(())
+}
+
+/// Prints what the header and size of a sealed file tell, unverified.
+fn print_info(input: &Path) -> anyhow::Result<()> {
+    let data = fs::read(input)?;
+    let info = frame::peek(&data)?;
+    println!("File: {}", input.display());
+    println!("Size: {} bytes", data.len());
+    let Some(header) = &info.header else {
+        println!("Format: legacy (no header)");
+        println!("Checksum: {}", info.checksum());
+        println!("Payload: {} bytes", info.payload_len);
+        return Ok(());
+    };
+    println!("Format: framed, flags {:#04x}", header.flags);
+    println!("Checksum: {}", info.checksum());
+    println!("Keyed MAC: {}", yes_no(header.has(frame::FLAG_KEYED)));
+    match &header.encryption {
+        Some(params) => match params.kdf {
+            frame::Kdf::Raw => println!("Encryption: XChaCha20-Poly1305, raw key"),
+            frame::Kdf::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+                ..
+            } => println!(
+                "Encryption: XChaCha20-Poly1305, Argon2id (m={m_cost} KiB, t={t_cost}, p={p_cost})"
+            ),
+        },
+        None => println!("Encryption: no"),
+    }
+    match info.content_hash() {
+        Some(hash) => println!("Content hash: blake3:{}", hex(&hash)),
+        None => println!("Content hash: none"),
     }
-    let count = reader.read_u32::<LittleEndian>()?;
-    let mut entries = Vec::with_capacity(count as usize);
-    for _ in 0..count {
-        let path_len = reader.read_u16::<LittleEndian>()? as usize;
-        let mut path_buf = vec![0u8; path_len];
-        reader.read_exact(&mut path_buf)?;
-        let offset = reader.read_u64::<LittleEndian>()?;
-        let size = reader.read_u64::<LittleEndian>()?;
-        let path = String::from_utf8(path_buf)?;
-        entries.push(IndexEntry { path, offset, size });
+    if let Some(len) = info.decoded_len() {
+        println!("Decoded size: {len} bytes (block index)");
     }
-    Ok(entries)
+    println!("Payload: {} bytes", info.payload_len);
+    match signature::split_signature(&data) {
+        Some((_, trailer)) => println!("Signed: key id {}", hex(&trailer.key_id)),
+        None => println!("Signed: no"),
+    }
+    Ok(())
 }
 
-fn list_archive(archive: PathBuf) -> anyhow::Result<()> {
-    let mut file = BufReader::new(File::open(archive)?);
-    let entries = read_index(&mut file)?;
-    println!("{:<8} {:<12} Path", "Offset", "Size");
-    for e in entries {
-        println!("{:<8} {:<12} {}", e.offset, e.size, e.path);
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
     }
+}
+
+fn parse_checksum(name: &str) -> Result<Checksum, String> {
+    Checksum::from_name(name).ok_or_else(|| {
+        let names: Vec<_> = Checksum::ALL.iter().map(|c| c.name()).collect();
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+/// Default outboard tree path: `<sealed>.obao`.
+fn outboard_path(sealed: &Path) -> PathBuf {
+    let mut path = sealed.as_os_str().to_owned();
+    path.push(".obao");
+    PathBuf::from(path)
+}
+
+/// Writes `length` decoded bytes from `offset` to stdout. The root hash is
+/// taken from the file's own footer, so this detects corruption but does
+/// not authenticate the file.
+fn read_range(
+    input: &Path,
+    outboard_file: &Path,
+    offset: u64,
+    length: u64,
+    keys: &Keys,
+) -> anyhow::Result<()> {
+    let mut file = File::open(input)?;
+    let tail_len = file
+        .metadata()?
+        .len()
+        .min((HASH_SIZE + signature::TRAILER_SIZE) as u64);
+    file.seek(SeekFrom::End(-(tail_len as i64)))?;
+    let mut tail = vec![0u8; tail_len as usize];
+    file.read_exact(&mut tail)?;
+    let root = outboard::root(&tail)?;
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut head = Vec::new();
+    (&file)
+        .take(frame::MAGIC.len() as u64 + 1)
+        .read_to_end(&mut head)?;
+    let end = offset
+        .checked_add(length)
+        .ok_or_else(|| anyhow::anyhow!("range end overflows"))?;
+    let data = outboard::decode_range(
+        BufReader::new(file),
+        BufReader::new(File::open(outboard_file)?),
+        &root,
+        offset..end,
+        &keys.open_options(&head),
+    )?;
+    io::stdout().write_all(&data)?;
     Ok(())
 }
 
-use std::io::Seek;
-use std::io::SeekFrom;
-
-fn unpack_archive(archive: PathBuf, out_dir: PathBuf) -> anyhow::Result<()> {
-    fs::create_dir_all(&out_dir)?;
-    let mut file = File::open(&archive)?;
-    let mut reader = BufReader::new(&file);
-    let entries = read_index(&mut reader)?;
-    for e in entries {
-        let out_path = out_dir.join(&e.path);
-        if let Some(p) = out_path.parent() {
-            fs::create_dir_all(p)?;
+// ---------------- signing ----------------
+
+fn keygen(output: PathBuf) -> anyhow::Result<()> {
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed)?;
+    let signing_key = SigningKey::from_bytes(&seed);
+    let secret_path = output.with_extension("key");
+    let public_path = output.with_extension("pub");
+    write_secret(&secret_path, format!("{}\n", hex(&seed)).as_bytes())?;
+    fs::write(
+        &public_path,
+        format!("{}\n", hex(signing_key.verifying_key().as_bytes())),
+    )?;
+    println!(
+        "Key pair written: {} (secret), {} (public), key id {}",
+        secret_path.display(),
+        public_path.display(),
+        hex(&signature::key_id(&signing_key.verifying_key()))
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_secret(path: &std::path::Path, contents: &[u8]) -> io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_secret(path: &std::path::Path, contents: &[u8]) -> io::Result<()> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?
+        .write_all(contents)
+}
+
+fn sign_file(input: PathBuf, key: PathBuf, output: Option<PathBuf>) -> anyhow::Result<()> {
+    #[cfg(feature = "licensing")]
+    bstseal_core::license::require(bstseal_core::license::Feature::Signing)?;
+    let signing_key = SigningKey::from_bytes(&keys::read_key_file(&key)?);
+    let data = fs::read(&input)?;
+    let signed = signature::sign(&data, &signing_key)?;
+    let output = output.unwrap_or(input);
+    fs::write(&output, signed)?;
+    println!(
+        "{}: signed with key id {}",
+        output.display(),
+        hex(&signature::key_id(&signing_key.verifying_key()))
+    );
+    Ok(())
+}
+
+/// Exits with `code`, recording the usage of the command so far first:
+/// `process::exit` skips the destructor of the usage batch opened in `main`.
+fn exit(code: i32) -> ! {
+    #[cfg(feature = "metering")]
+    bstseal_core::license::usage::flush();
+    std::process::exit(code)
+}
+
+fn verify_file(input: PathBuf, pubkey: PathBuf) -> anyhow::Result<()> {
+    let key = VerifyingKey::from_bytes(&keys::read_key_file(&pubkey)?)
+        .map_err(|_| anyhow::anyhow!("{} is not a valid public key", pubkey.display()))?;
+    let data = fs::read(&input)?;
+    match signature::verify_signature(&data, &key) {
+        Ok(_) => println!(
+            "{}: signature OK (key id {})",
+            input.display(),
+            hex(&signature::key_id(&key))
+        ),
+        Err(e) => {
+            eprintln!("{}: FAILED – {e}", input.display());
+            exit(1);
         }
-        file.seek(SeekFrom::Start(e.offset))?;
-        let mut compressed = vec![0u8; e.size as usize];
-        file.read_exact(&mut compressed)?;
-        let payload = bstseal_core::integrity::verify_footer(&compressed)?;
-        let data = decode_parallel(payload)?;
-        BufWriter::new(File::create(out_path)?).write_all(&data)?;
     }
     Ok(())
 }
 
-fn cat_file(archive: PathBuf, file_path: String) -> anyhow::Result<()> {
-    let mut file = File::open(&archive)?;
-    let mut reader = BufReader::new(&file);
-    let entries = read_index(&mut reader)?;
-    let target = entries
-        .into_iter()
-        .find(|e| e.path == file_path)
-        .ok_or_else(|| anyhow::anyhow!("path not found in archive"))?;
-    file.seek(SeekFrom::Start(target.offset))?;
-    let mut compressed = vec![0u8; target.size as usize];
-    file.read_exact(&mut compressed)?;
-    let payload = bstseal_core::integrity::verify_footer(&compressed)?;
-    let data = decode_parallel(payload)?;
-    io::stdout().write_all(&data)?;
-    Ok(())
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
 }
 
 fn run_bench(sample: Option<PathBuf>) -> anyhow::Result<()> {
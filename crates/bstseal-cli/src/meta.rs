@@ -0,0 +1,279 @@
+//! Reading file metadata for `pack` and restoring it on `unpack`.
+//!
+//! Modes, owners and symlinks are Unix-only; extended attributes are read
+//! and written on Linux. Elsewhere only mtimes are kept.
+
+use bstseal_core::archive::Metadata;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Mode bits [`restore`] applies unless told to keep all: no setuid or setgid.
+const SAFE_MODE: u32 = 0o1777;
+
+/// Metadata of `path` itself (symlinks are not followed).
+pub fn capture(path: &Path) -> io::Result<Metadata> {
+    let meta = fs::symlink_metadata(path)?;
+    let mut captured = Metadata {
+        mtime: meta.modified().ok().map(Into::into),
+        ..Default::default()
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        captured.mode = Some(meta.mode() & 0o7777);
+        captured.uid = Some(meta.uid());
+        captured.gid = Some(meta.gid());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        captured.xattrs = xattr::list(path)?;
+    }
+    Ok(captured)
+}
+
+/// Applies `meta` to the file, directory or symlink at `path`. Owners are
+/// only restored where permitted (normally as root); attributes the file
+/// system refuses are reported on stderr and skipped.
+///
+/// Unless `same_permissions` is set, setuid and setgid bits are cleared and
+/// only `user.*` attributes are applied: the other namespaces hold file
+/// capabilities, security labels and ACLs that an archive should not grant.
+pub fn restore(path: &Path, meta: &Metadata, same_permissions: bool) -> io::Result<()> {
+    let is_symlink = fs::symlink_metadata(path)?.file_type().is_symlink();
+    #[cfg(target_os = "linux")]
+    for (name, value) in &meta.xattrs {
+        if !same_permissions && !name.starts_with("user.") {
+            continue;
+        }
+        if let Err(e) = xattr::set(path, name, value) {
+            eprintln!("warning: {}: cannot set {name}: {e}", path.display());
+        }
+    }
+    #[cfg(unix)]
+    if meta.uid.is_some() || meta.gid.is_some() {
+        match std::os::unix::fs::lchown(path, meta.uid, meta.gid) {
+            Err(e) if e.kind() != io::ErrorKind::PermissionDenied => return Err(e),
+            _ => {}
+        }
+    }
+    if let Some(mtime) = meta.mtime {
+        if is_symlink {
+            #[cfg(unix)]
+            set_symlink_mtime(path, mtime)?;
+        } else {
+            // Directories can be opened read-only for this on Unix.
+            let file = fs::File::options()
+                .write(!path.is_dir())
+                .read(path.is_dir())
+                .open(path)?;
+            file.set_modified(mtime.into())?;
+        }
+    }
+    #[cfg(unix)]
+    if let (Some(mode), false) = (meta.mode, is_symlink) {
+        use std::os::unix::fs::PermissionsExt;
+        let mask = if same_permissions { 0o7777 } else { SAFE_MODE };
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & mask))?;
+    }
+    #[cfg(not(unix))]
+    let _ = is_symlink;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_symlink_mtime(path: &Path, mtime: bstseal_core::archive::Timestamp) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: mtime.secs as libc::time_t,
+            tv_nsec: mtime.nanos as _,
+        },
+    ];
+    // SAFETY: `path` is NUL-terminated and `times` holds two timespecs.
+    let rc = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    match rc {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Thin wrappers over the Linux `l*xattr` calls.
+#[cfg(target_os = "linux")]
+mod xattr {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        Ok(CString::new(path.as_os_str().as_bytes())?)
+    }
+
+    /// Calls `f(buf)` with a growing buffer until the result fits.
+    fn read_sized(mut f: impl FnMut(&mut [u8]) -> isize) -> io::Result<Vec<u8>> {
+        loop {
+            let len = f(&mut []);
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buf = vec![0u8; len as usize];
+            let n = f(&mut buf);
+            if n >= 0 {
+                buf.truncate(n as usize);
+                return Ok(buf);
+            }
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ERANGE) {
+                return Err(err);
+            }
+        }
+    }
+
+    /// All attributes with UTF-8 names. File systems without xattr support
+    /// have none.
+    pub fn list(path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let cpath = c_path(path)?;
+        // SAFETY: the pointers are valid for the given lengths.
+        let names = match read_sized(|buf| unsafe {
+            libc::llistxattr(cpath.as_ptr(), buf.as_mut_ptr().cast(), buf.len())
+        }) {
+            Ok(names) => names,
+            Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut attrs = Vec::new();
+        for name in names.split(|&b| b == 0).filter(|n| !n.is_empty()) {
+            let Ok(text) = std::str::from_utf8(name) else {
+                continue;
+            };
+            let cname = CString::new(name)?;
+            // SAFETY: as above.
+            let value = read_sized(|buf| unsafe {
+                libc::lgetxattr(
+                    cpath.as_ptr(),
+                    cname.as_ptr(),
+                    buf.as_mut_ptr().cast(),
+                    buf.len(),
+                )
+            })?;
+            attrs.push((text.to_owned(), value));
+        }
+        Ok(attrs)
+    }
+
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let (cpath, cname) = (c_path(path)?, CString::new(name)?);
+        // SAFETY: the pointers are valid for the given lengths.
+        let rc = unsafe {
+            libc::lsetxattr(
+                cpath.as_ptr(),
+                cname.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        match rc {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use bstseal_core::archive::Timestamp;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn restore_applies_captured_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("run.sh");
+        fs::write(&file, "#!/bin/sh\n").unwrap();
+        let link = dir.path().join("run");
+        symlink("run.sh", &link).unwrap();
+
+        let mtime = Timestamp {
+            secs: 1_600_000_000,
+            nanos: 250_000_000,
+        };
+        let wanted = Metadata {
+            mode: Some(0o750),
+            mtime: Some(mtime),
+            ..Default::default()
+        };
+        restore(&file, &wanted, false).unwrap();
+        restore(&link, &wanted, false).unwrap();
+
+        let captured = capture(&file).unwrap();
+        assert_eq!((captured.mode, captured.mtime), (Some(0o750), Some(mtime)));
+        assert_eq!(
+            fs::metadata(&file).unwrap().permissions().mode() & 0o7777,
+            0o750
+        );
+        // The link got the mtime; its target kept its own.
+        assert_eq!(capture(&link).unwrap().mtime, Some(mtime));
+    }
+
+    #[test]
+    fn restore_drops_privileges_unless_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("tool");
+        fs::write(&file, "#!/bin/sh\n").unwrap();
+        let mode = |file: &Path| fs::metadata(file).unwrap().permissions().mode() & 0o7777;
+
+        let setuid = Metadata {
+            mode: Some(0o4755),
+            ..Default::default()
+        };
+        restore(&file, &setuid, false).unwrap();
+        assert_eq!(mode(&file), 0o755);
+        restore(&file, &setuid, true).unwrap();
+        assert_eq!(mode(&file), 0o4755);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn restore_only_sets_user_attributes_unless_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let meta = Metadata {
+            xattrs: vec![
+                ("user.origin".into(), b"archive".to_vec()),
+                ("trusted.overlay".into(), b"y".to_vec()),
+            ],
+            ..Default::default()
+        };
+        let restored = |name: &str, same_permissions| {
+            let file = dir.path().join(name);
+            fs::write(&file, "data").unwrap();
+            restore(&file, &meta, same_permissions).unwrap();
+            let mut names: Vec<_> = xattr::list(&file)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .filter(|name| name.starts_with("user.") || name.starts_with("trusted."))
+                .collect();
+            names.sort();
+            names
+        };
+        // Which attributes stick depends on the file system and on
+        // CAP_SYS_ADMIN for `trusted.*`; refused ones are only warned about.
+        let all = restored("all", true);
+        let user: Vec<_> = all.into_iter().filter(|n| n.starts_with("user.")).collect();
+        assert_eq!(restored("default", false), user);
+    }
+}
@@ -0,0 +1,1687 @@
+//! `.bsa` archives: an index followed by one sealed frame per file (see
+//! SPEC.md).
+//!
+//! Version 1 (`BSTSEAL\0`) indexes regular files by path, offset and size.
+//! Version 2 (`BSTSEAL\x01`) adds directories and symlinks and stores each
+//! entry's [`Metadata`]: mode, mtime, owner and extended attributes.
+//!
+//! Entry paths are relative and `/`-separated. [`sanitize_path`] defines
+//! which paths are safe to extract; the writer only stores such paths, and
+//! [`ArchiveReader::safe_paths`] checks an untrusted index before
+//! extraction.
+//!
+//! Both versions normally put the index first ([`Layout::Leading`]). A
+//! streamed archive (`BSTSEAL\x02`, [`Layout::Trailing`]) holds version 2
+//! entries but writes the sealed frames first and the index last, followed
+//! by its offset, so it can be written to a pipe.
+//!
+//! [`ArchiveWriter::new`] seals entries as they are added and writes the
+//! index and data on [`ArchiveWriter::finish`], since the index comes first
+//! and holds every offset. [`ArchiveWriter::streaming`] writes each entry
+//! as it is added and keeps only the index in memory. [`ArchiveReader`]
+//! reads the index of any `Read + Seek` source, in either version and
+//! layout, and opens entries on demand. Existing archive files are changed
+//! in place with [`ArchiveWriter::open`] and shrunk with [`compact`].
+
+use crate::block_coder::BLOCK_SIZE;
+use crate::seal::{open, seal, OpenOptions, SealOptions, Sealer};
+use anyhow::Result;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod update;
+
+pub use update::{compact, recover};
+
+pub const MAGIC_V1: &[u8; 8] = b"BSTSEAL\0";
+pub const MAGIC_V2: &[u8; 8] = b"BSTSEAL\x01";
+/// Start of a streamed archive; its index is at the end.
+pub const MAGIC_STREAMED: &[u8; 8] = b"BSTSEAL\x02";
+/// Ends a streamed archive, after the index offset.
+pub const MAGIC_INDEX_END: &[u8; 8] = b"BSTSIDX\x01";
+
+/// Index offset and [`MAGIC_INDEX_END`].
+const TAIL_SIZE: u64 = 16;
+/// `signature::TRAILER_SIZE`, which signed archives end with; kept here so
+/// that archives can be read without the `signing` feature.
+const SIGNATURE_TRAILER_SIZE: u64 = 80;
+
+/// Upper bound for the capacity reserved up-front; `count` comes from
+/// untrusted input and is only trusted as far as entries actually parse.
+const MAX_PREALLOC_ENTRIES: usize = 1024;
+
+const KIND_FILE: u8 = 0;
+const KIND_DIRECTORY: u8 = 1;
+const KIND_SYMLINK: u8 = 2;
+
+const HAS_MODE: u8 = 0x01;
+const HAS_MTIME: u8 = 0x02;
+const HAS_UID: u8 = 0x04;
+const HAS_GID: u8 = 0x08;
+const HAS_XATTRS: u8 = 0x10;
+/// The file is a slice of a solid block.
+const IN_BLOCK: u8 = 0x20;
+const KNOWN_FLAGS: u8 = HAS_MODE | HAS_MTIME | HAS_UID | HAS_GID | HAS_XATTRS | IN_BLOCK;
+
+/// Block size for [`ArchiveWriter::solid`] when there is no reason to pick
+/// another: large enough to amortize tables and footers, small enough that
+/// reading one file decodes little else.
+pub const SOLID_BLOCK_SIZE: usize = 1 << 20;
+
+/// Bytes [`ArchiveWriter::add_reader`] compresses at a time.
+const PIECE_SIZE: usize = 256 * BLOCK_SIZE;
+
+/// Errors in the archive index.
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("invalid archive magic")]
+    Magic,
+    #[error("archive entry path is not UTF-8")]
+    PathEncoding,
+    #[error("archive entry path is longer than {} bytes", u16::MAX)]
+    PathTooLong,
+    #[error("archive has more than {} entries", u32::MAX)]
+    TooManyEntries,
+    #[error("archive index is malformed")]
+    Malformed,
+    #[error("archive entry {path:?} extends past the end of the archive")]
+    OutOfBounds { path: String },
+    #[error("path {0:?} not found in archive")]
+    NotFound(String),
+    #[error("archive entry {0:?} is not a regular file")]
+    NotAFile(String),
+    #[error("version 1 archives only hold regular files outside solid blocks, not {0:?}")]
+    NeedsV2(String),
+    #[error("unsafe archive path {path:?}: {reason}")]
+    UnsafePath { path: String, reason: &'static str },
+    #[error("archive holds {0:?} more than once")]
+    DuplicatePath(String),
+    #[error("archive index is at the end of the archive; use ArchiveReader")]
+    TrailingIndex,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Index format, chosen by the magic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    #[default]
+    V2,
+}
+
+impl Version {
+    fn magic(self) -> &'static [u8; 8] {
+        match self {
+            Version::V1 => MAGIC_V1,
+            Version::V2 => MAGIC_V2,
+        }
+    }
+}
+
+/// Where the index is stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Index first, then the sealed frames.
+    #[default]
+    Leading,
+    /// Sealed frames first, then the index, its offset and
+    /// [`MAGIC_INDEX_END`]. Version 2 entries only.
+    Trailing,
+}
+
+/// What an entry is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink { target: String },
+}
+
+/// Seconds and nanoseconds since the Unix epoch; negative before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub secs: i64,
+    pub nanos: u32,
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => Timestamp {
+                secs: after.as_secs() as i64,
+                nanos: after.subsec_nanos(),
+            },
+            Err(e) => {
+                let before = e.duration();
+                let (secs, nanos) = (before.as_secs() as i64, before.subsec_nanos());
+                match nanos {
+                    0 => Timestamp {
+                        secs: -secs,
+                        nanos: 0,
+                    },
+                    _ => Timestamp {
+                        secs: -secs - 1,
+                        nanos: 1_000_000_000 - nanos,
+                    },
+                }
+            }
+        }
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(time: Timestamp) -> Self {
+        let nanos = Duration::from_nanos(time.nanos as u64);
+        match u64::try_from(time.secs) {
+            Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs) + nanos,
+            Err(_) => UNIX_EPOCH - Duration::from_secs(time.secs.unsigned_abs()) + nanos,
+        }
+    }
+}
+
+/// File attributes stored by version 2 archives. Every field is optional;
+/// version 1 entries have none.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Permission bits (`0o7777`), as in `st_mode`.
+    pub mode: Option<u32>,
+    pub mtime: Option<Timestamp>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Extended attributes as (name, value).
+    pub xattrs: Vec<(String, Vec<u8>)>,
+}
+
+/// One entry in the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Relative path, `/`-separated.
+    pub path: String,
+    pub kind: EntryKind,
+    /// Start of the sealed frame within the archive (files only).
+    pub offset: u64,
+    /// Length of the sealed frame, payload and footer (files only).
+    pub size: u64,
+    pub metadata: Metadata,
+    /// Where the file is within the decoded frame, if that is a solid block
+    /// shared with other files.
+    pub block: Option<BlockSlice>,
+}
+
+/// Part of a decoded solid block holding one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockSlice {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl Entry {
+    fn new(path: String, kind: EntryKind, metadata: Metadata) -> Self {
+        Self {
+            path,
+            kind,
+            offset: 0,
+            size: 0,
+            metadata,
+            block: None,
+        }
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == EntryKind::File
+    }
+}
+
+/// Normalizes an entry path: `/` and `\\` both separate components, and
+/// empty and `.` components are dropped. Fails for empty and absolute paths
+/// (including `C:` prefixes), `..` components and NUL bytes.
+pub fn sanitize_path(path: &str) -> Result<String, ArchiveError> {
+    let unsafe_path = |reason| ArchiveError::UnsafePath {
+        path: path.to_owned(),
+        reason,
+    };
+    if path.contains('\0') {
+        return Err(unsafe_path("contains a NUL byte"));
+    }
+    if is_absolute(path) {
+        return Err(unsafe_path("is absolute"));
+    }
+    let mut parts = Vec::new();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => return Err(unsafe_path("contains `..`")),
+            part => parts.push(part),
+        }
+    }
+    if parts.is_empty() {
+        return Err(unsafe_path("is empty"));
+    }
+    Ok(parts.join("/"))
+}
+
+fn is_absolute(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    path.starts_with(['/', '\\'])
+        || (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+}
+
+/// Why a symlink at the sanitized `link` pointing at `target` is unsafe to
+/// extract: it resolves outside the archive root, judging by the path
+/// alone, or passes through one of the archive's `symlinks` on the way,
+/// whose own target would change where it ends up (`s -> .` makes
+/// `l -> s/..` the parent of the root).
+fn unsafe_target(link: &str, target: &str, symlinks: &HashSet<String>) -> Option<&'static str> {
+    if is_absolute(target) {
+        return Some("is a symlink pointing outside the archive");
+    }
+    let mut path: Vec<&str> = link.split('/').collect();
+    path.pop();
+    let mut parts = target
+        .split(['/', '\\'])
+        .filter(|part| !matches!(*part, "" | "."))
+        .peekable();
+    while let Some(part) = parts.next() {
+        if part != ".." {
+            path.push(part);
+            if parts.peek().is_some() && symlinks.contains(&path.join("/")) {
+                return Some("is a symlink pointing through another symlink");
+            }
+        } else if path.pop().is_none() {
+            return Some("is a symlink pointing outside the archive");
+        }
+    }
+    None
+}
+
+/// Parses the index at the start of an archive, without checking offsets.
+/// Streamed archives fail with [`ArchiveError::TrailingIndex`].
+pub fn read_index(reader: &mut impl Read) -> Result<(Version, Vec<Entry>), ArchiveError> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    let version = match &magic {
+        MAGIC_V1 => Version::V1,
+        MAGIC_V2 => Version::V2,
+        MAGIC_STREAMED => return Err(ArchiveError::TrailingIndex),
+        _ => return Err(ArchiveError::Magic),
+    };
+    read_entries(reader, version)
+}
+
+/// Finds the index of a streamed archive `len` bytes long from its tail,
+/// which may be followed by a signature trailer. Returns the index offset,
+/// where the data ends, and the entries.
+fn read_trailing_index<R: Read + Seek>(
+    reader: &mut R,
+    len: u64,
+) -> Result<(u64, Vec<Entry>), ArchiveError> {
+    for end in [len, len.saturating_sub(SIGNATURE_TRAILER_SIZE)] {
+        if end < MAGIC_STREAMED.len() as u64 + TAIL_SIZE {
+            continue;
+        }
+        let tail = end - TAIL_SIZE;
+        reader.seek(SeekFrom::Start(tail))?;
+        let index_offset = reader.read_u64::<LittleEndian>()?;
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC_INDEX_END {
+            continue;
+        }
+        if index_offset < MAGIC_STREAMED.len() as u64 || index_offset > tail {
+            return Err(ArchiveError::Malformed);
+        }
+        reader.seek(SeekFrom::Start(index_offset))?;
+        let mut index = reader.take(tail - index_offset);
+        let (_, entries) = read_entries(&mut index, Version::V2)?;
+        if index.limit() != 0 {
+            return Err(ArchiveError::Malformed);
+        }
+        return Ok((index_offset, entries));
+    }
+    Err(ArchiveError::Malformed)
+}
+
+/// Reads the entry count and entries that follow the magic.
+fn read_entries(
+    reader: &mut impl Read,
+    version: Version,
+) -> Result<(Version, Vec<Entry>), ArchiveError> {
+    let count = reader.read_u32::<LittleEndian>()?;
+    let mut entries = Vec::with_capacity((count as usize).min(MAX_PREALLOC_ENTRIES));
+    for _ in 0..count {
+        entries.push(match version {
+            Version::V1 => read_v1_entry(reader)?,
+            Version::V2 => read_v2_entry(reader)?,
+        });
+    }
+    Ok((version, entries))
+}
+
+fn read_v1_entry(reader: &mut impl Read) -> Result<Entry, ArchiveError> {
+    let path = read_string16(reader)?;
+    let offset = reader.read_u64::<LittleEndian>()?;
+    let size = reader.read_u64::<LittleEndian>()?;
+    Ok(Entry {
+        path,
+        kind: EntryKind::File,
+        offset,
+        size,
+        metadata: Metadata::default(),
+        block: None,
+    })
+}
+
+fn read_v2_entry(reader: &mut impl Read) -> Result<Entry, ArchiveError> {
+    let kind = reader.read_u8()?;
+    let flags = reader.read_u8()?;
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(ArchiveError::Malformed);
+    }
+    let path = read_string16(reader)?;
+    let mut metadata = Metadata::default();
+    if flags & HAS_MODE != 0 {
+        metadata.mode = Some(reader.read_u32::<LittleEndian>()?);
+    }
+    if flags & HAS_MTIME != 0 {
+        let secs = reader.read_i64::<LittleEndian>()?;
+        let nanos = reader.read_u32::<LittleEndian>()?;
+        if nanos >= 1_000_000_000 {
+            return Err(ArchiveError::Malformed);
+        }
+        metadata.mtime = Some(Timestamp { secs, nanos });
+    }
+    if flags & HAS_UID != 0 {
+        metadata.uid = Some(reader.read_u32::<LittleEndian>()?);
+    }
+    if flags & HAS_GID != 0 {
+        metadata.gid = Some(reader.read_u32::<LittleEndian>()?);
+    }
+    if flags & HAS_XATTRS != 0 {
+        let count = reader.read_u16::<LittleEndian>()?;
+        for _ in 0..count {
+            let name = read_string16(reader)?;
+            let len = reader.read_u32::<LittleEndian>()?;
+            metadata
+                .xattrs
+                .push((name, read_bytes(reader, len as u64)?));
+        }
+    }
+    if flags & IN_BLOCK != 0 && kind != KIND_FILE {
+        return Err(ArchiveError::Malformed);
+    }
+    let mut block = None;
+    let (kind, offset, size) = match kind {
+        KIND_FILE => {
+            let offset = reader.read_u64::<LittleEndian>()?;
+            let size = reader.read_u64::<LittleEndian>()?;
+            if flags & IN_BLOCK != 0 {
+                block = Some(BlockSlice {
+                    offset: reader.read_u64::<LittleEndian>()?,
+                    len: reader.read_u64::<LittleEndian>()?,
+                });
+            }
+            (EntryKind::File, offset, size)
+        }
+        KIND_DIRECTORY => (EntryKind::Directory, 0, 0),
+        KIND_SYMLINK => {
+            let target = read_string16(reader)?;
+            (EntryKind::Symlink { target }, 0, 0)
+        }
+        _ => return Err(ArchiveError::Malformed),
+    };
+    Ok(Entry {
+        path,
+        kind,
+        offset,
+        size,
+        metadata,
+        block,
+    })
+}
+
+/// Reads `len` bytes without trusting `len` for the allocation.
+fn read_bytes(reader: &mut impl Read, len: u64) -> Result<Vec<u8>, ArchiveError> {
+    let mut buf = Vec::new();
+    reader.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(buf)
+}
+
+fn read_string16(reader: &mut impl Read) -> Result<String, ArchiveError> {
+    let len = reader.read_u16::<LittleEndian>()?;
+    String::from_utf8(read_bytes(reader, len as u64)?).map_err(|_| ArchiveError::PathEncoding)
+}
+
+fn write_string16(out: &mut Vec<u8>, s: &str) -> Result<(), ArchiveError> {
+    let len = u16::try_from(s.len()).map_err(|_| ArchiveError::PathTooLong)?;
+    out.write_u16::<LittleEndian>(len)?;
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn write_entry(out: &mut Vec<u8>, entry: &Entry, version: Version) -> Result<(), ArchiveError> {
+    if version == Version::V1 {
+        if entry.block.is_some() {
+            return Err(ArchiveError::NeedsV2(entry.path.clone()));
+        }
+        write_string16(out, &entry.path)?;
+        out.write_u64::<LittleEndian>(entry.offset)?;
+        out.write_u64::<LittleEndian>(entry.size)?;
+        return Ok(());
+    }
+    let meta = &entry.metadata;
+    out.push(match entry.kind {
+        EntryKind::File => KIND_FILE,
+        EntryKind::Directory => KIND_DIRECTORY,
+        EntryKind::Symlink { .. } => KIND_SYMLINK,
+    });
+    let flag = |present: bool, flag: u8| if present { flag } else { 0 };
+    out.push(
+        flag(meta.mode.is_some(), HAS_MODE)
+            | flag(meta.mtime.is_some(), HAS_MTIME)
+            | flag(meta.uid.is_some(), HAS_UID)
+            | flag(meta.gid.is_some(), HAS_GID)
+            | flag(!meta.xattrs.is_empty(), HAS_XATTRS)
+            | flag(entry.block.is_some(), IN_BLOCK),
+    );
+    write_string16(out, &entry.path)?;
+    if let Some(mode) = meta.mode {
+        out.write_u32::<LittleEndian>(mode)?;
+    }
+    if let Some(mtime) = meta.mtime {
+        out.write_i64::<LittleEndian>(mtime.secs)?;
+        out.write_u32::<LittleEndian>(mtime.nanos)?;
+    }
+    if let Some(uid) = meta.uid {
+        out.write_u32::<LittleEndian>(uid)?;
+    }
+    if let Some(gid) = meta.gid {
+        out.write_u32::<LittleEndian>(gid)?;
+    }
+    if !meta.xattrs.is_empty() {
+        let count = u16::try_from(meta.xattrs.len()).map_err(|_| ArchiveError::Malformed)?;
+        out.write_u16::<LittleEndian>(count)?;
+        for (name, value) in &meta.xattrs {
+            write_string16(out, name)?;
+            let len = u32::try_from(value.len()).map_err(|_| ArchiveError::Malformed)?;
+            out.write_u32::<LittleEndian>(len)?;
+            out.extend_from_slice(value);
+        }
+    }
+    match &entry.kind {
+        EntryKind::File => {
+            out.write_u64::<LittleEndian>(entry.offset)?;
+            out.write_u64::<LittleEndian>(entry.size)?;
+            if let Some(block) = entry.block {
+                out.write_u64::<LittleEndian>(block.offset)?;
+                out.write_u64::<LittleEndian>(block.len)?;
+            }
+        }
+        EntryKind::Directory => {}
+        EntryKind::Symlink { target } => write_string16(out, target)?,
+    }
+    Ok(())
+}
+
+/// Builds an archive in memory and writes it to `W` on
+/// [`finish`](Self::finish), or streams it with
+/// [`streaming`](Self::streaming).
+pub struct ArchiveWriter<'a, W: Write> {
+    inner: W,
+    options: SealOptions<'a>,
+    version: Version,
+    layout: Layout,
+    entries: Vec<(Entry, Data)>,
+    paths: HashSet<String>,
+    /// Bytes written to `inner` so far, or its length when opened in place.
+    written: u64,
+    /// Set for archives opened in place; rolls them back unless committed.
+    journal: Option<update::Journal>,
+    /// Block size of a solid archive.
+    solid: Option<usize>,
+    /// Decoded contents of the block being filled, `blocks.len()`.
+    block: Vec<u8>,
+    blocks: Vec<Block>,
+    /// Blocks copied by [`add_raw`](Self::add_raw), by source offset.
+    raw_blocks: HashMap<u64, usize>,
+}
+
+/// Where the data of an entry being written is.
+enum Data {
+    /// Its own sealed frame; empty for non-files and once written.
+    Frame(Vec<u8>),
+    /// A slice of `blocks[index]`, or of the block being filled.
+    Block(usize),
+}
+
+/// A sealed block of a solid archive.
+struct Block {
+    /// Empty once written.
+    sealed: Vec<u8>,
+    /// Where it was written, in streamed archives.
+    offset: u64,
+    size: u64,
+}
+
+impl<'a, W: Write> ArchiveWriter<'a, W> {
+    /// Writes a version 2 archive; every file is sealed with `options`.
+    pub fn new(inner: W, options: SealOptions<'a>) -> Self {
+        Self::with_version(inner, options, Version::V2)
+    }
+
+    /// Version 1 archives are readable by older releases but drop metadata
+    /// and cannot hold directories, symlinks or solid blocks.
+    pub fn with_version(inner: W, options: SealOptions<'a>, version: Version) -> Self {
+        Self {
+            inner,
+            options,
+            version,
+            layout: Layout::Leading,
+            entries: Vec::new(),
+            paths: HashSet::new(),
+            written: 0,
+            journal: None,
+            solid: None,
+            block: Vec::new(),
+            blocks: Vec::new(),
+            raw_blocks: HashMap::new(),
+        }
+    }
+
+    /// Writes a streamed archive ([`Layout::Trailing`]): every file goes to
+    /// `inner` as soon as it is sealed, so memory use is bounded by the
+    /// largest file plus the index, or just the index with
+    /// [`add_reader`](Self::add_reader), and `inner` need not be seekable.
+    pub fn streaming(inner: W, options: SealOptions<'a>) -> Self {
+        Self {
+            layout: Layout::Trailing,
+            ..Self::with_version(inner, options, Version::V2)
+        }
+    }
+
+    /// Makes a solid archive: files smaller than `block_size` are
+    /// concatenated into blocks of about that size, each sealed as one
+    /// frame, so that small files share Huffman tables and footers. Reading
+    /// such a file decodes its whole block ([`SOLID_BLOCK_SIZE`] is a good
+    /// default). Needs version 2 and a tier with high-ratio compression.
+    pub fn solid(mut self, block_size: usize) -> Self {
+        self.solid = Some(block_size);
+        self
+    }
+
+    /// Compresses and seals `data` as the file `path`.
+    pub fn add(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        self.add_file(path, data, Metadata::default())
+    }
+
+    /// Compresses and seals `data` as the file `path` with `metadata`.
+    pub fn add_file(&mut self, path: &str, data: &[u8], metadata: Metadata) -> Result<()> {
+        let path = self.check(path, &EntryKind::File)?;
+        let entry = Entry::new(path, EntryKind::File, metadata);
+        match self.solid {
+            Some(block_size) if data.len() < block_size => {
+                let slice = BlockSlice {
+                    offset: self.block.len() as u64,
+                    len: data.len() as u64,
+                };
+                self.block.extend_from_slice(data);
+                let entry = Entry {
+                    block: Some(slice),
+                    ..entry
+                };
+                self.push(entry, Data::Block(self.blocks.len()))?;
+                if self.block.len() >= block_size {
+                    self.flush_block()?;
+                }
+                Ok(())
+            }
+            _ => {
+                let sealed = seal(data, &self.options)?;
+                self.push(entry, Data::Frame(sealed))
+            }
+        }
+    }
+
+    /// [`add_file`](Self::add_file) with the contents read from `reader`.
+    /// In a streamed archive they are compressed and written a piece at a
+    /// time, so memory use does not grow with the file, unless the seal
+    /// options need all data first (content hash, block index or
+    /// encryption). Otherwise the file is read whole.
+    pub fn add_reader(
+        &mut self,
+        path: &str,
+        mut reader: impl Read,
+        metadata: Metadata,
+    ) -> Result<()> {
+        // Files that may go to a solid block are read whole anyway.
+        let first = self.solid.map_or(PIECE_SIZE, |size| size.max(PIECE_SIZE));
+        let mut data = Vec::new();
+        (&mut reader).take(first as u64).read_to_end(&mut data)?;
+        if data.len() < first || self.layout != Layout::Trailing || !Sealer::supports(&self.options)
+        {
+            reader.read_to_end(&mut data)?;
+            return self.add_file(path, &data, metadata);
+        }
+
+        let path = self.check(path, &EntryKind::File)?;
+        let mut entry = Entry::new(path, EntryKind::File, metadata);
+        let (mut sealer, header) = Sealer::start(&self.options)?;
+        entry.offset = self.write_frame(&header)?;
+        loop {
+            // Pieces are whole blocks; a short one is the last.
+            let missing = PIECE_SIZE.saturating_sub(data.len());
+            (&mut reader).take(missing as u64).read_to_end(&mut data)?;
+            let len = data.len().min(PIECE_SIZE);
+            let payload = sealer.update(&data[..len])?;
+            self.write_frame(&payload)?;
+            data.drain(..len);
+            if len < PIECE_SIZE {
+                break;
+            }
+        }
+        self.write_frame(&sealer.finish())?;
+        entry.size = self.written - entry.offset;
+        self.paths.insert(entry.path.clone());
+        self.entries.push((entry, Data::Frame(Vec::new())));
+        Ok(())
+    }
+
+    /// Adds a directory, which may be empty.
+    pub fn add_dir(&mut self, path: &str, metadata: Metadata) -> Result<()> {
+        let path = self.check(path, &EntryKind::Directory)?;
+        let entry = Entry::new(path, EntryKind::Directory, metadata);
+        self.push(entry, Data::Frame(Vec::new()))
+    }
+
+    /// Adds a symlink pointing at `target`, which is stored verbatim.
+    /// Targets outside the archive are allowed here but refused by
+    /// [`ArchiveReader::safe_paths`].
+    pub fn add_symlink(&mut self, path: &str, target: &str, metadata: Metadata) -> Result<()> {
+        if target.len() > u16::MAX as usize {
+            return Err(ArchiveError::PathTooLong.into());
+        }
+        let kind = EntryKind::Symlink {
+            target: target.to_owned(),
+        };
+        let path = self.check(path, &kind)?;
+        self.push(Entry::new(path, kind, metadata), Data::Frame(Vec::new()))
+    }
+
+    /// Adds `entry` with its sealed frame as stored in another archive (see
+    /// [`ArchiveReader::raw`]), without decoding it. Offset and size are
+    /// taken from `sealed`. Files of a solid block pass the block; later
+    /// files from the same block (same `offset`) share the first copy and
+    /// may pass an empty `sealed`.
+    pub fn add_raw(&mut self, entry: Entry, sealed: Vec<u8>) -> Result<()> {
+        let path = self.check(&entry.path, &entry.kind)?;
+        let source = entry.offset;
+        let entry = Entry {
+            path,
+            offset: 0,
+            size: 0,
+            ..entry
+        };
+        if entry.block.is_none() {
+            return self.push(entry, Data::Frame(sealed));
+        }
+        if self.version == Version::V1 {
+            return Err(ArchiveError::NeedsV2(entry.path).into());
+        }
+        let index = match self.raw_blocks.get(&source) {
+            Some(&index) => index,
+            None => {
+                self.flush_block()?;
+                let index = self.add_block(sealed)?;
+                self.raw_blocks.insert(source, index);
+                index
+            }
+        };
+        self.push(entry, Data::Block(index))
+    }
+
+    /// Removes the entry stored as `path`. In an archive opened in place its
+    /// data stays in the file until [`compact`].
+    pub fn remove(&mut self, path: &str) -> Result<Entry, ArchiveError> {
+        let index = self
+            .entries
+            .iter()
+            .position(|(entry, _)| entry.path == path)
+            .ok_or_else(|| ArchiveError::NotFound(path.to_owned()))?;
+        self.paths.remove(path);
+        Ok(self.entries.remove(index).0)
+    }
+
+    /// Entries in index order, including those of an archive opened in place.
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().map(|(entry, _)| entry)
+    }
+
+    /// Returns the sanitized `path` if it may be added.
+    fn check(&self, path: &str, kind: &EntryKind) -> Result<String, ArchiveError> {
+        let path = sanitize_path(path)?;
+        if path.len() > u16::MAX as usize {
+            return Err(ArchiveError::PathTooLong);
+        }
+        if self.entries.len() == u32::MAX as usize {
+            return Err(ArchiveError::TooManyEntries);
+        }
+        if self.version == Version::V1 && (*kind != EntryKind::File || self.solid.is_some()) {
+            return Err(ArchiveError::NeedsV2(path));
+        }
+        if self.paths.contains(&path) {
+            return Err(ArchiveError::DuplicatePath(path));
+        }
+        Ok(path)
+    }
+
+    fn push(&mut self, mut entry: Entry, mut data: Data) -> Result<()> {
+        if let Data::Frame(sealed) = &mut data {
+            entry.size = sealed.len() as u64;
+            if self.layout == Layout::Trailing {
+                self.start()?;
+                if entry.is_file() {
+                    entry.offset = self.write_frame(sealed)?;
+                    *sealed = Vec::new();
+                }
+            }
+        }
+        self.paths.insert(entry.path.clone());
+        self.entries.push((entry, data));
+        Ok(())
+    }
+
+    /// Seals the block being filled.
+    fn flush_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        #[cfg(all(feature = "licensing", not(any(test, fuzzing))))]
+        crate::license::require(crate::license::Feature::HighRatio)?;
+        let sealed = seal(&self.block, &self.options)?;
+        self.block.clear();
+        self.add_block(sealed)?;
+        Ok(())
+    }
+
+    /// Stores a sealed block, writing it right away in streamed archives.
+    fn add_block(&mut self, sealed: Vec<u8>) -> Result<usize> {
+        let mut block = Block {
+            offset: 0,
+            size: sealed.len() as u64,
+            sealed,
+        };
+        if self.layout == Layout::Trailing {
+            block.offset = self.write_frame(&block.sealed)?;
+            block.sealed = Vec::new();
+        }
+        self.blocks.push(block);
+        Ok(self.blocks.len() - 1)
+    }
+
+    /// Writes a frame of a streamed archive and returns its offset.
+    fn write_frame(&mut self, sealed: &[u8]) -> Result<u64> {
+        self.start()?;
+        #[cfg(all(feature = "licensing", not(any(test, fuzzing))))]
+        crate::license::capabilities()?.check_archive_size(self.written + sealed.len() as u64)?;
+        let offset = self.written;
+        self.write(sealed)?;
+        Ok(offset)
+    }
+
+    /// Writes the magic of a streamed archive, once.
+    fn start(&mut self) -> io::Result<()> {
+        match self.written {
+            0 => self.write(MAGIC_STREAMED),
+            _ => Ok(()),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the index and the sealed entries, and returns the writer. For
+    /// an archive opened in place this is the point where the change takes
+    /// effect.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush_block()?;
+        // Files of copied blocks may come in any order with the others, so
+        // each is pointed at its block only now that all are sealed.
+        for (entry, data) in &mut self.entries {
+            if let Data::Block(b) = data {
+                entry.offset = self.blocks[*b].offset;
+                entry.size = self.blocks[*b].size;
+            }
+        }
+        if self.layout == Layout::Trailing {
+            self.start()?;
+            let index_offset = self.written;
+            let mut tail = self.index()?.split_off(MAGIC_STREAMED.len());
+            tail.write_u64::<LittleEndian>(index_offset)?;
+            tail.extend_from_slice(MAGIC_INDEX_END);
+            self.write(&tail)?;
+            self.inner.flush()?;
+            if let Some(journal) = self.journal.take() {
+                journal.commit()?;
+            }
+            return Ok(self.inner);
+        }
+        // Offsets are fixed-width, so the index length does not depend on them.
+        // Each block is stored where its first file is.
+        let mut header = self.index()?;
+        let mut offset = header.len() as u64;
+        let mut placed = vec![None; self.blocks.len()];
+        let mut frames = Vec::new();
+        for (i, (entry, data)) in self.entries.iter_mut().enumerate() {
+            match data {
+                Data::Frame(sealed) if entry.is_file() => {
+                    entry.offset = offset;
+                    offset += sealed.len() as u64;
+                    frames.push((i, false));
+                }
+                Data::Frame(_) => {}
+                Data::Block(b) => {
+                    if placed[*b].is_none() {
+                        placed[*b] = Some(offset);
+                        offset += self.blocks[*b].size;
+                        frames.push((*b, true));
+                    }
+                    entry.offset = placed[*b].unwrap_or_default();
+                }
+            }
+        }
+        #[cfg(all(feature = "licensing", not(any(test, fuzzing))))]
+        crate::license::capabilities()?.check_archive_size(offset)?;
+        header = self.index()?;
+
+        self.inner.write_all(&header)?;
+        for (i, is_block) in frames {
+            match (is_block, &self.entries[i].1) {
+                (true, _) => self.inner.write_all(&self.blocks[i].sealed)?,
+                (false, Data::Frame(sealed)) => self.inner.write_all(sealed)?,
+                (false, Data::Block(_)) => {}
+            }
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn index(&self) -> Result<Vec<u8>, ArchiveError> {
+        let mut header = Vec::new();
+        header.extend_from_slice(self.version.magic());
+        header.write_u32::<LittleEndian>(self.entries.len() as u32)?;
+        for (entry, _) in &self.entries {
+            write_entry(&mut header, entry, self.version)?;
+        }
+        Ok(header)
+    }
+}
+
+/// Reads entries from an archive.
+pub struct ArchiveReader<R> {
+    inner: R,
+    version: Version,
+    layout: Layout,
+    entries: Vec<Entry>,
+    /// The last solid block decoded.
+    block: Option<SolidBlock>,
+}
+
+/// A decoded solid block, kept while files in it are read.
+struct SolidBlock {
+    offset: u64,
+    sealed: Vec<u8>,
+    opened_with: OpenedWith,
+    data: Vec<u8>,
+}
+
+/// The options a [`SolidBlock`] was opened with, compared by value: a read
+/// with other keys or limits opens the block again.
+#[derive(PartialEq)]
+struct OpenedWith {
+    mac_key: Option<[u8; crate::integrity::KEY_SIZE]>,
+    #[cfg(feature = "encryption")]
+    secret: Option<[u8; 32]>,
+    #[cfg(feature = "signing")]
+    verifying_key: Option<crate::signature::VerifyingKey>,
+    limits: crate::limits::DecodeLimits,
+}
+
+impl OpenedWith {
+    fn new(options: &OpenOptions) -> Self {
+        Self {
+            mac_key: options.mac_key.copied(),
+            #[cfg(feature = "encryption")]
+            secret: options.decryption.map(|secret| secret.fingerprint()),
+            #[cfg(feature = "signing")]
+            verifying_key: options.verifying_key.copied(),
+            limits: options.limits,
+        }
+    }
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Reads the index, wherever it is, and checks that every file lies
+    /// within the data of `inner`.
+    pub fn new(mut inner: R) -> Result<Self, ArchiveError> {
+        let len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        let mut magic = [0u8; 8];
+        inner.read_exact(&mut magic)?;
+        let (version, layout, entries, data_end) = if &magic == MAGIC_STREAMED {
+            let (index_offset, entries) = read_trailing_index(&mut inner, len)?;
+            (Version::V2, Layout::Trailing, entries, index_offset)
+        } else {
+            inner.seek(SeekFrom::Start(0))?;
+            let (version, entries) = read_index(&mut inner)?;
+            (version, Layout::Leading, entries, len)
+        };
+        for entry in entries.iter().filter(|entry| entry.is_file()) {
+            if entry
+                .offset
+                .checked_add(entry.size)
+                .is_none_or(|end| end > data_end)
+            {
+                return Err(ArchiveError::OutOfBounds {
+                    path: entry.path.clone(),
+                });
+            }
+        }
+        Ok(Self {
+            inner,
+            version,
+            layout,
+            entries,
+            block: None,
+        })
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Entries in index order.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Sanitized paths of all entries, in index order, for extraction.
+    /// Fails if any path is unsafe (see [`sanitize_path`]), two entries
+    /// share a path, or a symlink points outside the archive or through
+    /// another symlink of it.
+    pub fn safe_paths(&self) -> Result<Vec<String>, ArchiveError> {
+        let symlinks: HashSet<_> = self
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry.kind, EntryKind::Symlink { .. }))
+            .filter_map(|entry| sanitize_path(&entry.path).ok())
+            .collect();
+        let mut seen = HashSet::new();
+        let mut paths = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let path = sanitize_path(&entry.path)?;
+            if let EntryKind::Symlink { target } = &entry.kind {
+                if let Some(reason) = unsafe_target(&path, target, &symlinks) {
+                    return Err(ArchiveError::UnsafePath {
+                        path: entry.path.clone(),
+                        reason,
+                    });
+                }
+            }
+            if !seen.insert(path.clone()) {
+                return Err(ArchiveError::DuplicatePath(path));
+            }
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// The first entry stored as `path`.
+    pub fn find(&self, path: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    /// The sealed frame of the file `entry`, as stored: for a file in a
+    /// solid block, the whole block.
+    pub fn raw(&mut self, entry: &Entry) -> Result<impl Read + '_, ArchiveError> {
+        if !entry.is_file() {
+            return Err(ArchiveError::NotAFile(entry.path.clone()));
+        }
+        self.inner.seek(SeekFrom::Start(entry.offset))?;
+        Ok((&mut self.inner).take(entry.size))
+    }
+
+    /// Verifies and decodes the file `entry`.
+    pub fn read(&mut self, entry: &Entry, options: &OpenOptions) -> Result<Vec<u8>> {
+        self.read_with(entry, |_| *options)
+    }
+
+    /// [`read`](Self::read) with options chosen from the sealed frame, such
+    /// as keys for an encrypted one. A file in a solid block is read by
+    /// decoding the whole block, which is kept for the next file in it that
+    /// is read with the same options.
+    pub fn read_with<'o>(
+        &mut self,
+        entry: &Entry,
+        options: impl FnOnce(&[u8]) -> OpenOptions<'o>,
+    ) -> Result<Vec<u8>> {
+        let mut sealed = Vec::new();
+        let Some(slice) = entry.block else {
+            self.raw(entry)?.read_to_end(&mut sealed)?;
+            return open(&sealed, &options(&sealed));
+        };
+        let cached = match self.block.take() {
+            Some(block) if block.offset == entry.offset => {
+                sealed = block.sealed;
+                Some((block.opened_with, block.data))
+            }
+            _ => {
+                self.raw(entry)?.read_to_end(&mut sealed)?;
+                None
+            }
+        };
+        let options = options(&sealed);
+        let opened_with = OpenedWith::new(&options);
+        let block = match cached {
+            Some((with, block)) if with == opened_with => block,
+            _ => open(&sealed, &options)?,
+        };
+        let range = slice
+            .offset
+            .checked_add(slice.len)
+            .filter(|end| *end <= block.len() as u64)
+            .map(|end| slice.offset as usize..end as usize);
+        let data = range.map(|range| block[range].to_vec());
+        self.block = Some(SolidBlock {
+            offset: entry.offset,
+            sealed,
+            opened_with,
+            data: block,
+        });
+        Ok(data.ok_or_else(|| ArchiveError::OutOfBounds {
+            path: entry.path.clone(),
+        })?)
+    }
+
+    /// Decoded contents of the file stored as `path`. The entry is verified
+    /// in full before the first byte is returned.
+    pub fn open(&mut self, path: &str, options: &OpenOptions) -> Result<impl Read> {
+        let entry = self
+            .find(path)
+            .cloned()
+            .ok_or_else(|| ArchiveError::NotFound(path.to_owned()))?;
+        Ok(Cursor::new(self.read(&entry, options)?))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Written by `bstseal pack` before the format moved into this module.
+    const V1_FIXTURE: &[u8] = include_bytes!("../testdata/v1.bsa");
+
+    fn fixture_data() -> Vec<u8> {
+        (0..5000).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn huge_count_does_not_preallocate() {
+        let mut data = MAGIC_V1.to_vec();
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_index(&mut data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn parses_entries() {
+        let mut data = MAGIC_V1.to_vec();
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&3u16.to_le_bytes());
+        data.extend_from_slice(b"a/b");
+        data.extend_from_slice(&29u64.to_le_bytes());
+        data.extend_from_slice(&7u64.to_le_bytes());
+        let (version, entries) = read_index(&mut data.as_slice()).unwrap();
+        assert_eq!(version, Version::V1);
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].is_file() && entries[0].metadata == Metadata::default());
+        assert_eq!(entries[0].path, "a/b");
+        assert_eq!((entries[0].offset, entries[0].size), (29, 7));
+        // The index points past the end of these bytes.
+        assert!(matches!(
+            ArchiveReader::new(Cursor::new(data)),
+            Err(ArchiveError::OutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn reads_archives_written_by_the_cli() {
+        let mut archive = ArchiveReader::new(Cursor::new(V1_FIXTURE)).unwrap();
+        let paths: Vec<&str> = archive.entries().iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["hello.txt", "docs/data.bin", "empty.txt"]);
+
+        let options = OpenOptions::default();
+        let mut hello = String::new();
+        archive
+            .open("hello.txt", &options)
+            .unwrap()
+            .read_to_string(&mut hello)
+            .unwrap();
+        assert_eq!(hello, "hello world\n");
+        let entry = archive.find("docs/data.bin").unwrap().clone();
+        assert_eq!(archive.read(&entry, &options).unwrap(), fixture_data());
+        assert!(archive
+            .read(&archive.entries()[2].clone(), &options)
+            .unwrap()
+            .is_empty());
+        assert!(archive.open("missing", &options).is_err());
+    }
+
+    #[test]
+    fn writer_reproduces_the_v1_layout() {
+        let mut writer =
+            ArchiveWriter::with_version(Vec::new(), SealOptions::default(), Version::V1);
+        writer.add("hello.txt", b"hello world\n").unwrap();
+        writer.add("docs/data.bin", &fixture_data()).unwrap();
+        writer.add("empty.txt", b"").unwrap();
+        assert_eq!(writer.len(), 3);
+        assert!(writer.add_dir("docs", Metadata::default()).is_err());
+        assert_eq!(writer.finish().unwrap(), V1_FIXTURE);
+    }
+
+    #[test]
+    fn v2_entries_keep_their_metadata() {
+        let file_meta = Metadata {
+            mode: Some(0o755),
+            mtime: Some(Timestamp {
+                secs: 1_700_000_000,
+                nanos: 123_456_789,
+            }),
+            uid: Some(1000),
+            gid: None,
+            xattrs: vec![("user.origin".into(), b"build-42".to_vec())],
+        };
+        let dir_meta = Metadata {
+            mode: Some(0o700),
+            ..Default::default()
+        };
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default());
+        writer.add_dir("bin", dir_meta.clone()).unwrap();
+        writer
+            .add_file("bin/run", b"#!/bin/sh\n", file_meta.clone())
+            .unwrap();
+        writer.add_dir("empty", Metadata::default()).unwrap();
+        writer
+            .add_symlink("run", "bin/run", Metadata::default())
+            .unwrap();
+        let data = writer.finish().unwrap();
+        assert!(data.starts_with(MAGIC_V2));
+
+        let mut archive = ArchiveReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(archive.version(), Version::V2);
+        let kinds: Vec<_> = archive.entries().iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            [
+                EntryKind::Directory,
+                EntryKind::File,
+                EntryKind::Directory,
+                EntryKind::Symlink {
+                    target: "bin/run".into()
+                }
+            ]
+        );
+        assert_eq!(archive.find("bin").unwrap().metadata, dir_meta);
+        let run = archive.find("bin/run").unwrap().clone();
+        assert_eq!(run.metadata, file_meta);
+        assert_eq!(
+            archive.read(&run, &OpenOptions::default()).unwrap(),
+            b"#!/bin/sh\n"
+        );
+        assert!(matches!(
+            archive.open("empty", &OpenOptions::default()),
+            Err(e) if matches!(e.downcast_ref(), Some(ArchiveError::NotAFile(_)))
+        ));
+    }
+
+    #[test]
+    fn unknown_kinds_and_flags_are_rejected() {
+        for (kind, flags) in [(3u8, 0u8), (0, 0x80)] {
+            let mut data = MAGIC_V2.to_vec();
+            data.extend_from_slice(&1u32.to_le_bytes());
+            data.extend_from_slice(&[kind, flags, 1, 0, b'a']);
+            data.extend_from_slice(&[0; 16]);
+            assert!(matches!(
+                read_index(&mut data.as_slice()),
+                Err(ArchiveError::Malformed)
+            ));
+        }
+    }
+
+    #[test]
+    fn timestamps_round_trip_through_system_time() {
+        for (secs, nanos) in [(0, 0), (1_700_000_000, 5), (-1, 999_999_999), (-86_400, 0)] {
+            let time = Timestamp { secs, nanos };
+            assert_eq!(Timestamp::from(SystemTime::from(time)), time);
+        }
+        let before_epoch = UNIX_EPOCH - Duration::from_millis(1500);
+        assert_eq!(
+            Timestamp::from(before_epoch),
+            Timestamp {
+                secs: -2,
+                nanos: 500_000_000
+            }
+        );
+    }
+
+    #[test]
+    fn raw_entries_are_the_sealed_frames() {
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default());
+        writer.add("a", b"first").unwrap();
+        writer.add("b", b"second").unwrap();
+        let mut archive = ArchiveReader::new(Cursor::new(writer.finish().unwrap())).unwrap();
+        let entry = archive.find("b").unwrap().clone();
+        let mut sealed = Vec::new();
+        archive
+            .raw(&entry)
+            .unwrap()
+            .read_to_end(&mut sealed)
+            .unwrap();
+        assert_eq!(sealed, seal(b"second", &SealOptions::default()).unwrap());
+
+        let data = archive.into_inner().into_inner();
+        let truncated = &data[..data.len() - 1];
+        assert!(matches!(
+            ArchiveReader::new(Cursor::new(truncated)),
+            Err(ArchiveError::OutOfBounds { path }) if path == "b"
+        ));
+    }
+    #[test]
+    fn streamed_archives_put_the_index_last() {
+        let mut writer = ArchiveWriter::streaming(Vec::new(), SealOptions::default());
+        writer.add("hello.txt", b"hello world\n").unwrap();
+        writer.add_dir("docs", Metadata::default()).unwrap();
+        writer.add("docs/data.bin", &fixture_data()).unwrap();
+        assert!(writer.add("hello.txt", b"again").is_err());
+        let data = writer.finish().unwrap();
+        assert!(data.starts_with(MAGIC_STREAMED) && data.ends_with(MAGIC_INDEX_END));
+        // The first frame follows the magic directly.
+        let hello = seal(b"hello world\n", &SealOptions::default()).unwrap();
+        assert_eq!(&data[8..8 + hello.len()], hello);
+        assert!(matches!(
+            read_index(&mut data.as_slice()),
+            Err(ArchiveError::TrailingIndex)
+        ));
+
+        #[cfg(feature = "signing")]
+        let data = {
+            let key = crate::signature::SigningKey::from_bytes(&[7; 32]);
+            assert_eq!(
+                SIGNATURE_TRAILER_SIZE,
+                crate::signature::TRAILER_SIZE as u64
+            );
+            crate::signature::sign(&data, &key).unwrap()
+        };
+        let mut archive = ArchiveReader::new(Cursor::new(data.as_slice())).unwrap();
+        assert_eq!(
+            (archive.version(), archive.layout()),
+            (Version::V2, Layout::Trailing)
+        );
+        let paths: Vec<&str> = archive.entries().iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["hello.txt", "docs", "docs/data.bin"]);
+        let entry = archive.find("docs/data.bin").unwrap().clone();
+        assert_eq!(
+            archive.read(&entry, &OpenOptions::default()).unwrap(),
+            fixture_data()
+        );
+
+        let empty = ArchiveWriter::streaming(Vec::new(), SealOptions::default());
+        let empty = ArchiveReader::new(Cursor::new(empty.finish().unwrap())).unwrap();
+        assert!(empty.entries().is_empty());
+    }
+
+    #[test]
+    fn streamed_archives_with_bad_tails_are_rejected() {
+        let mut writer = ArchiveWriter::streaming(Vec::new(), SealOptions::default());
+        writer.add("a", b"first").unwrap();
+        let data = writer.finish().unwrap();
+        let tail = data.len() - TAIL_SIZE as usize;
+
+        let truncated = &data[..data.len() - 1];
+        let mut past_end = data.clone();
+        past_end[tail..tail + 8].copy_from_slice(&(data.len() as u64).to_le_bytes());
+        // Index starts one byte late, so the count and entry misparse.
+        let mut shifted = data.clone();
+        let offset = u64::from_le_bytes(data[tail..tail + 8].try_into().unwrap());
+        shifted[tail..tail + 8].copy_from_slice(&(offset + 1).to_le_bytes());
+        for data in [truncated, &past_end, &shifted] {
+            assert!(ArchiveReader::new(Cursor::new(data)).is_err());
+        }
+    }
+
+    #[test]
+    fn solid_archives_share_blocks_between_small_files() {
+        let small: Vec<(String, Vec<u8>)> = (0..40)
+            .map(|i| {
+                (
+                    format!("json/{i}.json"),
+                    format!("{{\"id\": {i}}}").into_bytes(),
+                )
+            })
+            .collect();
+        for streamed in [false, true] {
+            let mut writer = match streamed {
+                false => ArchiveWriter::new(Vec::new(), SealOptions::default()),
+                true => ArchiveWriter::streaming(Vec::new(), SealOptions::default()),
+            }
+            .solid(200);
+            writer.add("big.bin", &fixture_data()).unwrap();
+            for (path, data) in &small {
+                writer.add(path, data).unwrap();
+            }
+            writer.add_dir("empty", Metadata::default()).unwrap();
+            let data = writer.finish().unwrap();
+
+            let mut archive = ArchiveReader::new(Cursor::new(data)).unwrap();
+            let entries = archive.entries().to_vec();
+            assert_eq!(entries[0].block, None);
+            let blocks: HashSet<u64> = entries[1..41].iter().map(|e| e.offset).collect();
+            assert!(blocks.len() > 1 && blocks.len() < 10, "{blocks:?}");
+            assert!(entries[1..41].iter().all(|e| e.block.is_some()));
+            for (entry, (path, data)) in entries[1..41].iter().zip(&small).rev() {
+                assert_eq!(&entry.path, path);
+                assert_eq!(&archive.read(entry, &OpenOptions::default()).unwrap(), data);
+            }
+            let mut big = Vec::new();
+            archive
+                .open("big.bin", &OpenOptions::default())
+                .unwrap()
+                .read_to_end(&mut big)
+                .unwrap();
+            assert_eq!(big, fixture_data());
+        }
+    }
+
+    #[test]
+    fn solid_files_decode_only_their_block() {
+        // A block is sealed once it reaches the block size.
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default()).solid(16);
+        for (path, data) in [
+            ("a", b"first block!"),
+            ("a2", b"also first!!"),
+            ("b", b"second block"),
+        ] {
+            writer.add(path, data).unwrap();
+        }
+        let mut data = writer.finish().unwrap();
+        let archive = ArchiveReader::new(Cursor::new(data.as_slice())).unwrap();
+        let (a, b) = (archive.entries()[0].clone(), archive.entries()[2].clone());
+        assert_eq!(a.offset, archive.entries()[1].offset);
+        assert_ne!(a.offset, b.offset);
+        data[b.offset as usize + b.size as usize / 2] ^= 1;
+
+        let mut archive = ArchiveReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(
+            archive.read(&a, &OpenOptions::default()).unwrap(),
+            b"first block!"
+        );
+        assert!(archive.read(&b, &OpenOptions::default()).is_err());
+
+        let mut v1 =
+            ArchiveWriter::with_version(Vec::new(), SealOptions::default(), Version::V1).solid(16);
+        assert!(matches!(
+            v1.add("a", b"a").unwrap_err().downcast().unwrap(),
+            ArchiveError::NeedsV2(_)
+        ));
+    }
+
+    #[test]
+    fn readers_are_sealed_in_pieces_like_whole_files() {
+        let key = [3u8; crate::integrity::KEY_SIZE];
+        let large: Vec<u8> = (0..2 * PIECE_SIZE + 5000)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let options = SealOptions {
+            mac_key: Some(&key),
+            ..Default::default()
+        };
+        let pack = |from_reader: bool| {
+            let mut writer = ArchiveWriter::streaming(Vec::new(), options).solid(1024);
+            for (path, data) in [("large.bin", &large[..]), ("small.txt", b"small")] {
+                match from_reader {
+                    true => writer.add_reader(path, data, Metadata::default()),
+                    false => writer.add_file(path, data, Metadata::default()),
+                }
+                .unwrap();
+            }
+            writer.finish().unwrap()
+        };
+        let data = pack(true);
+        assert_eq!(data, pack(false));
+
+        let mut archive = ArchiveReader::new(Cursor::new(data)).unwrap();
+        let entry = archive.entries()[0].clone();
+        let options = OpenOptions {
+            mac_key: Some(&key),
+            ..Default::default()
+        };
+        assert_eq!(archive.read(&entry, &options).unwrap(), large);
+    }
+
+    #[test]
+    fn cached_solid_blocks_are_only_reused_with_the_same_options() {
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default()).solid(1024);
+        writer.add("a.txt", b"a").unwrap();
+        writer.add("b.txt", b"b").unwrap();
+        let mut archive = ArchiveReader::new(Cursor::new(writer.finish().unwrap())).unwrap();
+        let entries = archive.entries().to_vec();
+        assert_eq!(entries[0].offset, entries[1].offset);
+
+        let plain = OpenOptions::default();
+        assert_eq!(archive.read(&entries[0], &plain).unwrap(), b"a");
+        let wrong = [9u8; crate::integrity::KEY_SIZE];
+        let keyed = OpenOptions {
+            mac_key: Some(&wrong),
+            ..Default::default()
+        };
+        assert!(archive.read(&entries[1], &keyed).is_err());
+        assert_eq!(archive.read(&entries[1], &plain).unwrap(), b"b");
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn cached_encrypted_blocks_are_opened_again_with_another_secret() {
+        use crate::crypto::Secret;
+        let secret = Secret::raw([1; 32]);
+        let options = SealOptions {
+            encryption: Some(&secret),
+            ..Default::default()
+        };
+        let mut writer = ArchiveWriter::new(Vec::new(), options).solid(1024);
+        writer.add("a.txt", b"a").unwrap();
+        writer.add("b.txt", b"b").unwrap();
+        let mut archive = ArchiveReader::new(Cursor::new(writer.finish().unwrap())).unwrap();
+        let entries = archive.entries().to_vec();
+
+        let with = |secret| OpenOptions {
+            decryption: Some(secret),
+            ..Default::default()
+        };
+        assert_eq!(archive.read(&entries[0], &with(&secret)).unwrap(), b"a");
+        let wrong = Secret::raw([2; 32]);
+        assert!(archive.read(&entries[1], &with(&wrong)).is_err());
+        let same = Secret::raw([1; 32]);
+        assert_eq!(archive.read(&entries[1], &with(&same)).unwrap(), b"b");
+    }
+
+    #[test]
+    fn copied_blocks_mix_with_the_block_being_filled() {
+        let mut source = ArchiveWriter::new(Vec::new(), SealOptions::default()).solid(1024);
+        source.add("x", b"copied x").unwrap();
+        source.add("y", b"copied y").unwrap();
+        let mut source = ArchiveReader::new(Cursor::new(source.finish().unwrap())).unwrap();
+        let (x, y) = (source.entries()[0].clone(), source.entries()[1].clone());
+        let mut block = Vec::new();
+        source.raw(&x).unwrap().read_to_end(&mut block).unwrap();
+
+        for streamed in [false, true] {
+            let mut writer = match streamed {
+                false => ArchiveWriter::new(Vec::new(), SealOptions::default()),
+                true => ArchiveWriter::streaming(Vec::new(), SealOptions::default()),
+            }
+            .solid(1024);
+            writer.add_raw(x.clone(), block.clone()).unwrap();
+            writer.add("small", b"in a new block").unwrap();
+            writer.add_raw(y.clone(), Vec::new()).unwrap();
+            let mut archive = ArchiveReader::new(Cursor::new(writer.finish().unwrap())).unwrap();
+            for (entry, data) in archive.entries().to_vec().iter().zip([
+                &b"copied x"[..],
+                b"in a new block",
+                b"copied y",
+            ]) {
+                assert_eq!(
+                    archive.read(entry, &OpenOptions::default()).unwrap(),
+                    data,
+                    "{}",
+                    entry.path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn block_slices_are_checked() {
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default()).solid(64);
+        writer.add("a", b"alpha").unwrap();
+        let data = writer.finish().unwrap();
+        let mut archive = ArchiveReader::new(Cursor::new(data)).unwrap();
+        let mut entry = archive.entries()[0].clone();
+        entry.block = Some(BlockSlice {
+            offset: 3,
+            len: u64::MAX,
+        });
+        assert!(archive.read(&entry, &OpenOptions::default()).is_err());
+
+        // A block slice on a directory.
+        let mut index = Vec::new();
+        index.extend_from_slice(&[KIND_DIRECTORY, IN_BLOCK, 1, 0, b'd']);
+        assert!(matches!(
+            read_v2_entry(&mut index.as_slice()),
+            Err(ArchiveError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn paths_are_normalized_or_rejected() {
+        for (path, normalized) in [
+            ("a/b", "a/b"),
+            ("./a//b/", "a/b"),
+            ("dir\\file.txt", "dir/file.txt"),
+            ("..data", "..data"),
+        ] {
+            assert_eq!(sanitize_path(path).unwrap(), normalized);
+        }
+        for path in [
+            "",
+            ".",
+            "../x",
+            "a/../../x",
+            "a\\..\\x",
+            "/etc/passwd",
+            "\\x",
+            "C:x",
+            "a\0b",
+        ] {
+            assert!(
+                matches!(sanitize_path(path), Err(ArchiveError::UnsafePath { .. })),
+                "{path:?}"
+            );
+        }
+        let none = HashSet::new();
+        assert_eq!(unsafe_target("a/link", "../b/c", &none), None);
+        assert!(unsafe_target("a/link", "../../b", &none).is_some());
+        assert!(unsafe_target("link", "b/../../c", &none).is_some());
+        assert!(unsafe_target("link", "/etc", &none).is_some());
+        let links = HashSet::from(["s".to_owned(), "a/t".to_owned()]);
+        assert!(unsafe_target("l", "s/..", &links).is_some());
+        assert!(unsafe_target("a/l", "../s/x", &links).is_some());
+        assert_eq!(unsafe_target("a/l", "t", &links), None);
+        assert!(unsafe_target("l", "a/t/../s", &links).is_some());
+    }
+
+    #[test]
+    fn writer_rejects_unsafe_and_duplicate_paths() {
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default());
+        writer.add("./docs/a.txt", b"a").unwrap();
+        for path in ["../a.txt", "/a.txt", "docs//a.txt"] {
+            assert!(writer.add(path, b"b").is_err(), "{path:?}");
+        }
+        assert_eq!(writer.len(), 1);
+        let archive = ArchiveReader::new(Cursor::new(writer.finish().unwrap())).unwrap();
+        assert_eq!(archive.entries()[0].path, "docs/a.txt");
+        assert_eq!(archive.safe_paths().unwrap(), ["docs/a.txt"]);
+    }
+
+    #[test]
+    fn malicious_archives_fail_path_checks() {
+        let fixtures: [(&[u8], &str); 5] = [
+            (
+                include_bytes!("../testdata/malicious/traversal.bsa"),
+                "../evil.txt",
+            ),
+            (
+                include_bytes!("../testdata/malicious/absolute.bsa"),
+                "/tmp/bstseal-evil.txt",
+            ),
+            (
+                include_bytes!("../testdata/malicious/duplicate.bsa"),
+                "a.txt",
+            ),
+            (
+                include_bytes!("../testdata/malicious/symlink-escape.bsa"),
+                "link",
+            ),
+            (
+                include_bytes!("../testdata/malicious/symlink-chain.bsa"),
+                "l",
+            ),
+        ];
+        for (data, bad_path) in fixtures {
+            // Listing and reading single entries still works.
+            let mut archive = ArchiveReader::new(Cursor::new(data)).unwrap();
+            let entry = archive.entries().last().unwrap().clone();
+            assert_eq!(
+                archive.read(&entry, &OpenOptions::default()).unwrap(),
+                b"hello world\n"
+            );
+            match archive.safe_paths() {
+                Err(ArchiveError::UnsafePath { path, .. } | ArchiveError::DuplicatePath(path)) => {
+                    assert_eq!(path, bad_path)
+                }
+                other => panic!("{bad_path}: {other:?}"),
+            }
+        }
+    }
+}
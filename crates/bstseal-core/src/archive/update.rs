@@ -0,0 +1,411 @@
+//! Changing archive files in place.
+//!
+//! [`ArchiveWriter::open`] keeps the existing data and appends new frames,
+//! a new index and its tail, which makes the file a streamed archive
+//! ([`Layout::Trailing`](super::Layout::Trailing)). Before the first byte
+//! changes, `<archive>.journal` records the old length and magic and is
+//! synced; it is removed once the new tail is on disk. An interrupted change
+//! is rolled back by truncating to the old length and restoring the magic:
+//! when the writer is dropped without `finish`, or by [`recover`] after a
+//! crash. Removed and replaced entries leave dead space until [`compact`].
+
+use super::{ArchiveReader, ArchiveWriter, Data, MAGIC_STREAMED};
+use crate::seal::SealOptions;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_MAGIC: &[u8; 8] = b"BSTSJNL\x01";
+/// Magic, old length and old archive magic.
+const JOURNAL_SIZE: usize = 24;
+
+impl<'a> ArchiveWriter<'a, File> {
+    /// Opens the archive at `path` to add, remove (see
+    /// [`remove`](Self::remove)) and replace entries in place. The file
+    /// stays locked until the writer is dropped. Readers that call
+    /// [`recover`] first wait for the lock and see the old archive until
+    /// [`finish`](Self::finish) returns, also if the process dies first;
+    /// others may find a half-written tail and fail with
+    /// [`ArchiveError::Malformed`](super::ArchiveError::Malformed). A
+    /// signature on the archive does not verify afterwards.
+    pub fn open(path: &Path, options: SealOptions<'a>) -> Result<Self> {
+        let mut file = open_locked(path)?;
+        recover_locked(path, &file)?;
+        let entries = ArchiveReader::new(BufReader::new(&mut file))?.entries;
+        let mut magic = [0u8; 8];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut magic)?;
+        let len = file.seek(SeekFrom::End(0))?;
+        let journal = Journal::begin(path, &file, len, magic)?;
+
+        let mut writer = Self::streaming(file, options);
+        writer.written = len;
+        writer.paths = entries.iter().map(|entry| entry.path.clone()).collect();
+        writer.entries = entries
+            .into_iter()
+            .map(|entry| (entry, Data::Frame(Vec::new())))
+            .collect();
+        writer.journal = Some(journal);
+        Ok(writer)
+    }
+}
+
+/// Rollback record of an archive being changed in place.
+pub(super) struct Journal {
+    path: PathBuf,
+    archive: File,
+    len: u64,
+    magic: [u8; 8],
+    committed: bool,
+}
+
+impl Journal {
+    fn begin(archive_path: &Path, archive: &File, len: u64, magic: [u8; 8]) -> io::Result<Self> {
+        let path = journal_path(archive_path);
+        let mut record = JOURNAL_MAGIC.to_vec();
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&magic);
+        let mut file = File::create(&path)?;
+        file.write_all(&record)?;
+        file.sync_all()?;
+        sync_dir(&path)?;
+        Ok(Self {
+            path,
+            archive: archive.try_clone()?,
+            len,
+            magic,
+            committed: false,
+        })
+    }
+
+    /// Makes the appended index current: syncs it, switches the magic to
+    /// the streamed layout and drops the journal.
+    pub(super) fn commit(mut self) -> io::Result<()> {
+        self.archive.sync_all()?;
+        if &self.magic != MAGIC_STREAMED {
+            self.archive.seek(SeekFrom::Start(0))?;
+            self.archive.write_all(MAGIC_STREAMED)?;
+            self.archive.sync_all()?;
+        }
+        self.committed = true;
+        fs::remove_file(&self.path)?;
+        sync_dir(&self.path)
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        // Failing here leaves the journal for `recover`.
+        if !self.committed && rollback(&self.archive, self.len, &self.magic).is_ok() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Rolls back a change to the archive at `path` that was interrupted, if
+/// there is one. Returns whether a journal was found. Safe to call while
+/// another process changes the archive; it waits for that to finish.
+pub fn recover(path: &Path) -> io::Result<bool> {
+    if !journal_path(path).exists() {
+        return Ok(false);
+    }
+    let file = open_locked(path)?;
+    recover_locked(path, &file)
+}
+
+/// Opens and locks the archive at `path`. [`compact`] replaces the file
+/// while holding the lock on the old one, so a lock taken after waiting is
+/// checked against the file now at `path` and taken again if that changed.
+fn open_locked(path: &Path) -> io::Result<File> {
+    loop {
+        let file = File::options().read(true).write(true).open(path)?;
+        file.lock()?;
+        if is_current(path, &file)? {
+            return Ok(file);
+        }
+    }
+}
+
+/// Whether `file` is still the one at `path`.
+#[cfg(unix)]
+fn is_current(path: &Path, file: &File) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (linked, opened) = (fs::metadata(path)?, file.metadata()?);
+    Ok((linked.dev(), linked.ino()) == (opened.dev(), opened.ino()))
+}
+
+/// Not checked without inode numbers.
+#[cfg(not(unix))]
+fn is_current(_path: &Path, _file: &File) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// [`recover`] with the archive already open and locked.
+fn recover_locked(path: &Path, archive: &File) -> io::Result<bool> {
+    let journal = journal_path(path);
+    let record = match fs::read(&journal) {
+        Ok(record) => record,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    // The archive is only touched once the journal is complete and synced.
+    if record.len() == JOURNAL_SIZE && record.starts_with(JOURNAL_MAGIC) {
+        let len = u64::from_le_bytes(record[8..16].try_into().unwrap());
+        let magic = record[16..24].try_into().unwrap();
+        rollback(archive, len, &magic)?;
+    }
+    fs::remove_file(&journal)?;
+    sync_dir(&journal)?;
+    Ok(true)
+}
+
+fn rollback(mut archive: &File, len: u64, magic: &[u8; 8]) -> io::Result<()> {
+    archive.set_len(len)?;
+    archive.seek(SeekFrom::Start(0))?;
+    archive.write_all(magic)?;
+    archive.sync_all()
+}
+
+/// Rewrites the archive at `path` without the space left by removed and
+/// replaced entries, as a streamed archive. Entries are copied as sealed,
+/// one at a time, into a temporary file that then replaces the archive.
+/// Solid blocks are copied whole, once, with any removed files still in
+/// them. Returns the sizes before and after.
+pub fn compact(path: &Path) -> Result<(u64, u64)> {
+    let file = open_locked(path)?;
+    recover_locked(path, &file)?;
+    let before = file.metadata()?.len();
+    let mut reader = ArchiveReader::new(BufReader::new(&file))?;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".compact");
+    let tmp = PathBuf::from(tmp);
+    let result = (|| -> Result<u64> {
+        let out = File::create(&tmp)?;
+        // The rename replaces the archive, so keep its permissions.
+        out.set_permissions(file.metadata()?.permissions())?;
+        let mut writer = ArchiveWriter::streaming(BufWriter::new(out), SealOptions::default());
+        let mut blocks = HashSet::new();
+        for entry in reader.entries().to_vec() {
+            let mut sealed = Vec::new();
+            let copied = entry.block.is_some() && !blocks.insert(entry.offset);
+            if entry.is_file() && !copied {
+                reader.raw(&entry)?.read_to_end(&mut sealed)?;
+            }
+            writer.add_raw(entry, sealed)?;
+        }
+        let out = writer.finish()?.into_inner().map_err(|e| e.into_error())?;
+        out.sync_all()?;
+        Ok(out.metadata()?.len())
+    })();
+    match result {
+        Ok(after) => {
+            fs::rename(&tmp, path)?;
+            sync_dir(path)?;
+            Ok((before, after))
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+fn journal_path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(".journal");
+    PathBuf::from(path)
+}
+
+/// Makes a created, removed or renamed entry in the parent of `path`
+/// durable.
+fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{Layout, Metadata};
+    use crate::seal::OpenOptions;
+    use std::io::Cursor;
+
+    fn contents(path: &Path) -> Vec<(String, Vec<u8>)> {
+        let mut archive = ArchiveReader::new(File::open(path).unwrap()).unwrap();
+        let mut files = Vec::new();
+        for entry in archive.entries().to_vec() {
+            let data = match entry.is_file() {
+                true => archive.read(&entry, &OpenOptions::default()).unwrap(),
+                false => Vec::new(),
+            };
+            files.push((entry.path, data));
+        }
+        files
+    }
+
+    fn pack(path: &Path, files: &[(&str, &[u8])]) {
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default());
+        for (name, data) in files {
+            writer.add(name, data).unwrap();
+        }
+        fs::write(path, writer.finish().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn entries_are_added_replaced_and_removed_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bsa");
+        pack(&path, &[("a.txt", b"alpha"), ("b.txt", b"beta")]);
+
+        let mut writer = ArchiveWriter::open(&path, SealOptions::default()).unwrap();
+        assert!(writer.add("a.txt", b"again").is_err());
+        writer.remove("a.txt").unwrap();
+        writer.add("a.txt", b"alpha 2").unwrap();
+        writer.add_dir("docs", Metadata::default()).unwrap();
+        writer.add("docs/c.txt", b"gamma").unwrap();
+        assert!(writer.remove("missing").is_err());
+        writer.finish().unwrap();
+        assert!(!journal_path(&path).exists());
+
+        let mut writer = ArchiveWriter::open(&path, SealOptions::default()).unwrap();
+        writer.remove("b.txt").unwrap();
+        writer.finish().unwrap();
+
+        let expected = [
+            ("a.txt".to_owned(), b"alpha 2".to_vec()),
+            ("docs".to_owned(), Vec::new()),
+            ("docs/c.txt".to_owned(), b"gamma".to_vec()),
+        ];
+        assert_eq!(contents(&path), expected);
+        let archive = ArchiveReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(archive.layout(), Layout::Trailing);
+
+        let (before, after) = compact(&path).unwrap();
+        assert!(after < before, "{before} -> {after}");
+        assert_eq!(contents(&path), expected);
+        assert_eq!(fs::metadata(&path).unwrap().len(), after);
+    }
+
+    #[test]
+    fn solid_blocks_survive_changes_and_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bsa");
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default()).solid(64);
+        for name in ["a.json", "b.json", "c.json"] {
+            writer.add(name, name.as_bytes()).unwrap();
+        }
+        fs::write(&path, writer.finish().unwrap()).unwrap();
+
+        let mut writer = ArchiveWriter::open(&path, SealOptions::default())
+            .unwrap()
+            .solid(64);
+        writer.remove("b.json").unwrap();
+        writer.add("d.json", b"d.json").unwrap();
+        writer.finish().unwrap();
+
+        let expected = [
+            ("a.json".to_owned(), b"a.json".to_vec()),
+            ("c.json".to_owned(), b"c.json".to_vec()),
+            ("d.json".to_owned(), b"d.json".to_vec()),
+        ];
+        assert_eq!(contents(&path), expected);
+        compact(&path).unwrap();
+        assert_eq!(contents(&path), expected);
+        // The shared block is copied once.
+        let archive = ArchiveReader::new(File::open(&path).unwrap()).unwrap();
+        let offsets: Vec<u64> = archive.entries().iter().map(|e| e.offset).collect();
+        assert_eq!(offsets[0], offsets[1]);
+        assert_ne!(offsets[1], offsets[2]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn compaction_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bsa");
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default());
+        writer.add("a.txt", b"a").unwrap();
+        fs::write(&path, writer.finish().unwrap()).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        compact(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn unfinished_changes_are_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bsa");
+        pack(&path, &[("a.txt", b"alpha")]);
+        let original = fs::read(&path).unwrap();
+
+        // Dropped without finish.
+        let mut writer = ArchiveWriter::open(&path, SealOptions::default()).unwrap();
+        writer.add("b.txt", b"beta").unwrap();
+        drop(writer);
+        assert_eq!(fs::read(&path).unwrap(), original);
+        assert!(!recover(&path).unwrap());
+
+        // A crash after appending, simulated by leaving the journal and
+        // the appended bytes behind.
+        let mut writer = ArchiveWriter::open(&path, SealOptions::default()).unwrap();
+        writer.add("b.txt", b"beta").unwrap();
+        let journal = fs::read(journal_path(&path)).unwrap();
+        let crashed = fs::read(&path).unwrap();
+        drop(writer);
+        fs::write(&path, &crashed).unwrap();
+        fs::write(journal_path(&path), journal).unwrap();
+        assert!(crashed.len() > original.len());
+        assert!(recover(&path).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), original);
+        assert!(ArchiveReader::new(Cursor::new(original)).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writers_waiting_during_compaction_change_the_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bsa");
+        pack(&path, &[("a.txt", b"alpha")]);
+
+        // Hold the lock like `compact` does while it replaces the file.
+        let old = File::open(&path).unwrap();
+        old.lock().unwrap();
+        let waiting = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let mut writer = ArchiveWriter::open(&path, SealOptions::default()).unwrap();
+                writer.add("b.txt", b"beta").unwrap();
+                writer.finish().unwrap();
+            }
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let replacement = dir.path().join("data.bsa.compact");
+        pack(&replacement, &[("a.txt", b"alpha")]);
+        fs::rename(&replacement, &path).unwrap();
+        drop(old);
+        waiting.join().unwrap();
+
+        assert_eq!(
+            contents(&path),
+            [
+                ("a.txt".to_owned(), b"alpha".to_vec()),
+                ("b.txt".to_owned(), b"beta".to_vec())
+            ]
+        );
+    }
+}
@@ -1,8 +1,10 @@
 // src/block_coder.rs
 //! Dispatches between different block-level compression algorithms.
 
+use crate::limits::{DecodeError, DecodeLimits};
 use crate::{huff, raw};
-use anyhow::{anyhow, Result};
+use alloc::vec::Vec;
+use anyhow::Result;
 
 pub const BLOCK_SIZE: usize = 4096;
 
@@ -20,7 +22,7 @@ impl TryFrom<u8> for BlockType {
         match value {
             0 => Ok(BlockType::Raw),
             1 => Ok(BlockType::Huffman),
-            _ => Err(anyhow!("Unknown block type: {}", value)),
+            _ => Err(DecodeError::UnknownBlockType(value).into()),
         }
     }
 }
@@ -53,7 +55,7 @@ pub fn encode_block(input: &[u8]) -> Result<Vec<u8>> {
         final_block.push(BlockType::Huffman as u8);
 
         // Добавляем размер исходных данных как VarInt
-        crate::utils::write_varint_u64(&mut final_block, input.len() as u64)?;
+        crate::utils::push_varint_u64(&mut final_block, input.len() as u64);
 
         final_block.extend_from_slice(&huff_encoded);
         Ok(final_block)
@@ -70,23 +72,24 @@ pub fn encode_block(input: &[u8]) -> Result<Vec<u8>> {
 /// Decodes a single block of data.
 ///
 /// It reads a `BlockType` byte to determine the encoding format (Huffman or raw)
-/// and dispatches to the appropriate decoder.
+/// and dispatches to the appropriate decoder. Uses [`DecodeLimits::default`].
 pub fn decode_block(input: &[u8]) -> Result<Vec<u8>> {
-    if input.is_empty() {
-        return Err(anyhow!("Input to decode_block cannot be empty."));
-    }
+    decode_block_with_limits(input, &DecodeLimits::default())
+}
 
-    let block_type = BlockType::try_from(input[0])?;
+/// Decodes a single block, rejecting blocks whose decoded size exceeds
+/// `limits.max_block_size` before any memory is reserved for them.
+pub fn decode_block_with_limits(input: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+    let expected_size = declared_size(input, limits)?;
     let payload = &input[1..];
 
-    match block_type {
+    match BlockType::try_from(input[0])? {
         BlockType::Raw => raw::decode(payload),
         BlockType::Huffman => {
-            // Извлекаем размер из VarInt в начале данных
-            let (expected_size, bytes_read) = crate::utils::read_varint_u64(payload)
-                .ok_or_else(|| anyhow!("Failed to read varint for expected size"))?;
-
-            let mut out = Vec::with_capacity(expected_size as usize);
+            // Размер уже проверен; пропускаем VarInt перед Huffman-потоком
+            let (_, bytes_read) =
+                crate::utils::read_varint_u64(payload).ok_or(DecodeError::BadVarint)?;
+            let mut out = Vec::new();
             huff::decode(
                 &payload[bytes_read..],
                 &mut out,
@@ -97,9 +100,50 @@ pub fn decode_block(input: &[u8]) -> Result<Vec<u8>> {
     }
 }
 
+/// Returns the decoded size announced by an encoded block without decoding
+/// it, after checking it against `limits`.
+pub(crate) fn declared_size(input: &[u8], limits: &DecodeLimits) -> Result<u64> {
+    if input.is_empty() {
+        return Err(DecodeError::EmptyBlock.into());
+    }
+    let payload = &input[1..];
+    let size = match BlockType::try_from(input[0])? {
+        BlockType::Raw => payload.len() as u64,
+        BlockType::Huffman => {
+            crate::utils::read_varint_u64(payload)
+                .ok_or(DecodeError::BadVarint)?
+                .0
+        }
+    };
+    check_block_size(size, limits)?;
+    Ok(size)
+}
+
+/// Largest encoded size a block can have and still decode within `limits`:
+/// type byte, size varint, a full code table and 15-bit codes for every byte.
+pub(crate) fn max_encoded_len(limits: &DecodeLimits) -> u64 {
+    1 + 10 + 513 + (limits.max_block_size as u64 * 15).div_ceil(8)
+}
+
+fn check_block_size(size: u64, limits: &DecodeLimits) -> Result<(), DecodeError> {
+    if size > limits.max_output_size as u64 {
+        return Err(DecodeError::OutputTooLarge {
+            limit: limits.max_output_size,
+        });
+    }
+    if size > limits.max_block_size as u64 {
+        return Err(DecodeError::BlockTooLarge {
+            size,
+            limit: limits.max_block_size,
+        });
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_encode_decode_roundtrip_compressible() {
@@ -141,4 +185,183 @@ mod tests {
         // The logic should fall back to raw.
         assert_eq!(encoded[0], BlockType::Raw as u8);
     }
+
+    fn decode_err(input: &[u8]) -> DecodeError {
+        decode_block(input)
+            .unwrap_err()
+            .downcast_ref::<DecodeError>()
+            .cloned()
+            .expect("typed decode error")
+    }
+
+    /// 33 one-bit codes: the last byte carries 7 padding bits.
+    fn padded_huffman_block() -> Vec<u8> {
+        let mut data = b"ab".repeat(16);
+        data.push(b'a');
+        let encoded = encode_block(&data).unwrap();
+        assert_eq!(encoded[0], BlockType::Huffman as u8);
+        encoded
+    }
+
+    #[test]
+    fn rejects_huge_declared_size() {
+        let mut input = vec![BlockType::Huffman as u8];
+        crate::utils::push_varint_u64(&mut input, u64::MAX >> 1);
+        input.extend_from_slice(&[1, b'a', 1]);
+        assert!(matches!(
+            decode_err(&input),
+            DecodeError::BlockTooLarge { .. }
+        ));
+    }
+
+    #[test]
+    fn rejects_block_over_limit() {
+        let data = vec![b'a'; 1000];
+        let encoded = encode_block(&data).unwrap();
+        let limits = DecodeLimits {
+            max_block_size: 999,
+            ..DecodeLimits::default()
+        };
+        let err = decode_block_with_limits(&encoded, &limits).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DecodeError>(),
+            Some(&DecodeError::BlockTooLarge {
+                size: 1000,
+                limit: 999
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_bitstream() {
+        let mut encoded = padded_huffman_block();
+        encoded.pop();
+        assert_eq!(
+            decode_err(&encoded),
+            DecodeError::Truncated {
+                decoded: 32,
+                expected: 33
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_nonzero_padding() {
+        let mut encoded = padded_huffman_block();
+        *encoded.last_mut().unwrap() |= 1;
+        assert_eq!(decode_err(&encoded), DecodeError::NonZeroPadding);
+    }
+
+    #[test]
+    fn rejects_trailing_data() {
+        let mut encoded = padded_huffman_block();
+        encoded.push(0);
+        assert_eq!(decode_err(&encoded), DecodeError::TrailingData(1));
+    }
+
+    #[test]
+    fn rejects_unknown_block_type() {
+        assert_eq!(decode_err(&[7, 0]), DecodeError::UnknownBlockType(7));
+        assert_eq!(decode_err(&[]), DecodeError::EmptyBlock);
+    }
+
+    fn skewed_bytes() -> impl Strategy<Value = Vec<u8>> {
+        // Few distinct symbols: Huffman always wins.
+        prop::collection::vec(prop::sample::select(b"aaaabbc".to_vec()), 64..BLOCK_SIZE)
+    }
+
+    fn wide_skewed_bytes() -> impl Strategy<Value = Vec<u8>> {
+        // Every symbol present, one dominant: exercises 256-entry tables.
+        prop::collection::vec(any::<u8>(), 0..64).prop_map(|tail| {
+            let mut data = vec![0u8; 3000];
+            data.extend(0..=255u8);
+            data.extend(tail);
+            data
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_roundtrip_any(data in prop::collection::vec(any::<u8>(), 0..BLOCK_SIZE)) {
+            let encoded = encode_block(&data).unwrap();
+            prop_assert_eq!(decode_block(&encoded).unwrap(), data);
+        }
+
+        #[test]
+        fn prop_roundtrip_raw(data in prop::collection::vec(any::<u8>(), 1024..BLOCK_SIZE)) {
+            // Uniform random data of this size is never worth a Huffman table.
+            let mut data = data;
+            data.extend(0..=255u8);
+            let encoded = encode_block(&data).unwrap();
+            prop_assert_eq!(encoded[0], BlockType::Raw as u8);
+            prop_assert_eq!(decode_block(&encoded).unwrap(), data);
+        }
+
+        #[test]
+        fn prop_roundtrip_huffman(data in skewed_bytes()) {
+            let encoded = encode_block(&data).unwrap();
+            prop_assert_eq!(encoded[0], BlockType::Huffman as u8);
+            prop_assert_eq!(decode_block(&encoded).unwrap(), data);
+        }
+
+        #[test]
+        fn prop_roundtrip_huffman_single_symbol(byte in any::<u8>(), len in 16usize..BLOCK_SIZE) {
+            let data = vec![byte; len];
+            let encoded = encode_block(&data).unwrap();
+            prop_assert_eq!(encoded[0], BlockType::Huffman as u8);
+            prop_assert_eq!(decode_block(&encoded).unwrap(), data);
+        }
+
+        #[test]
+        fn prop_roundtrip_huffman_all_symbols(data in wide_skewed_bytes()) {
+            let encoded = encode_block(&data).unwrap();
+            prop_assert_eq!(encoded[0], BlockType::Huffman as u8);
+            prop_assert_eq!(decode_block(&encoded).unwrap(), data);
+        }
+
+        #[test]
+        fn prop_decode_never_panics(input in prop::collection::vec(any::<u8>(), 0..512)) {
+            let _ = decode_block(&input);
+        }
+
+        #[test]
+        fn prop_corrupted_huffman_block_never_panics(
+            data in skewed_bytes(),
+            flip in any::<prop::sample::Index>(),
+            bit in 0u8..8,
+        ) {
+            let mut encoded = encode_block(&data).unwrap();
+            // Keep the type byte: a flipped type turns the block into raw.
+            let i = 1 + flip.index(encoded.len() - 1);
+            encoded[i] ^= 1 << bit;
+            if let Ok(decoded) = decode_block(&encoded) {
+                // A flip may still decode, but never to a length other than
+                // the one the (possibly flipped) header declares.
+                let declared = declared_size(&encoded, &DecodeLimits::default()).unwrap();
+                prop_assert_eq!(decoded.len() as u64, declared);
+            }
+        }
+    }
+
+    // Regression inputs for decoder bugs; add new findings from `fuzz/` here.
+
+    #[test]
+    fn regression_huffman_count_without_table() {
+        // Huffman block that declares 1 byte but carries an empty table.
+        assert!(decode_block(&[1, 1, 0]).is_err());
+    }
+
+    #[test]
+    fn regression_single_symbol_code_with_set_bits() {
+        // One-symbol code ("0"); a set bit has no matching code.
+        assert_eq!(
+            decode_err(&[1, 2, 1, b'a', 1, 0x80]),
+            DecodeError::Truncated {
+                decoded: 0,
+                expected: 2
+            }
+        );
+    }
 }
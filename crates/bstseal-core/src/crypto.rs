@@ -0,0 +1,395 @@
+//! Authenticated encryption of compressed blocks with XChaCha20-Poly1305.
+//!
+//! Every block is sealed on its own with nonce `prefix || index` (index as
+//! `u64` little-endian), so blocks can be decrypted in parallel or one at a
+//! time. The associated data of a block is a single byte that is `1` for the
+//! last block and `0` otherwise, which makes truncation detectable; the index
+//! in the nonce makes reordering detectable.
+//!
+//! The header stores a key check value: the tag of an empty message under
+//! nonce `prefix || u64::MAX`, with the header bytes before it as associated
+//! data. A wrong key or password fails there with [`CryptoError::WrongKey`]
+//! instead of surfacing as a corrupted block.
+
+use crate::frame::{EncryptionParams, Kdf, KEY_CHECK_SIZE, NONCE_PREFIX_SIZE, SALT_SIZE};
+use crate::limits::DecodeError;
+use crate::utils;
+use anyhow::Result;
+use argon2::{Algorithm, Argon2, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::fmt;
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// Size of an encryption key.
+pub const KEY_SIZE: usize = 32;
+/// Size of the Poly1305 tag appended to every block.
+pub const TAG_SIZE: usize = 16;
+
+/// Largest Argon2id memory cost (KiB) accepted from a frame header (2 GiB).
+pub const MAX_ARGON2_M_COST: u32 = 2 << 20;
+/// Largest Argon2id pass count accepted from a frame header.
+pub const MAX_ARGON2_T_COST: u32 = 64;
+/// Largest Argon2id parallelism accepted from a frame header.
+pub const MAX_ARGON2_P_COST: u32 = 64;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum CryptoError {
+    #[error("wrong key or password (or corrupted encryption header)")]
+    WrongKey,
+    #[error("encrypted block {index} failed authentication")]
+    BlockAuthentication { index: u64 },
+    #[error("encrypted payload has no blocks")]
+    Truncated,
+    #[error("frame is encrypted; a key or password is required")]
+    SecretRequired,
+    #[error("frame is encrypted with a password, not a raw key")]
+    PasswordRequired,
+    #[error("frame is encrypted with a raw key, not a password")]
+    RawKeyRequired,
+    #[error("frame is not encrypted")]
+    NotEncrypted,
+    #[error("Argon2id parameters in header exceed limits")]
+    KdfParamsTooLarge,
+    #[error("key derivation failed: {0}")]
+    Kdf(argon2::Error),
+    #[error("system random number generator failed")]
+    Rng,
+}
+
+/// Argon2id cost parameters used when sealing with a password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// Memory cost in KiB.
+    pub m_cost: u32,
+    /// Number of passes.
+    pub t_cost: u32,
+    /// Degree of parallelism.
+    pub p_cost: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            m_cost: argon2::Params::DEFAULT_M_COST,
+            t_cost: argon2::Params::DEFAULT_T_COST,
+            p_cost: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+enum SecretKind {
+    Raw([u8; KEY_SIZE]),
+    Password {
+        password: Vec<u8>,
+        params: Argon2Params,
+    },
+}
+
+/// Key material for encrypting or decrypting frames: a raw key or a
+/// password.
+///
+/// The last derived key is cached, so sealing or opening many archive
+/// entries with one password runs Argon2id once.
+pub struct Secret {
+    kind: SecretKind,
+    derived: Mutex<Option<(Kdf, [u8; KEY_SIZE])>>,
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            SecretKind::Raw(_) => "raw key",
+            SecretKind::Password { .. } => "password",
+        };
+        f.debug_tuple("Secret").field(&kind).finish()
+    }
+}
+
+impl Secret {
+    /// Uses `key` directly as the XChaCha20-Poly1305 key.
+    pub fn raw(key: [u8; KEY_SIZE]) -> Self {
+        Self::new(SecretKind::Raw(key))
+    }
+
+    /// Derives keys from `password` with Argon2id and default parameters.
+    pub fn password(password: impl Into<Vec<u8>>) -> Self {
+        Self::new(SecretKind::Password {
+            password: password.into(),
+            params: Argon2Params::default(),
+        })
+    }
+
+    /// Sets the Argon2id parameters used when sealing. No effect on raw keys.
+    pub fn with_argon2_params(mut self, new: Argon2Params) -> Self {
+        if let SecretKind::Password { params, .. } = &mut self.kind {
+            *params = new;
+        }
+        self
+    }
+
+    fn new(kind: SecretKind) -> Self {
+        Self {
+            kind,
+            derived: Mutex::new(None),
+        }
+    }
+
+    /// Tells secrets that open frames differently apart without keeping a
+    /// copy of the key material. Argon2id parameters only matter for
+    /// sealing and are left out.
+    pub(crate) fn fingerprint(&self) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new_derive_key("bstseal 2024 secret fingerprint");
+        match &self.kind {
+            SecretKind::Raw(key) => hasher.update(b"raw").update(key),
+            SecretKind::Password { password, .. } => hasher.update(b"password").update(password),
+        };
+        *hasher.finalize().as_bytes()
+    }
+
+    /// Key and KDF description for a new frame.
+    fn key_for_sealing(&self) -> Result<(Kdf, [u8; KEY_SIZE]), CryptoError> {
+        let (password, params) = match &self.kind {
+            SecretKind::Raw(key) => return Ok((Kdf::Raw, *key)),
+            SecretKind::Password { password, params } => (password, params),
+        };
+        let mut cached = self.derived.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((
+            kdf @ Kdf::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+                ..
+            },
+            key,
+        )) = *cached
+        {
+            if (m_cost, t_cost, p_cost) == (params.m_cost, params.t_cost, params.p_cost) {
+                return Ok((kdf, key));
+            }
+        }
+        let mut salt = [0u8; SALT_SIZE];
+        getrandom::getrandom(&mut salt).map_err(|_| CryptoError::Rng)?;
+        let kdf = Kdf::Argon2id {
+            m_cost: params.m_cost,
+            t_cost: params.t_cost,
+            p_cost: params.p_cost,
+            salt,
+        };
+        let key = derive_key(password, &kdf)?;
+        *cached = Some((kdf, key));
+        Ok((kdf, key))
+    }
+
+    /// Key for a frame whose header says `kdf`.
+    fn key_for_opening(&self, kdf: &Kdf) -> Result<[u8; KEY_SIZE], CryptoError> {
+        match (&self.kind, kdf) {
+            (SecretKind::Raw(key), Kdf::Raw) => Ok(*key),
+            (SecretKind::Raw(_), Kdf::Argon2id { .. }) => Err(CryptoError::PasswordRequired),
+            (SecretKind::Password { .. }, Kdf::Raw) => Err(CryptoError::RawKeyRequired),
+            (SecretKind::Password { password, .. }, Kdf::Argon2id { .. }) => {
+                let mut cached = self.derived.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some((cached_kdf, key)) = *cached {
+                    if cached_kdf == *kdf {
+                        return Ok(key);
+                    }
+                }
+                let key = derive_key(password, kdf)?;
+                *cached = Some((*kdf, key));
+                Ok(key)
+            }
+        }
+    }
+}
+
+fn derive_key(password: &[u8], kdf: &Kdf) -> Result<[u8; KEY_SIZE], CryptoError> {
+    let Kdf::Argon2id {
+        m_cost,
+        t_cost,
+        p_cost,
+        salt,
+    } = kdf
+    else {
+        unreachable!("raw keys are not derived");
+    };
+    if *m_cost > MAX_ARGON2_M_COST || *t_cost > MAX_ARGON2_T_COST || *p_cost > MAX_ARGON2_P_COST {
+        return Err(CryptoError::KdfParamsTooLarge);
+    }
+    let params =
+        argon2::Params::new(*m_cost, *t_cost, *p_cost, Some(KEY_SIZE)).map_err(CryptoError::Kdf)?;
+    let mut key = [0u8; KEY_SIZE];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password, salt, &mut key)
+        .map_err(CryptoError::Kdf)?;
+    Ok(key)
+}
+
+/// Cipher for the blocks of one frame.
+pub(crate) struct BlockCipher {
+    aead: XChaCha20Poly1305,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+}
+
+impl BlockCipher {
+    /// Picks a fresh nonce prefix and key for a new frame. The returned
+    /// parameters still need their key check, see [`BlockCipher::key_check`].
+    pub(crate) fn for_sealing(secret: &Secret) -> Result<(EncryptionParams, Self), CryptoError> {
+        let (kdf, key) = secret.key_for_sealing()?;
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        getrandom::getrandom(&mut nonce_prefix).map_err(|_| CryptoError::Rng)?;
+        let params = EncryptionParams {
+            kdf,
+            nonce_prefix,
+            key_check: [0; KEY_CHECK_SIZE],
+        };
+        Ok((params, Self::new(&key, nonce_prefix)))
+    }
+
+    /// Cipher for an existing frame; `header_aad` is the header up to the
+    /// key check. Fails with [`CryptoError::WrongKey`] if the check fails.
+    pub(crate) fn for_opening(
+        secret: &Secret,
+        params: &EncryptionParams,
+        header_aad: &[u8],
+    ) -> Result<Self, CryptoError> {
+        let key = secret.key_for_opening(&params.kdf)?;
+        let cipher = Self::new(&key, params.nonce_prefix);
+        let payload = Payload {
+            msg: &params.key_check,
+            aad: header_aad,
+        };
+        cipher
+            .aead
+            .decrypt(&cipher.nonce(u64::MAX), payload)
+            .map_err(|_| CryptoError::WrongKey)?;
+        Ok(cipher)
+    }
+
+    fn new(key: &[u8; KEY_SIZE], nonce_prefix: [u8; NONCE_PREFIX_SIZE]) -> Self {
+        Self {
+            aead: XChaCha20Poly1305::new(key.into()),
+            nonce_prefix,
+        }
+    }
+
+    fn nonce(&self, index: u64) -> XNonce {
+        let mut nonce = XNonce::default();
+        nonce[..NONCE_PREFIX_SIZE].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_SIZE..].copy_from_slice(&index.to_le_bytes());
+        nonce
+    }
+
+    /// Key check value for a header whose bytes before the check are
+    /// `header_aad`.
+    pub(crate) fn key_check(&self, header_aad: &[u8]) -> [u8; KEY_CHECK_SIZE] {
+        let payload = Payload {
+            msg: &[],
+            aad: header_aad,
+        };
+        let tag = self
+            .aead
+            .encrypt(&self.nonce(u64::MAX), payload)
+            .expect("XChaCha20-Poly1305 encryption is infallible");
+        tag.try_into().expect("empty message yields a bare tag")
+    }
+
+    fn seal_block(&self, index: u64, last: bool, block: &[u8]) -> Vec<u8> {
+        let payload = Payload {
+            msg: block,
+            aad: &[last as u8],
+        };
+        self.aead
+            .encrypt(&self.nonce(index), payload)
+            .expect("XChaCha20-Poly1305 encryption is infallible")
+    }
+
+    pub(crate) fn open_block(
+        &self,
+        index: u64,
+        last: bool,
+        sealed: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let payload = Payload {
+            msg: sealed,
+            aad: &[last as u8],
+        };
+        self.aead
+            .decrypt(&self.nonce(index), payload)
+            .map_err(|_| CryptoError::BlockAuthentication { index })
+    }
+
+    /// Encrypts the blocks of an `encode_parallel` stream and appends them to
+    /// `out`, each prefixed with a varint length. An empty stream becomes a
+    /// single empty block, so truncation to nothing is detected as well.
+    pub(crate) fn encrypt_blocks(&self, compressed: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let mut blocks = split_blocks(compressed)?;
+        if blocks.is_empty() {
+            blocks.push(&[]);
+        }
+        let last = blocks.len() - 1;
+        #[cfg(feature = "parallel")]
+        let iter = blocks.par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let iter = blocks.iter();
+        let sealed: Vec<Vec<u8>> = iter
+            .enumerate()
+            .map(|(i, block)| self.seal_block(i as u64, i == last, block))
+            .collect();
+        for block in sealed {
+            utils::push_varint_u64(out, block.len() as u64);
+            out.extend_from_slice(&block);
+        }
+        Ok(())
+    }
+
+    /// Reverses [`BlockCipher::encrypt_blocks`], returning a stream for
+    /// `decode_parallel`.
+    pub(crate) fn decrypt_blocks(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let blocks = split_blocks(payload)?;
+        if blocks.is_empty() {
+            return Err(CryptoError::Truncated.into());
+        }
+        let last = blocks.len() - 1;
+        #[cfg(feature = "parallel")]
+        let iter = blocks.par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let iter = blocks.iter();
+        let opened = iter
+            .enumerate()
+            .map(|(i, block)| self.open_block(i as u64, i == last, block))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut out = Vec::with_capacity(payload.len());
+        for block in opened {
+            if block.is_empty() {
+                if last != 0 {
+                    return Err(DecodeError::EmptyBlock.into());
+                }
+                continue;
+            }
+            utils::push_varint_u64(&mut out, block.len() as u64);
+            out.extend_from_slice(&block);
+        }
+        Ok(out)
+    }
+}
+
+/// Splits a varint-length-prefixed block sequence.
+fn split_blocks(data: &[u8]) -> Result<Vec<&[u8]>, DecodeError> {
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (len, varint_len) =
+            utils::read_varint_u64(&data[pos..]).ok_or(DecodeError::BadVarint)?;
+        let start = pos + varint_len;
+        let remaining = data.len() - start;
+        if len > remaining as u64 {
+            return Err(DecodeError::IncompleteBlock { len, remaining });
+        }
+        pos = start + len as usize;
+        blocks.push(&data[start..pos]);
+    }
+    Ok(blocks)
+}
@@ -1,23 +1,31 @@
 use crate::block_coder::{self, BLOCK_SIZE};
+use crate::limits::{DecodeError, DecodeLimits};
 use crate::utils;
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+#[cfg(feature = "parallel")]
 use rayon::prelude::*;
 
 /// Encodes input data by splitting it into blocks and processing them in parallel.
 ///
-/// Each encoded block is prefixed with a varint indicating its size.
+/// Each encoded block is prefixed with a varint indicating its size. Without
+/// the `parallel` feature the blocks are encoded on the calling thread.
 pub fn encode_parallel(input: &[u8]) -> Result<Vec<u8>> {
-    // Ensure license is valid before proceeding (skip in unit tests)
-    #[cfg(not(test))]
-    crate::license::ensure_license_valid().map_err(|e| anyhow!(e))?;
+    let encoded = licensed(|| encode_blocks(input))??;
+    #[cfg(all(feature = "metering", not(any(test, fuzzing))))]
+    crate::license::usage::record(crate::license::usage::Operation::Encode, input.len() as u64);
+    Ok(encoded)
+}
+
+fn encode_blocks(input: &[u8]) -> Result<Vec<u8>> {
     if input.is_empty() {
         return Ok(Vec::new());
     }
 
-    let results: Vec<Result<Vec<u8>>> = input
-        .par_chunks(BLOCK_SIZE)
-        .map(block_coder::encode_block)
-        .collect();
+    #[cfg(feature = "parallel")]
+    let chunks = input.par_chunks(BLOCK_SIZE);
+    #[cfg(not(feature = "parallel"))]
+    let chunks = input.chunks(BLOCK_SIZE);
+    let results: Vec<Result<Vec<u8>>> = chunks.map(block_coder::encode_block).collect();
 
     let mut final_data = Vec::new();
     for result in results {
@@ -32,48 +40,132 @@ pub fn encode_parallel(input: &[u8]) -> Result<Vec<u8>> {
 /// Decodes data that was previously encoded with `encode_parallel`.
 ///
 /// It reads a sequence of blocks, each prefixed with a varint length header,
-/// and decodes them, reassembling the original data.
+/// and decodes them, reassembling the original data. Uses
+/// [`DecodeLimits::default`].
 pub fn decode_parallel(encoded_data: &[u8]) -> Result<Vec<u8>> {
-    #[cfg(not(test))]
-    crate::license::ensure_license_valid().map_err(|e| anyhow!(e))?;
+    decode_parallel_with_limits(encoded_data, &DecodeLimits::default())
+}
+
+/// Same as [`decode_parallel`], but enforces `limits` on every block and on
+/// the total output before decoding starts.
+pub fn decode_parallel_with_limits(encoded_data: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+    let decoded = licensed(|| decode_blocks(encoded_data, limits))??;
+    #[cfg(all(feature = "metering", not(any(test, fuzzing))))]
+    crate::license::usage::record(
+        crate::license::usage::Operation::Decode,
+        decoded.len() as u64,
+    );
+    Ok(decoded)
+}
+
+fn decode_blocks(encoded_data: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
     if encoded_data.is_empty() {
         return Ok(Vec::new());
     }
 
     // 1. Собираем границы всех блоков.
     let mut boundaries = Vec::<(usize, usize)>::new(); // (start, end)
+    let mut declared_total: u64 = 0;
     let mut pos = 0;
     while pos < encoded_data.len() {
-        let (block_len, varint_len) = utils::read_varint_u64(&encoded_data[pos..])
-            .ok_or_else(|| anyhow!("Failed to read block length varint"))?;
+        let (block_len, varint_len) =
+            utils::read_varint_u64(&encoded_data[pos..]).ok_or(DecodeError::BadVarint)?;
         let start = pos + varint_len;
+        let remaining = encoded_data.len() - start;
+        if block_len > remaining as u64 {
+            return Err(DecodeError::IncompleteBlock {
+                len: block_len,
+                remaining,
+            }
+            .into());
+        }
         let end = start + block_len as usize;
-        if end > encoded_data.len() {
-            return Err(anyhow!("Incomplete block data"));
+        declared_total += block_coder::declared_size(&encoded_data[start..end], limits)?;
+        if declared_total > limits.max_output_size as u64 {
+            return Err(DecodeError::OutputTooLarge {
+                limit: limits.max_output_size,
+            }
+            .into());
         }
         boundaries.push((start, end));
         pos = end;
     }
 
     // 2. Декодируем блоки параллельно. Сохраняем порядок с индексом.
-    let mut decoded_parts: Vec<(usize, Vec<u8>)> = boundaries
-        .par_iter()
+    #[cfg(feature = "parallel")]
+    let blocks = boundaries.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let blocks = boundaries.iter();
+    let mut decoded_parts: Vec<(usize, Vec<u8>)> = blocks
         .enumerate()
-        .map(|(idx, &(s, e))| Ok((idx, block_coder::decode_block(&encoded_data[s..e])?)))
+        .map(|(idx, &(s, e))| {
+            Ok((
+                idx,
+                block_coder::decode_block_with_limits(&encoded_data[s..e], limits)?,
+            ))
+        })
         .collect::<Result<Vec<_>>>()?;
 
     decoded_parts.sort_by_key(|&(idx, _)| idx);
-    let total_len: usize = decoded_parts.iter().map(|(_, v)| v.len()).sum();
-    let mut out = Vec::with_capacity(total_len);
+    let mut out = Vec::with_capacity(declared_total as usize);
     for (_, mut part) in decoded_parts {
         out.append(&mut part);
     }
     Ok(out)
 }
 
+/// Worker threads [`encode_parallel`] and [`decode_parallel`] use under the
+/// current license: rayon's global pool, capped by the tier. Always 1
+/// without the `parallel` feature.
+pub fn worker_threads() -> Result<usize> {
+    #[cfg(feature = "parallel")]
+    return licensed(rayon::current_num_threads);
+    #[cfg(not(feature = "parallel"))]
+    licensed(|| 1)
+}
+
+/// Ensures the license is valid, then runs `f` on no more worker threads than
+/// its tier allows. Skipped in unit tests and fuzzing, and without the
+/// `licensing` feature.
+fn licensed<T: Send>(f: impl FnOnce() -> T + Send) -> Result<T> {
+    #[cfg(all(feature = "licensing", feature = "parallel", not(any(test, fuzzing))))]
+    if let Some(pool) = thread_pool(crate::license::capabilities()?.max_threads)? {
+        return Ok(pool.install(f));
+    }
+    #[cfg(all(
+        feature = "licensing",
+        not(feature = "parallel"),
+        not(any(test, fuzzing))
+    ))]
+    crate::license::ensure_license_valid()?;
+    Ok(f())
+}
+
+/// Pool capped at `max_threads`, or `None` when the global pool is small
+/// enough already. Rebuilt when a reloaded license changes the cap.
+#[cfg(all(feature = "licensing", feature = "parallel", not(any(test, fuzzing))))]
+fn thread_pool(max_threads: Option<usize>) -> Result<Option<std::sync::Arc<rayon::ThreadPool>>> {
+    use std::sync::{Arc, Mutex};
+    static POOL: Mutex<Option<(usize, Arc<rayon::ThreadPool>)>> = Mutex::new(None);
+    let n = match max_threads {
+        Some(n) if n < rayon::current_num_threads() => n,
+        _ => return Ok(None),
+    };
+    let mut pool = POOL.lock().unwrap();
+    match &*pool {
+        Some((size, pool)) if *size == n => Ok(Some(pool.clone())),
+        _ => {
+            let built = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(n).build()?);
+            *pool = Some((n, built.clone()));
+            Ok(Some(built))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     fn run_roundtrip_test(original_data: &[u8]) {
         let encoded_data = encode_parallel(original_data).expect("Encoding failed");
@@ -115,4 +207,55 @@ mod tests {
         let data = vec![b'a'; BLOCK_SIZE * 2];
         run_roundtrip_test(&data);
     }
+
+    #[test]
+    fn test_parallel_decode_output_limit() {
+        let data = vec![b'a'; BLOCK_SIZE * 3];
+        let encoded = encode_parallel(&data).unwrap();
+        let limits = DecodeLimits {
+            max_output_size: BLOCK_SIZE * 2,
+            ..DecodeLimits::default()
+        };
+        let err = decode_parallel_with_limits(&encoded, &limits).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DecodeError>(),
+            Some(DecodeError::OutputTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parallel_decode_incomplete_block() {
+        let encoded = encode_parallel(b"hello world").unwrap();
+        let err = decode_parallel(&encoded[..encoded.len() - 1]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DecodeError>(),
+            Some(DecodeError::IncompleteBlock { .. })
+        ));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_parallel_roundtrip(
+            data in prop::collection::vec(prop::sample::select(b"abcdefgh\0\xff".to_vec()), 0..BLOCK_SIZE * 4)
+        ) {
+            let encoded = encode_parallel(&data).unwrap();
+            prop_assert_eq!(decode_parallel(&encoded).unwrap(), data);
+        }
+
+        #[test]
+        fn prop_parallel_decode_never_panics(input in prop::collection::vec(any::<u8>(), 0..1024)) {
+            let _ = decode_parallel(&input);
+        }
+    }
+
+    #[test]
+    fn regression_block_length_overflow() {
+        // Maximal varint block length must not overflow `start + len`.
+        let input = [
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0x00,
+        ];
+        assert!(decode_parallel(&input).is_err());
+    }
 }
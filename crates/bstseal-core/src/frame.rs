@@ -0,0 +1,477 @@
+//! Optional header in front of a sealed stream (`.bsc`).
+//!
+//! Legacy frames have no header and are just `payload || blake3(payload)`.
+//! A frame that starts with [`MAGIC`] carries a flags byte describing how the
+//! rest of the frame is protected:
+//!
+//! ```text
+//! +-------+-------+------------------+----------------------+-----------------+-------------------+---------+--------+
+//! | magic | flags | checksum id      | content hash         | block index     | encryption params | payload | footer |
+//! | BSCF  | 1 B   | if FLAG_CHECKSUM | if FLAG_CONTENT_HASH | if FLAG_INDEXED | if FLAG_ENCRYPTED | [...]   |        |
+//! +-------+-------+------------------+----------------------+-----------------+-------------------+---------+--------+
+//! ```
+//!
+//! [`peek`] reads the header (and so the content hash) without verifying or
+//! decoding anything.
+//!
+//! A valid legacy payload never starts with `BSCF`: it would announce a block
+//! of 66 bytes whose type byte is `S`, and no such block type exists.
+
+use crate::integrity::Checksum;
+use crate::limits::DecodeError;
+use crate::utils;
+use alloc::vec::Vec;
+use thiserror::Error;
+
+/// Marks a frame with a header.
+pub const MAGIC: [u8; 4] = *b"BSCF";
+
+/// The footer is `blake3::keyed_hash(key, header || payload)`.
+pub const FLAG_KEYED: u8 = 0x01;
+/// The payload is a sequence of XChaCha20-Poly1305 encrypted blocks and the
+/// header carries [`EncryptionParams`].
+pub const FLAG_ENCRYPTED: u8 = 0x02;
+
+/// The header carries a [`BlockIndex`] for random access.
+pub const FLAG_INDEXED: u8 = 0x04;
+
+/// The footer uses the [`Checksum`] named by the byte after the flags
+/// instead of Blake3. Not allowed together with [`FLAG_KEYED`].
+pub const FLAG_CHECKSUM: u8 = 0x08;
+
+/// The header carries `blake3` of the original (uncompressed) content,
+/// checked after decoding.
+pub const FLAG_CONTENT_HASH: u8 = 0x10;
+
+const KNOWN_FLAGS: u8 =
+    FLAG_KEYED | FLAG_ENCRYPTED | FLAG_INDEXED | FLAG_CHECKSUM | FLAG_CONTENT_HASH;
+
+/// Size of the content hash.
+pub const CONTENT_HASH_SIZE: usize = blake3::OUT_LEN;
+
+/// Size of the Argon2id salt.
+pub const SALT_SIZE: usize = 16;
+/// Size of the random nonce prefix; the block index fills the other 8 bytes
+/// of the 24-byte XChaCha20 nonce.
+pub const NONCE_PREFIX_SIZE: usize = 16;
+/// Size of the key check value (a Poly1305 tag).
+pub const KEY_CHECK_SIZE: usize = 16;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    #[error("frame header is truncated")]
+    Truncated,
+    #[error("frame header has unsupported flags {0:#04x}")]
+    UnknownFlags(u8),
+    #[error("unknown key derivation function {0}")]
+    UnknownKdf(u8),
+    #[error("unknown checksum algorithm {0}")]
+    UnknownChecksum(u8),
+    #[error("keyed frames can only use the Blake3 checksum")]
+    KeyedChecksum,
+}
+
+/// How the encryption key is obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    /// A 32-byte key supplied by the caller is used as is.
+    Raw,
+    /// The key is derived from a password with Argon2id.
+    Argon2id {
+        /// Memory cost in KiB.
+        m_cost: u32,
+        /// Number of passes.
+        t_cost: u32,
+        /// Degree of parallelism.
+        p_cost: u32,
+        salt: [u8; SALT_SIZE],
+    },
+}
+
+impl Kdf {
+    fn id(&self) -> u8 {
+        match self {
+            Kdf::Raw => 0,
+            Kdf::Argon2id { .. } => 1,
+        }
+    }
+}
+
+/// Encryption parameters stored in the header of an encrypted frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncryptionParams {
+    pub kdf: Kdf,
+    pub nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    /// Tag over the preceding header bytes; tells a wrong key apart from a
+    /// corrupted block. Always the last field of the header.
+    pub key_check: [u8; KEY_CHECK_SIZE],
+}
+
+/// Where each block record starts in the payload, so a decoded byte range
+/// can be mapped to the records that hold it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockIndex {
+    /// Total decoded size.
+    pub decoded_len: u64,
+    /// Decoded size of every block but the last.
+    pub block_size: u32,
+    /// Offset of each record (varint length + block) from the payload start.
+    pub offsets: Vec<u64>,
+}
+
+impl BlockIndex {
+    /// Indexes the varint-prefixed records of `payload`.
+    pub fn build(payload: &[u8], decoded_len: u64, block_size: u32) -> Result<Self, DecodeError> {
+        let mut offsets = Vec::new();
+        let mut pos = 0;
+        while pos < payload.len() {
+            let (len, varint_len) =
+                utils::read_varint_u64(&payload[pos..]).ok_or(DecodeError::BadVarint)?;
+            let remaining = payload.len() - pos - varint_len;
+            if len > remaining as u64 {
+                return Err(DecodeError::IncompleteBlock { len, remaining });
+            }
+            offsets.push(pos as u64);
+            pos += varint_len + len as usize;
+        }
+        Ok(Self {
+            decoded_len,
+            block_size,
+            offsets,
+        })
+    }
+
+    /// Byte range of record `i` in a payload of `payload_len` bytes.
+    pub fn record(&self, i: usize, payload_len: u64) -> Option<core::ops::Range<u64>> {
+        let start = *self.offsets.get(i)?;
+        let end = self.offsets.get(i + 1).copied().unwrap_or(payload_len);
+        (start <= end && end <= payload_len).then_some(start..end)
+    }
+}
+
+/// Parsed frame header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub flags: u8,
+    /// Present exactly when [`FLAG_CHECKSUM`] is set.
+    pub checksum: Option<Checksum>,
+    /// Present exactly when [`FLAG_CONTENT_HASH`] is set.
+    pub content_hash: Option<[u8; CONTENT_HASH_SIZE]>,
+    /// Present exactly when [`FLAG_INDEXED`] is set.
+    pub index: Option<BlockIndex>,
+    /// Present exactly when [`FLAG_ENCRYPTED`] is set.
+    pub encryption: Option<EncryptionParams>,
+}
+
+impl FrameHeader {
+    /// Serialized size of the header.
+    pub fn encoded_len(&self) -> usize {
+        let mut buf = Vec::new();
+        self.write(&mut buf);
+        buf.len()
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Algorithm of the footer that follows the payload.
+    pub fn checksum(&self) -> Checksum {
+        self.checksum.unwrap_or_default()
+    }
+
+    /// Appends the serialized header to `out`.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&MAGIC);
+        out.push(self.flags);
+        if let Some(checksum) = self.checksum {
+            out.push(checksum.id());
+        }
+        if let Some(hash) = &self.content_hash {
+            out.extend_from_slice(hash);
+        }
+        if let Some(index) = &self.index {
+            out.extend_from_slice(&index.decoded_len.to_le_bytes());
+            out.extend_from_slice(&index.block_size.to_le_bytes());
+            out.extend_from_slice(&(index.offsets.len() as u64).to_le_bytes());
+            for offset in &index.offsets {
+                out.extend_from_slice(&offset.to_le_bytes());
+            }
+        }
+        if let Some(enc) = &self.encryption {
+            out.push(enc.kdf.id());
+            if let Kdf::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+                salt,
+            } = &enc.kdf
+            {
+                out.extend_from_slice(&m_cost.to_le_bytes());
+                out.extend_from_slice(&t_cost.to_le_bytes());
+                out.extend_from_slice(&p_cost.to_le_bytes());
+                out.extend_from_slice(salt);
+            }
+            out.extend_from_slice(&enc.nonce_prefix);
+            out.extend_from_slice(&enc.key_check);
+        }
+    }
+
+    /// Parses the header at the start of `data`.
+    ///
+    /// Returns `Ok(None)` for a legacy frame without header, otherwise the
+    /// header and its length in bytes.
+    pub fn parse(data: &[u8]) -> Result<Option<(Self, usize)>, FrameError> {
+        if !data.starts_with(&MAGIC) {
+            return Ok(None);
+        }
+        let mut rest = &data[MAGIC.len()..];
+        let [flags] = take::<1>(&mut rest)?;
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(FrameError::UnknownFlags(flags));
+        }
+        let mut header = Self {
+            flags,
+            ..Self::default()
+        };
+        if header.has(FLAG_CHECKSUM) {
+            let [id] = take(&mut rest)?;
+            let checksum = Checksum::from_id(id).ok_or(FrameError::UnknownChecksum(id))?;
+            if header.has(FLAG_KEYED) && checksum != Checksum::Blake3 {
+                return Err(FrameError::KeyedChecksum);
+            }
+            header.checksum = Some(checksum);
+        }
+        if header.has(FLAG_CONTENT_HASH) {
+            header.content_hash = Some(take(&mut rest)?);
+        }
+        if header.has(FLAG_INDEXED) {
+            let decoded_len = u64::from_le_bytes(take(&mut rest)?);
+            let block_size = u32::from_le_bytes(take(&mut rest)?);
+            let count = u64::from_le_bytes(take(&mut rest)?);
+            // Checked before allocating: a hostile count cannot exceed the input.
+            if count > (rest.len() / 8) as u64 {
+                return Err(FrameError::Truncated);
+            }
+            let offsets = (0..count)
+                .map(|_| take(&mut rest).map(u64::from_le_bytes))
+                .collect::<Result<_, _>>()?;
+            header.index = Some(BlockIndex {
+                decoded_len,
+                block_size,
+                offsets,
+            });
+        }
+        if header.has(FLAG_ENCRYPTED) {
+            let kdf = match take::<1>(&mut rest)? {
+                [0] => Kdf::Raw,
+                [1] => Kdf::Argon2id {
+                    m_cost: u32::from_le_bytes(take(&mut rest)?),
+                    t_cost: u32::from_le_bytes(take(&mut rest)?),
+                    p_cost: u32::from_le_bytes(take(&mut rest)?),
+                    salt: take(&mut rest)?,
+                },
+                [id] => return Err(FrameError::UnknownKdf(id)),
+            };
+            header.encryption = Some(EncryptionParams {
+                kdf,
+                nonce_prefix: take(&mut rest)?,
+                key_check: take(&mut rest)?,
+            });
+        }
+        Ok(Some((header, data.len() - rest.len())))
+    }
+}
+
+/// What [`peek`] learns from a frame without verifying or decoding it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// `None` for legacy frames.
+    pub header: Option<FrameHeader>,
+    /// Size of the (possibly encrypted) compressed payload.
+    pub payload_len: usize,
+    /// A signature trailer follows the footer.
+    pub signed: bool,
+}
+
+impl FrameInfo {
+    pub fn checksum(&self) -> Checksum {
+        self.header
+            .as_ref()
+            .map_or(Checksum::Blake3, FrameHeader::checksum)
+    }
+
+    /// Blake3 of the original content, if the frame stores it.
+    pub fn content_hash(&self) -> Option<[u8; CONTENT_HASH_SIZE]> {
+        self.header.as_ref().and_then(|h| h.content_hash)
+    }
+
+    /// Decoded size, if the frame has a block index.
+    pub fn decoded_len(&self) -> Option<u64> {
+        self.header.as_ref()?.index.as_ref().map(|i| i.decoded_len)
+    }
+}
+
+/// Describes a sealed frame from its header and size alone. Nothing is
+/// verified: use `integrity::verify` (or `seal::open`) before trusting it.
+pub fn peek(data: &[u8]) -> Result<FrameInfo, FrameError> {
+    #[cfg(feature = "signing")]
+    let (signed, data) = match crate::signature::split_signature(data) {
+        Some((content, _)) => (true, content),
+        None => (false, data),
+    };
+    #[cfg(not(feature = "signing"))]
+    let signed = false;
+    let (header, header_len) = match FrameHeader::parse(data)? {
+        Some((header, len)) => (Some(header), len),
+        None => (None, 0),
+    };
+    let footer_len = header
+        .as_ref()
+        .map_or(Checksum::Blake3, |h| h.checksum())
+        .size();
+    let payload_len = data
+        .len()
+        .checked_sub(header_len + footer_len)
+        .ok_or(FrameError::Truncated)?;
+    Ok(FrameInfo {
+        header,
+        payload_len,
+        signed,
+    })
+}
+
+fn take<const N: usize>(rest: &mut &[u8]) -> Result<[u8; N], FrameError> {
+    let bytes = rest.get(..N).ok_or(FrameError::Truncated)?;
+    *rest = &rest[N..];
+    Ok(bytes.try_into().expect("slice has N bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_roundtrip() {
+        let header = FrameHeader {
+            flags: FLAG_KEYED,
+            ..FrameHeader::default()
+        };
+        let mut buf = Vec::new();
+        header.write(&mut buf);
+        buf.extend_from_slice(b"payload");
+        assert_eq!(FrameHeader::parse(&buf), Ok(Some((header, 5))));
+    }
+
+    #[test]
+    fn encrypted_header_roundtrip() {
+        for kdf in [
+            Kdf::Raw,
+            Kdf::Argon2id {
+                m_cost: 19456,
+                t_cost: 2,
+                p_cost: 1,
+                salt: [3; SALT_SIZE],
+            },
+        ] {
+            let header = FrameHeader {
+                flags: FLAG_ENCRYPTED | FLAG_INDEXED | FLAG_CHECKSUM | FLAG_CONTENT_HASH,
+                checksum: Some(Checksum::Crc32c),
+                content_hash: Some([9; CONTENT_HASH_SIZE]),
+                index: Some(BlockIndex {
+                    decoded_len: 9000,
+                    block_size: 4096,
+                    offsets: alloc::vec![0, 1200, 2400],
+                }),
+                encryption: Some(EncryptionParams {
+                    kdf,
+                    nonce_prefix: [1; NONCE_PREFIX_SIZE],
+                    key_check: [2; KEY_CHECK_SIZE],
+                }),
+            };
+            let mut buf = Vec::new();
+            header.write(&mut buf);
+            let len = buf.len();
+            assert_eq!(len, header.encoded_len());
+            for cut in 0..len {
+                assert_eq!(FrameHeader::parse(&buf[..cut]).ok(), {
+                    if cut < MAGIC.len() {
+                        Some(None)
+                    } else {
+                        None
+                    }
+                });
+            }
+            buf.extend_from_slice(b"payload");
+            assert_eq!(FrameHeader::parse(&buf), Ok(Some((header, len))));
+        }
+    }
+
+    #[test]
+    fn block_index_maps_records() {
+        let mut payload = Vec::new();
+        for len in [3usize, 200, 1] {
+            utils::push_varint_u64(&mut payload, len as u64);
+            payload.resize(payload.len() + len, 0);
+        }
+        let index = BlockIndex::build(&payload, 9000, 4096).unwrap();
+        assert_eq!(index.offsets, [0, 4, 206]);
+        assert_eq!(index.record(1, payload.len() as u64), Some(4..206));
+        assert_eq!(index.record(2, payload.len() as u64), Some(206..208));
+        assert_eq!(index.record(3, payload.len() as u64), None);
+        assert!(BlockIndex::build(&payload[..10], 9000, 4096).is_err());
+    }
+
+    #[test]
+    fn peek_reads_header_and_sizes() {
+        let header = FrameHeader {
+            flags: FLAG_CONTENT_HASH | FLAG_CHECKSUM,
+            checksum: Some(Checksum::Xxh3_64),
+            content_hash: Some([7; CONTENT_HASH_SIZE]),
+            ..FrameHeader::default()
+        };
+        let mut frame = Vec::new();
+        header.write(&mut frame);
+        frame.extend_from_slice(b"payload!footer!!");
+        let info = peek(&frame).unwrap();
+        assert_eq!(info.checksum(), Checksum::Xxh3_64);
+        assert_eq!(info.content_hash(), Some([7; CONTENT_HASH_SIZE]));
+        assert_eq!((info.payload_len, info.signed), (8, false));
+        assert_eq!(info.decoded_len(), None);
+
+        let legacy = peek(&[0; 40]).unwrap();
+        assert_eq!((legacy.header, legacy.payload_len), (None, 8));
+        assert_eq!(peek(&frame[..40]), Err(FrameError::Truncated));
+    }
+
+    #[test]
+    fn hostile_index_count_is_rejected_without_allocating() {
+        let mut buf = b"BSCF\x04".to_vec();
+        buf.extend_from_slice(&0u64.to_le_bytes());
+        buf.extend_from_slice(&4096u32.to_le_bytes());
+        buf.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(FrameHeader::parse(&buf), Err(FrameError::Truncated));
+    }
+
+    #[test]
+    fn legacy_and_invalid_headers() {
+        assert_eq!(FrameHeader::parse(b"\x05\x00hello"), Ok(None));
+        assert_eq!(FrameHeader::parse(b"BSCF"), Err(FrameError::Truncated));
+        assert_eq!(
+            FrameHeader::parse(b"BSCF\x80"),
+            Err(FrameError::UnknownFlags(0x80))
+        );
+        assert_eq!(
+            FrameHeader::parse(b"BSCF\x02\x07"),
+            Err(FrameError::UnknownKdf(7))
+        );
+        assert_eq!(
+            FrameHeader::parse(b"BSCF\x08\x09"),
+            Err(FrameError::UnknownChecksum(9))
+        );
+        assert_eq!(
+            FrameHeader::parse(b"BSCF\x09\x03"),
+            Err(FrameError::KeyedChecksum)
+        );
+    }
+}
@@ -1,14 +1,24 @@
 #![allow(dead_code)]
 //! Huffman coding implementation with canonical codes and a fast lookup table for decoding.
 
+use crate::limits::DecodeError;
+use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 use anyhow::{anyhow, Result};
-use byteorder::{ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "std")]
+use byteorder::ReadBytesExt;
+use core::cmp::Reverse;
+use core::ptr;
+#[cfg(feature = "std")]
 use once_cell::sync::Lazy;
-use std::collections::BinaryHeap;
+#[cfg(feature = "std")]
 use std::collections::HashMap;
+#[cfg(feature = "std")]
 use std::io::{Read, Write};
-use std::ptr;
-use std::sync::{Arc, RwLock};
+#[cfg(feature = "std")]
+use std::sync::RwLock;
 
 const MAX_CODE_LEN: usize = 15;
 // Number of bits used for the fast Huffman decode lookup table.
@@ -17,6 +27,7 @@ const MAX_CODE_LEN: usize = 15;
 const FAST_DECODE_BITS: usize = 16;
 const TABLE_SIZE: usize = 1 << FAST_DECODE_BITS;
 const CACHE_LIMIT: usize = 32;
+#[cfg(feature = "std")]
 static CODE_CACHE: Lazy<RwLock<HashMap<Vec<u8>, Arc<Vec<FastDecodeEntry>>>>> =
     Lazy::new(|| RwLock::new(HashMap::new()));
 
@@ -38,6 +49,46 @@ pub struct CanonicalCode {
     fast_decode_table: Arc<Vec<FastDecodeEntry>>,
 }
 
+/// Checks that `lengths` describe a prefix code: every length fits in
+/// `MAX_CODE_LEN` bits and the Kraft sum does not exceed one.
+fn validate_lengths(lengths: &[u8; 256]) -> Result<(), DecodeError> {
+    let mut kraft: u32 = 0;
+    for &len in lengths.iter() {
+        if len as usize > MAX_CODE_LEN {
+            return Err(DecodeError::InvalidCodeTable("code length exceeds 15 bits"));
+        }
+        if len > 0 {
+            kraft += 1 << (MAX_CODE_LEN - len as usize);
+        }
+    }
+    if kraft > 1 << MAX_CODE_LEN {
+        return Err(DecodeError::InvalidCodeTable("oversubscribed code lengths"));
+    }
+    Ok(())
+}
+
+/// Repairs code lengths that were clamped to `MAX_CODE_LEN` and therefore
+/// oversubscribe the code space. The deepest codes below the limit are made
+/// one bit longer (rarest symbol first) until the Kraft sum fits again.
+fn limit_lengths(lengths: &mut [u8; 256], freqs: &[u64; 256]) {
+    let full: u32 = 1 << MAX_CODE_LEN;
+    let mut kraft: u32 = lengths
+        .iter()
+        .filter(|&&l| l > 0)
+        .map(|&l| 1u32 << (MAX_CODE_LEN - l as usize))
+        .sum();
+    while kraft > full {
+        let Some(sym) = (0..256)
+            .filter(|&s| lengths[s] > 0 && (lengths[s] as usize) < MAX_CODE_LEN)
+            .max_by_key(|&s| (lengths[s], Reverse(freqs[s])))
+        else {
+            break;
+        };
+        kraft -= 1 << (MAX_CODE_LEN - lengths[sym] as usize - 1);
+        lengths[sym] += 1;
+    }
+}
+
 impl CanonicalCode {
     pub fn new(freqs: &[u64; 256]) -> Result<Self> {
         let mut code_lengths = [0u8; 256];
@@ -53,7 +104,7 @@ impl CanonicalCode {
         } else {
             let mut heap = BinaryHeap::new();
             for (symbol, &freq) in active_symbols {
-                heap.push(std::cmp::Reverse((freq, vec![symbol as u8])));
+                heap.push(Reverse((freq, vec![symbol as u8])));
             }
 
             let mut combined: Vec<_> = heap
@@ -63,7 +114,7 @@ impl CanonicalCode {
                 .collect();
 
             while combined.len() > 1 {
-                combined.sort_by_key(|k| std::cmp::Reverse(k.0));
+                combined.sort_by_key(|k| Reverse(k.0));
                 let (f1, s1) = combined.pop().unwrap();
                 let (f2, s2) = combined.pop().unwrap();
 
@@ -85,44 +136,13 @@ impl CanonicalCode {
                 *len = MAX_CODE_LEN as u8;
             }
         }
+        limit_lengths(&mut code_lengths, freqs);
 
         Self::from_lengths(&code_lengths)
     }
 
     pub fn from_lengths(lengths: &[u8; 256]) -> Result<Self> {
-        let mut codes = [HuffCode::default(); 256];
-        let mut bl_count = [0u32; MAX_CODE_LEN + 1];
-        for &len in lengths.iter() {
-            if len as usize > MAX_CODE_LEN {
-                return Err(anyhow!(
-                    "Code length {} exceeds MAX_CODE_LEN {}",
-                    len,
-                    MAX_CODE_LEN
-                ));
-            }
-            if len > 0 {
-                bl_count[len as usize] += 1;
-            }
-        }
-
-        let mut next_code = [0u16; MAX_CODE_LEN + 1];
-        let mut code = 0;
-        for bits in 1..=MAX_CODE_LEN {
-            code = (code + bl_count[bits - 1] as u16) << 1;
-            next_code[bits] = code;
-        }
-
-        for i in 0..256 {
-            let len = lengths[i];
-            if len > 0 {
-                codes[i] = HuffCode {
-                    code: next_code[len as usize],
-                    len,
-                };
-                next_code[len as usize] += 1;
-            }
-        }
-
+        let codes = build_codes(lengths)?;
         let fast_decode_table = Arc::new(Self::build_fast_decode_table(&codes));
 
         Ok(Self {
@@ -158,66 +178,54 @@ impl CanonicalCode {
         (hc.code, hc.len)
     }
 
-    pub fn write_lengths<W: Write>(&self, writer: &mut W) -> Result<()> {
+    fn push_lengths(&self, out: &mut Vec<u8>) {
         let non_zero: Vec<_> = self
             .codes
             .iter()
             .enumerate()
             .filter(|(_, hc)| hc.len > 0)
             .collect();
-        writer.write_u8(non_zero.len() as u8)?;
+        // A non-empty table has 1..=256 entries; 256 wraps to 0 on the wire.
+        out.push(non_zero.len() as u8);
         for (symbol, hc) in non_zero {
-            writer.write_u8(symbol as u8)?;
-            writer.write_u8(hc.len)?;
+            out.push(symbol as u8);
+            out.push(hc.len);
         }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_lengths<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut table = Vec::new();
+        self.push_lengths(&mut table);
+        writer.write_all(&table)?;
         Ok(())
     }
 
+    #[cfg(feature = "std")]
     pub fn read_lengths<R: Read>(reader: &mut R) -> Result<Self> {
-        let mut lengths = [0u8; 256];
-        let count = reader.read_u8()?;
-        for _ in 0..count {
-            let symbol = reader.read_u8()?;
-            let len = reader.read_u8()?;
-            lengths[symbol as usize] = len;
-        }
+        let truncated = |_| DecodeError::InvalidCodeTable("truncated table");
+        let mut table = vec![reader.read_u8().map_err(truncated)?];
+        table.resize(1 + 2 * table_entries(table[0]), 0);
+        reader.read_exact(&mut table[1..]).map_err(truncated)?;
+        let (lengths, _) = parse_lengths(&table)?;
+        Self::from_validated_lengths(&lengths)
+    }
+
+    /// Builds the code for lengths accepted by [`parse_lengths`], reusing a
+    /// cached decode table when one exists (std only).
+    fn from_validated_lengths(lengths: &[u8; 256]) -> Result<Self> {
         // Проверяем кэш по ключу длины
-        let key: Vec<u8> = lengths.to_vec();
-        if let Some(entry) = CODE_CACHE.read().unwrap().get(&key) {
+        #[cfg(feature = "std")]
+        if let Some(entry) = CODE_CACHE.read().unwrap().get(&lengths[..]) {
             // Быстрый путь: таблица уже есть
             return Ok(Self {
-                codes: {
-                    // восстановим коды (быстро)
-                    let mut codes = [HuffCode::default(); 256];
-                    let mut bl_count = [0u32; MAX_CODE_LEN + 1];
-                    for &len in &lengths {
-                        if len > 0 {
-                            bl_count[len as usize] += 1;
-                        }
-                    }
-                    let mut next_code = [0u16; MAX_CODE_LEN + 1];
-                    let mut code = 0u16;
-                    for bits in 1..=MAX_CODE_LEN {
-                        code = (code + bl_count[bits - 1] as u16) << 1;
-                        next_code[bits] = code;
-                    }
-                    for i in 0..256 {
-                        let len = lengths[i];
-                        if len > 0 {
-                            codes[i] = HuffCode {
-                                code: next_code[len as usize],
-                                len,
-                            };
-                            next_code[len as usize] += 1;
-                        }
-                    }
-                    codes
-                },
+                codes: build_codes(lengths)?,
                 fast_decode_table: entry.clone(),
             });
         }
         // Нет в кэше — строим и добавляем
-        let cc = Self::from_lengths(&lengths)?;
+        let cc = Self::from_lengths(lengths)?;
+        #[cfg(feature = "std")]
         {
             let mut cache = CODE_CACHE.write().unwrap();
             if cache.len() >= CACHE_LIMIT {
@@ -226,12 +234,80 @@ impl CanonicalCode {
                     cache.remove(&first_key);
                 }
             }
-            cache.insert(key, cc.fast_decode_table.clone());
+            cache.insert(lengths.to_vec(), cc.fast_decode_table.clone());
         }
         Ok(cc)
     }
 }
 
+/// Assigns canonical codes to `lengths` (DEFLATE order).
+fn build_codes(lengths: &[u8; 256]) -> Result<[HuffCode; 256]> {
+    let mut codes = [HuffCode::default(); 256];
+    let mut bl_count = [0u32; MAX_CODE_LEN + 1];
+    for &len in lengths.iter() {
+        if len as usize > MAX_CODE_LEN {
+            return Err(anyhow!(
+                "Code length {} exceeds MAX_CODE_LEN {}",
+                len,
+                MAX_CODE_LEN
+            ));
+        }
+        if len > 0 {
+            bl_count[len as usize] += 1;
+        }
+    }
+
+    let mut next_code = [0u16; MAX_CODE_LEN + 1];
+    let mut code = 0u16;
+    for bits in 1..=MAX_CODE_LEN {
+        code = (code + bl_count[bits - 1] as u16) << 1;
+        next_code[bits] = code;
+    }
+
+    for i in 0..256 {
+        let len = lengths[i];
+        if len > 0 {
+            codes[i] = HuffCode {
+                code: next_code[len as usize],
+                len,
+            };
+            next_code[len as usize] += 1;
+        }
+    }
+    Ok(codes)
+}
+
+/// Number of `(symbol, length)` pairs announced by a table's count byte.
+fn table_entries(count: u8) -> usize {
+    match count {
+        0 => 256,
+        n => n as usize,
+    }
+}
+
+/// Parses a serialized code table from the start of `input`.
+///
+/// Returns the validated code lengths and the number of bytes consumed.
+pub(crate) fn parse_lengths(input: &[u8]) -> Result<([u8; 256], usize), DecodeError> {
+    let truncated = DecodeError::InvalidCodeTable("truncated table");
+    let count = table_entries(*input.first().ok_or(truncated.clone())?);
+    let end = 1 + 2 * count;
+    let entries = input.get(1..end).ok_or(truncated)?;
+    let mut lengths = [0u8; 256];
+    for pair in entries.chunks_exact(2) {
+        let (symbol, len) = (pair[0], pair[1]);
+        if len == 0 {
+            return Err(DecodeError::InvalidCodeTable("zero code length"));
+        }
+        if lengths[symbol as usize] != 0 {
+            return Err(DecodeError::InvalidCodeTable("duplicate symbol"));
+        }
+        lengths[symbol as usize] = len;
+    }
+    validate_lengths(&lengths)?;
+    Ok((lengths, end))
+}
+
 pub fn encode(input: &[u8]) -> Result<Vec<u8>> {
     if input.is_empty() {
         return Ok(Vec::new());
@@ -245,7 +321,7 @@ pub fn encode(input: &[u8]) -> Result<Vec<u8>> {
     let huff_tree = CanonicalCode::new(&freqs)?;
 
     let mut out = Vec::new();
-    huff_tree.write_lengths(&mut out)?;
+    huff_tree.push_lengths(&mut out);
 
     let mut bit_writer = BitWriter::new();
     for &byte in input {
@@ -259,13 +335,26 @@ pub fn encode(input: &[u8]) -> Result<Vec<u8>> {
     Ok(out)
 }
 
+/// Decodes a Huffman sub-stream produced by [`encode`] and appends the symbols
+/// to `out`.
+///
+/// With `expected_size` set the stream is validated strictly: exactly that
+/// many symbols must be present, the padding bits of the last byte must be
+/// zero and nothing may follow the bitstream. Without it, decoding simply
+/// stops when the bits run out.
 pub fn decode(input: &[u8], out: &mut Vec<u8>, expected_size: Option<usize>) -> Result<()> {
     if input.is_empty() {
-        return Ok(());
+        return match expected_size {
+            Some(expected) if expected > 0 => Err(DecodeError::Truncated {
+                decoded: 0,
+                expected,
+            }
+            .into()),
+            _ => Ok(()),
+        };
     }
-    let mut reader = std::io::Cursor::new(input);
-    let huff_tree = CanonicalCode::read_lengths(&mut reader)?;
-    let data_start_pos = reader.position() as usize;
+    let (lengths, data_start_pos) = parse_lengths(input)?;
+    let huff_tree = CanonicalCode::from_validated_lengths(&lengths)?;
     let bit_buf = &input[data_start_pos..];
 
     // Fast decode path using custom bit cursor (≈3× быстрее стандартного BitReader).
@@ -275,7 +364,8 @@ pub fn decode(input: &[u8], out: &mut Vec<u8>, expected_size: Option<usize>) ->
     let total_bits = bit_buf.len() * 8;
     let mut decoded: usize = 0;
     let expect = expected_size.unwrap_or(usize::MAX);
-    out.reserve(expect);
+    // Every code is at least one bit long, so the bitstream bounds the output.
+    out.reserve(expect.min(total_bits));
 
     // Быстрый peek 16 бит с использованием небезопасного чтения u32 без проверок границ.
     // Для последних ≤3 байтов потока fallback на безопасный вариант.
@@ -298,7 +388,7 @@ pub fn decode(input: &[u8], out: &mut Vec<u8>, expected_size: Option<usize>) ->
         }
     };
 
-    while decoded < expect {
+    'outer: while decoded < expect {
         let bits_consumed = byte_pos * 8 + bit_pos as usize;
         if bits_consumed >= total_bits {
             break;
@@ -326,6 +416,10 @@ pub fn decode(input: &[u8], out: &mut Vec<u8>, expected_size: Option<usize>) ->
 
         // распаковка двух символов на итерацию, если хватает бит
         for _ in 0..2 {
+            // The fast path must never read into the zero fill past the end.
+            if total_bits - (byte_pos * 8 + bit_pos as usize) < FAST_DECODE_BITS {
+                continue 'outer;
+            }
             let idx = peek16(byte_pos, bit_pos) as usize;
             let entry = &huff_tree.fast_decode_table[idx];
             if entry.len == 0 {
@@ -341,7 +435,7 @@ pub fn decode(input: &[u8], out: &mut Vec<u8>, expected_size: Option<usize>) ->
                     byte_pos = br.byte_pos;
                     bit_pos = br.bit_pos;
                 } else {
-                    break;
+                    break 'outer;
                 }
             } else {
                 out.push(entry.symbol);
@@ -356,6 +450,21 @@ pub fn decode(input: &[u8], out: &mut Vec<u8>, expected_size: Option<usize>) ->
         }
     }
 
+    if let Some(expected) = expected_size {
+        if decoded < expected {
+            return Err(DecodeError::Truncated { decoded, expected }.into());
+        }
+        if bit_pos > 0 {
+            if bit_buf[byte_pos] & (0xFF >> bit_pos) != 0 {
+                return Err(DecodeError::NonZeroPadding.into());
+            }
+            byte_pos += 1;
+        }
+        if byte_pos < bit_buf.len() {
+            return Err(DecodeError::TrailingData(bit_buf.len() - byte_pos).into());
+        }
+    }
+
     Ok(())
 }
 
@@ -506,3 +615,105 @@ impl<'a> BitReader<'a> {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn read_err(table: &[u8]) -> DecodeError {
+        parse_lengths(table).unwrap_err()
+    }
+
+    #[test]
+    fn rejects_oversubscribed_table() {
+        // Three one-bit codes cannot form a prefix code.
+        let table = [3, b'a', 1, b'b', 1, b'c', 1];
+        assert_eq!(
+            read_err(&table),
+            DecodeError::InvalidCodeTable("oversubscribed code lengths")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_tables() {
+        assert!(matches!(
+            read_err(&[2, b'a', 1]),
+            DecodeError::InvalidCodeTable(_)
+        ));
+        assert!(matches!(
+            read_err(&[1, b'a', 16]),
+            DecodeError::InvalidCodeTable(_)
+        ));
+        assert!(matches!(
+            read_err(&[1, b'a', 0]),
+            DecodeError::InvalidCodeTable(_)
+        ));
+        assert!(matches!(
+            read_err(&[2, b'a', 1, b'a', 1]),
+            DecodeError::InvalidCodeTable(_)
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn read_lengths_matches_write_lengths() {
+        let code = CanonicalCode::new(&[3u64; 256]).unwrap();
+        let mut table = Vec::new();
+        code.write_lengths(&mut table).unwrap();
+        let read = CanonicalCode::read_lengths(&mut std::io::Cursor::new(&table)).unwrap();
+        for sym in 0..=255u8 {
+            assert_eq!(read.get_code(sym), code.get_code(sym));
+        }
+    }
+
+    #[test]
+    fn roundtrip_all_256_symbols() {
+        let mut data = vec![0u8; 2048];
+        data.extend(0..=255u8);
+        let encoded = encode(&data).unwrap();
+        assert_eq!(encoded[0], 0, "256 entries are stored as a zero count");
+        let mut out = Vec::new();
+        decode(&encoded, &mut out, Some(data.len())).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn deep_trees_are_length_limited() {
+        // Fibonacci frequencies produce a Huffman tree deeper than 15 levels.
+        let mut freqs = [0u64; 256];
+        let (mut a, mut b) = (1u64, 1u64);
+        for f in freqs.iter_mut().take(24) {
+            *f = a;
+            (a, b) = (b, a + b);
+        }
+        let code = CanonicalCode::new(&freqs).unwrap();
+        let mut lengths = [0u8; 256];
+        for (sym, len) in lengths.iter_mut().enumerate() {
+            *len = code.get_code(sym as u8).1;
+        }
+        validate_lengths(&lengths).unwrap();
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_lengths_never_panics(table in prop::collection::vec(any::<u8>(), 0..600)) {
+            if let Ok((lengths, used)) = parse_lengths(&table) {
+                prop_assert!(used <= table.len());
+                prop_assert!(CanonicalCode::from_validated_lengths(&lengths).is_ok());
+            }
+        }
+
+        #[test]
+        fn prop_code_lengths_are_valid(freqs in prop::collection::vec(0u64..1 << 20, 256)) {
+            let mut arr = [0u64; 256];
+            arr.copy_from_slice(&freqs);
+            let code = CanonicalCode::new(&arr).unwrap();
+            let mut lengths = [0u8; 256];
+            for (sym, len) in lengths.iter_mut().enumerate() {
+                *len = code.get_code(sym as u8).1;
+            }
+            prop_assert!(validate_lengths(&lengths).is_ok());
+        }
+    }
+}
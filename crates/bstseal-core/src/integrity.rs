@@ -14,20 +14,239 @@
 //! Verification is O(n) hashing + constant-time compare.
 //!
 //! This helper is **format-agnostic** – it can wrap any byte slice.
+//!
+//! A plain digest only detects accidental corruption: whoever changes the
+//! payload can recompute it. [`add_keyed_footer`] seals a frame with
+//! `blake3::keyed_hash` instead. It prepends a [`FrameHeader`] with
+//! [`FLAG_KEYED`] set, and the MAC covers that header as well as the payload,
+//! so the flag cannot be stripped. [`verify`] accepts either kind.
+//!
+//! When a frame has a header, either footer covers `header || payload`.
+//!
+//! Unkeyed frames may use another [`Checksum`] (e.g. a 4-byte CRC-32C for
+//! tiny messages); [`add_footer_with`] records the algorithm in the header.
 
+use crate::frame::{FrameError, FrameHeader, FLAG_CHECKSUM, FLAG_KEYED};
+use alloc::vec::Vec;
+use core::fmt;
+use sha2::Digest as _;
 use thiserror::Error;
 
-/// Size of the Blake3 hash in bytes.
+/// Size of the Blake3 hash in bytes, and the largest footer of any
+/// [`Checksum`].
 pub const HASH_SIZE: usize = blake3::OUT_LEN;
 
+/// Size of the key for [`add_keyed_footer`].
+pub const KEY_SIZE: usize = blake3::KEY_LEN;
+
+/// Which digest protects a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FooterKind {
+    /// Unkeyed `blake3(payload)`.
+    Plain,
+    /// `blake3::keyed_hash(key, header || payload)`.
+    Keyed,
+}
+
+/// Footer checksum algorithm, identified by one byte in the frame header.
+///
+/// Only the Blake3 variants resist deliberate tampering well enough to be
+/// signed; XXH3 and CRC-32C detect accidental corruption only. Multi-byte
+/// integers are stored big-endian, as in their canonical form.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Checksum {
+    /// 32-byte Blake3 (legacy frames, and the only choice for keyed frames).
+    #[default]
+    Blake3,
+    /// Blake3 truncated to 16 bytes.
+    Blake3_128,
+    /// 8-byte XXH3-64.
+    Xxh3_64,
+    /// 4-byte CRC-32C (Castagnoli).
+    Crc32c,
+    /// 32-byte SHA-256.
+    Sha256,
+}
+
+impl Checksum {
+    pub const ALL: [Checksum; 5] = [
+        Checksum::Blake3,
+        Checksum::Blake3_128,
+        Checksum::Xxh3_64,
+        Checksum::Crc32c,
+        Checksum::Sha256,
+    ];
+
+    /// Algorithm byte stored in the frame header.
+    pub const fn id(self) -> u8 {
+        match self {
+            Checksum::Blake3 => 0,
+            Checksum::Blake3_128 => 1,
+            Checksum::Xxh3_64 => 2,
+            Checksum::Crc32c => 3,
+            Checksum::Sha256 => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.id() == id)
+    }
+
+    /// Footer size in bytes.
+    pub const fn size(self) -> usize {
+        match self {
+            Checksum::Blake3 | Checksum::Sha256 => 32,
+            Checksum::Blake3_128 => 16,
+            Checksum::Xxh3_64 => 8,
+            Checksum::Crc32c => 4,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Checksum::Blake3 => "blake3",
+            Checksum::Blake3_128 => "blake3-128",
+            Checksum::Xxh3_64 => "xxh3-64",
+            Checksum::Crc32c => "crc32c",
+            Checksum::Sha256 => "sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    pub fn digest(self, data: &[u8]) -> Digest {
+        let mut hasher = Hasher::new(self);
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A footer value: up to [`HASH_SIZE`] bytes of a [`Checksum`].
+#[derive(Clone, Copy)]
+pub struct Digest {
+    checksum: Checksum,
+    bytes: [u8; HASH_SIZE],
+}
+
+impl Digest {
+    /// `bytes` must be `checksum.size()` long.
+    fn new(checksum: Checksum, bytes: &[u8]) -> Self {
+        let mut digest = Self {
+            checksum,
+            bytes: [0; HASH_SIZE],
+        };
+        digest.bytes[..checksum.size()].copy_from_slice(bytes);
+        digest
+    }
+
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.checksum.size()]
+    }
+}
+
+/// Constant-time comparison.
+impl PartialEq for Digest {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq::constant_time_eq(self.as_bytes(), other.as_bytes())
+    }
+}
+
+impl Eq for Digest {}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_bytes()
+            .iter()
+            .try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+static CRC32C: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+/// Incremental [`Checksum::digest`]; also computes keyed Blake3 MACs.
+pub(crate) struct Hasher {
+    checksum: Checksum,
+    state: HasherState,
+}
+
+// One per frame being verified; not worth boxing the Blake3 state.
+#[allow(clippy::large_enum_variant)]
+enum HasherState {
+    Blake3(blake3::Hasher),
+    Xxh3(xxhash_rust::xxh3::Xxh3),
+    Crc32c(crc::Digest<'static, u32>),
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    pub(crate) fn new(checksum: Checksum) -> Self {
+        let state = match checksum {
+            Checksum::Blake3 | Checksum::Blake3_128 => HasherState::Blake3(blake3::Hasher::new()),
+            Checksum::Xxh3_64 => HasherState::Xxh3(Default::default()),
+            Checksum::Crc32c => HasherState::Crc32c(CRC32C.digest()),
+            Checksum::Sha256 => HasherState::Sha256(sha2::Sha256::new()),
+        };
+        Self { checksum, state }
+    }
+
+    pub(crate) fn new_keyed(key: &[u8; KEY_SIZE]) -> Self {
+        Self {
+            checksum: Checksum::Blake3,
+            state: HasherState::Blake3(blake3::Hasher::new_keyed(key)),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match &mut self.state {
+            HasherState::Blake3(h) => {
+                h.update(data);
+            }
+            HasherState::Xxh3(h) => h.update(data),
+            HasherState::Crc32c(h) => h.update(data),
+            HasherState::Sha256(h) => h.update(data),
+        }
+    }
+
+    pub(crate) fn finalize(self) -> Digest {
+        let c = self.checksum;
+        match self.state {
+            HasherState::Blake3(h) => Digest::new(c, &h.finalize().as_bytes()[..c.size()]),
+            HasherState::Xxh3(h) => Digest::new(c, &h.digest().to_be_bytes()),
+            HasherState::Crc32c(h) => Digest::new(c, &h.finalize().to_be_bytes()),
+            HasherState::Sha256(h) => Digest::new(c, &h.finalize()),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum IntegrityError {
-    #[error("file is smaller than integrity footer ({HASH_SIZE} bytes)")]
+    #[error("input is smaller than its integrity footer")]
     TooSmall,
-    #[error("checksum mismatch: expected {expected:?}, got {actual:?}")]
+    #[error(transparent)]
+    Header(#[from] FrameError),
+    #[error("frame is sealed with a keyed MAC; a key is required to verify it")]
+    KeyRequired,
+    #[error("frame is not sealed with a keyed MAC")]
+    NotKeyed,
+    #[error("decoded content does not match the content hash in the header")]
+    ContentMismatch,
+    #[error("{algorithm} checksum mismatch: expected {expected:?}, got {actual:?}")]
     Mismatch {
-        expected: [u8; HASH_SIZE],
-        actual: [u8; HASH_SIZE],
+        algorithm: Checksum,
+        expected: Digest,
+        actual: Digest,
     },
 }
 
@@ -48,16 +267,134 @@ pub fn verify_footer(data: &[u8]) -> Result<&[u8], IntegrityError> {
         return Err(IntegrityError::TooSmall);
     }
     let (payload, footer) = data.split_at(data.len() - HASH_SIZE);
-    let expected = blake3::hash(payload);
-    let mut actual_arr = [0u8; HASH_SIZE];
-    actual_arr.copy_from_slice(footer);
-    let expected_arr = *expected.as_bytes();
-    if expected_arr == actual_arr {
-        Ok(payload)
+    check_digest(Checksum::Blake3.digest(payload), footer)?;
+    Ok(payload)
+}
+
+/// Returns `data` sealed with `checksum`. Blake3 gives the legacy layout of
+/// [`add_footer`]; other algorithms prepend a header naming the algorithm.
+pub fn add_footer_with(data: &[u8], checksum: Checksum) -> Vec<u8> {
+    if checksum == Checksum::Blake3 {
+        return add_footer(data);
+    }
+    let header = FrameHeader {
+        flags: FLAG_CHECKSUM,
+        checksum: Some(checksum),
+        ..Default::default()
+    };
+    let mut out = Vec::with_capacity(header.encoded_len() + data.len() + checksum.size());
+    header.write(&mut out);
+    out.extend_from_slice(data);
+    push_footer(&mut out, None, checksum);
+    out
+}
+
+/// Appends the footer over all of `out`: keyed Blake3 with `key`,
+/// `checksum` without. `out` must start with a header that matches.
+pub(crate) fn push_footer(out: &mut Vec<u8>, key: Option<&[u8; KEY_SIZE]>, checksum: Checksum) {
+    let mut hasher = match key {
+        Some(key) => Hasher::new_keyed(key),
+        None => Hasher::new(checksum),
+    };
+    hasher.update(out);
+    out.extend_from_slice(hasher.finalize().as_bytes());
+}
+
+/// Returns `header || data || blake3::keyed_hash(key, header || data)`.
+pub fn add_keyed_footer(data: &[u8], key: &[u8; KEY_SIZE]) -> Vec<u8> {
+    let header = FrameHeader {
+        flags: FLAG_KEYED,
+        ..Default::default()
+    };
+    let mut out = Vec::with_capacity(header.encoded_len() + data.len() + HASH_SIZE);
+    header.write(&mut out);
+    out.extend_from_slice(data);
+    push_footer(&mut out, Some(key), Checksum::Blake3);
+    out
+}
+
+/// Verifies a frame produced by [`add_keyed_footer`]. Returns the payload
+/// **without** header and footer on success.
+///
+/// Frames without the keyed flag are rejected with
+/// [`IntegrityError::NotKeyed`], so a keyed seal cannot be downgraded to a
+/// plain digest.
+pub fn verify_keyed_footer<'a>(
+    data: &'a [u8],
+    key: &[u8; KEY_SIZE],
+) -> Result<&'a [u8], IntegrityError> {
+    verify_frame(data, Some(key)).map(|(_, payload)| payload)
+}
+
+/// Reports which footer protects `data`.
+pub fn footer_kind(data: &[u8]) -> Result<FooterKind, IntegrityError> {
+    Ok(match FrameHeader::parse(data)? {
+        Some((header, _)) if header.has(FLAG_KEYED) => FooterKind::Keyed,
+        _ => FooterKind::Plain,
+    })
+}
+
+/// Verifies either footer kind and returns the payload.
+///
+/// With a `key`, only keyed frames are accepted. Without one, keyed frames
+/// fail with [`IntegrityError::KeyRequired`].
+pub fn verify<'a>(
+    data: &'a [u8],
+    key: Option<&[u8; KEY_SIZE]>,
+) -> Result<&'a [u8], IntegrityError> {
+    verify_frame(data, key).map(|(_, payload)| payload)
+}
+
+/// Like [`verify`], but also returns the frame header, if there is one.
+/// The footer is checked before the header is trusted.
+pub fn verify_frame<'a>(
+    data: &'a [u8],
+    key: Option<&[u8; KEY_SIZE]>,
+) -> Result<(Option<FrameHeader>, &'a [u8]), IntegrityError> {
+    let header = FrameHeader::parse(data)?;
+    let checksum = header
+        .as_ref()
+        .map_or(Checksum::Blake3, |(h, _)| h.checksum());
+    let keyed = header.is_some_and(|(h, _)| h.has(FLAG_KEYED));
+    if data.len() < checksum.size() {
+        return Err(IntegrityError::TooSmall);
+    }
+    let (sealed, footer) = data.split_at(data.len() - checksum.size());
+    let mut hasher = match (keyed, key) {
+        (true, Some(key)) => Hasher::new_keyed(key),
+        (true, None) => return Err(IntegrityError::KeyRequired),
+        (false, Some(_)) => return Err(IntegrityError::NotKeyed),
+        (false, None) => Hasher::new(checksum),
+    };
+    hasher.update(sealed);
+    check_digest(hasher.finalize(), footer)?;
+    Ok(match FrameHeader::parse(sealed)? {
+        Some((header, len)) => (Some(header), &sealed[len..]),
+        None => (None, sealed),
+    })
+}
+
+/// Checks decoded output against the content hash of a frame header.
+pub fn check_content(header: &FrameHeader, decoded: &[u8]) -> Result<(), IntegrityError> {
+    match header.content_hash {
+        Some(hash) if blake3::hash(decoded) != blake3::Hash::from(hash) => {
+            Err(IntegrityError::ContentMismatch)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Compares `expected` against the stored footer in constant time.
+/// `footer` must be `expected.checksum().size()` bytes long.
+pub(crate) fn check_digest(expected: Digest, footer: &[u8]) -> Result<(), IntegrityError> {
+    let actual = Digest::new(expected.checksum, footer);
+    if expected == actual {
+        Ok(())
     } else {
         Err(IntegrityError::Mismatch {
-            expected: expected_arr,
-            actual: actual_arr,
+            algorithm: expected.checksum,
+            expected,
+            actual,
         })
     }
 }
@@ -81,4 +418,103 @@ mod tests {
         corrupted[0] ^= 0xAA; // flip a bit
         assert!(verify_footer(&corrupted).is_err());
     }
+
+    #[test]
+    fn rejects_short_input() {
+        assert!(matches!(
+            verify_footer(&[0u8; HASH_SIZE - 1]),
+            Err(IntegrityError::TooSmall)
+        ));
+        assert_eq!(verify_footer(&add_footer(b"")).unwrap(), b"");
+    }
+
+    const KEY: [u8; KEY_SIZE] = [7; KEY_SIZE];
+
+    #[test]
+    fn keyed_roundtrip() {
+        let sealed = add_keyed_footer(b"payload bytes", &KEY);
+        assert_eq!(footer_kind(&sealed).unwrap(), FooterKind::Keyed);
+        assert_eq!(
+            verify_keyed_footer(&sealed, &KEY).unwrap(),
+            b"payload bytes"
+        );
+        assert_eq!(verify(&sealed, Some(&KEY)).unwrap(), b"payload bytes");
+        assert_eq!(
+            verify_keyed_footer(&add_keyed_footer(b"", &KEY), &KEY).unwrap(),
+            b""
+        );
+    }
+
+    #[test]
+    fn keyed_rejects_wrong_key_and_tampering() {
+        let sealed = add_keyed_footer(b"payload bytes", &KEY);
+        let other = [8u8; KEY_SIZE];
+        assert!(matches!(
+            verify_keyed_footer(&sealed, &other),
+            Err(IntegrityError::Mismatch { .. })
+        ));
+
+        // Recomputing a plain digest over tampered data does not help.
+        let mut tampered = sealed[..sealed.len() - HASH_SIZE].to_vec();
+        tampered[6] ^= 1;
+        let forged = add_footer(&tampered);
+        assert!(verify_keyed_footer(&forged, &KEY).is_err());
+    }
+
+    #[test]
+    fn verify_dispatches_on_footer_kind() {
+        let plain = add_footer(b"payload bytes");
+        let sealed = add_keyed_footer(b"payload bytes", &KEY);
+        assert_eq!(verify(&plain, None).unwrap(), b"payload bytes");
+        assert!(matches!(
+            verify(&sealed, None),
+            Err(IntegrityError::KeyRequired)
+        ));
+        assert!(matches!(
+            verify(&plain, Some(&KEY)),
+            Err(IntegrityError::NotKeyed)
+        ));
+        assert!(matches!(
+            verify(b"BSCF\x01", Some(&KEY)),
+            Err(IntegrityError::TooSmall)
+        ));
+    }
+
+    #[test]
+    fn checksum_known_answers() {
+        let hex = |d: Digest| alloc::format!("{d:?}");
+        assert_eq!(hex(Checksum::Crc32c.digest(b"123456789")), "e3069283");
+        assert_eq!(hex(Checksum::Xxh3_64.digest(b"")), "2d06800538d394c2");
+        assert_eq!(
+            hex(Checksum::Sha256.digest(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            Checksum::Blake3_128.digest(b"abc").as_bytes(),
+            &blake3::hash(b"abc").as_bytes()[..16]
+        );
+        for checksum in Checksum::ALL {
+            assert_eq!(Checksum::from_id(checksum.id()), Some(checksum));
+            assert_eq!(Checksum::from_name(checksum.name()), Some(checksum));
+        }
+    }
+
+    #[test]
+    fn every_checksum_roundtrips_and_reports_mismatch() {
+        for checksum in Checksum::ALL {
+            let sealed = add_footer_with(b"payload bytes", checksum);
+            assert_eq!(verify(&sealed, None).unwrap(), b"payload bytes");
+            let header_len = if checksum == Checksum::Blake3 { 0 } else { 6 };
+            assert_eq!(sealed.len(), header_len + 13 + checksum.size());
+
+            let mut corrupted = sealed.clone();
+            corrupted[header_len] ^= 1;
+            match verify(&corrupted, None) {
+                Err(IntegrityError::Mismatch { algorithm, .. }) => assert_eq!(algorithm, checksum),
+                other => panic!("{checksum}: {other:?}"),
+            }
+        }
+        let err = verify(&add_footer_with(b"x", Checksum::Crc32c)[..5], None).unwrap_err();
+        assert!(matches!(err, IntegrityError::Header(FrameError::Truncated)));
+    }
 }
@@ -1,67 +1,96 @@
+//! Without the default `std` feature the crate builds as `no_std + alloc`
+//! and provides block coding, integrity footers and the sequential
+//! [`stream`] decoder. Threaded coding and licensing require `std`.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![allow(clippy::type_complexity)]
 #![allow(clippy::unnecessary_cast)]
 
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod archive;
 pub mod block_coder;
+#[cfg(feature = "encryption")]
+pub mod crypto;
+#[cfg(feature = "std")]
 pub mod encode;
+pub mod frame;
 pub mod huff;
 pub mod integrity;
+#[cfg(feature = "licensing")]
+pub mod license;
+pub mod limits;
+#[cfg(feature = "outboard")]
+pub mod outboard;
 pub mod raw;
+#[cfg(feature = "std")]
+pub mod seal;
+#[cfg(feature = "signing")]
+pub mod signature;
+pub mod stream;
 pub mod utils;
-pub mod license;
 
-pub use license::{verify_license, Tier, set_license_secret, ensure_license_valid, set_license_key};
+#[cfg(feature = "legacy-hmac")]
+pub use license::set_license_secret;
+#[cfg(feature = "licensing")]
+pub use license::{
+    ensure_license_valid, license_info, reload_license, set_license_key, verify_license,
+    Capabilities, Tier,
+};
 
 // Re-export key functions to make them available directly at the crate root,
 // e.g., `bstseal_core::encode_parallel()`
-pub use encode::{decode_parallel, encode_parallel};
+#[cfg(feature = "std")]
+pub use encode::{decode_parallel, decode_parallel_with_limits, encode_parallel};
+pub use limits::{DecodeError, DecodeLimits};
+pub use stream::{decode_sequential, StreamDecoder, VerifyingDecoder};
 
-// The commented-out tests below can be re-enabled once the full pipeline is stable.
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
-    // use super::*;
+    use super::*;
 
-    // #[test]
-    // fn test_encode_parallel_basic() {
-    //     const TEST_DATA_SIZE: usize = 4096 * 3 + 123; // A little over 3 blocks
-    //     let mut original_data = Vec::with_capacity(TEST_DATA_SIZE);
-    //     for i in 0..TEST_DATA_SIZE {
-    //         // Simple compressible data
-    //         original_data.push((i % 16) as u8);
-    //     }
+    #[test]
+    fn test_encode_parallel_basic() {
+        const TEST_DATA_SIZE: usize = 4096 * 3 + 123; // A little over 3 blocks
+        let mut original_data = Vec::with_capacity(TEST_DATA_SIZE);
+        for i in 0..TEST_DATA_SIZE {
+            // Simple compressible data
+            original_data.push((i % 16) as u8);
+        }
 
-    //     let encoded_data = encode_parallel(&original_data).unwrap();
-    //     let decoded_data = decode_parallel(&encoded_data).unwrap();
+        let encoded_data = encode_parallel(&original_data).unwrap();
+        let decoded_data = decode_parallel(&encoded_data).unwrap();
 
-    //     assert_eq!(
-    //         decoded_data, original_data,
-    //         "Roundtrip failed for basic parallel encoding."
-    //     );
-    // }
+        assert_eq!(
+            decoded_data, original_data,
+            "Roundtrip failed for basic parallel encoding."
+        );
+    }
 
-    // #[test]
-    // fn test_encode_parallel_empty() {
-    //     let original_data: Vec<u8> = Vec::new();
-    //     let encoded_data = encode_parallel(&original_data).unwrap();
-    //     let decoded_data = decode_parallel(&encoded_data).unwrap();
-    //     assert!(
-    //         encoded_data.is_empty(),
-    //         "Encoding empty data should result in empty data"
-    //     );
-    //     assert!(
-    //         decoded_data.is_empty(),
-    //         "Decoding empty data should result in empty data"
-    //     );
-    // }
+    #[test]
+    fn test_encode_parallel_empty() {
+        let original_data: Vec<u8> = Vec::new();
+        let encoded_data = encode_parallel(&original_data).unwrap();
+        let decoded_data = decode_parallel(&encoded_data).unwrap();
+        assert!(
+            encoded_data.is_empty(),
+            "Encoding empty data should result in empty data"
+        );
+        assert!(
+            decoded_data.is_empty(),
+            "Decoding empty data should result in empty data"
+        );
+    }
 
-    // #[test]
-    // fn test_encode_parallel_single_small_block() {
-    //     let original_data: Vec<u8> = (0..100).map(|i| i as u8).collect(); // Less than a full block
-    //     let encoded_data = encode_parallel(&original_data).unwrap();
-    //     let decoded_data = decode_parallel(&encoded_data).unwrap();
+    #[test]
+    fn test_encode_parallel_single_small_block() {
+        let original_data: Vec<u8> = (0..100).map(|i| i as u8).collect(); // Less than a full block
+        let encoded_data = encode_parallel(&original_data).unwrap();
+        let decoded_data = decode_parallel(&encoded_data).unwrap();
 
-    //     assert_eq!(
-    //         decoded_data, original_data,
-    //         "Roundtrip failed for single small block."
-    //     );
-    // }
+        assert_eq!(
+            decoded_data, original_data,
+            "Roundtrip failed for single small block."
+        );
+    }
 }
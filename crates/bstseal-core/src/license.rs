@@ -1,25 +1,117 @@
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
-use hmac::{Hmac, Mac};
-use once_cell::sync::OnceCell;
-use sha2::Sha256;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use std::path::PathBuf;
+#[cfg(feature = "legacy-hmac")]
+use std::sync::RwLock;
 
-/// HMAC-SHA256 alias.
-type HmacSha256 = Hmac<Sha256>;
+pub mod manager;
+pub mod revocation;
+#[cfg(feature = "metering")]
+pub mod usage;
+pub use manager::{license_manager, LicenseManager};
+pub use revocation::{set_revocation_list, RevocationList};
 
-/// Available pricing tiers.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Domain separator prepended to `<uuid>.<tier>.<expires>` before signing.
+pub const LICENSE_CONTEXT: &[u8] = b"bstseal license v2\x00";
+
+/// Public half of the development issuer key in `testdata/dev-issuer.key`.
+/// Its secret is public, so builds that trust it accept anyone's licenses.
+pub const DEV_ISSUER_PUBLIC_KEY: [u8; 32] = parse_key(include_str!("../testdata/dev-issuer.pub"));
+
+/// Issuer key licenses are verified against: the compile-time variable
+/// `BSTSEAL_LICENSE_PUBKEY` (64 hex digits). Only test builds and builds with
+/// the `dev-issuer` feature may leave it unset, and then trust the
+/// development key.
+pub const ISSUER_PUBLIC_KEY: [u8; 32] = match option_env!("BSTSEAL_LICENSE_PUBKEY") {
+    Some(hex) => parse_key(hex),
+    None => DEV_ISSUER_PUBLIC_KEY,
+};
+
+/// Licenses from the development issuer, for tests and local builds that
+/// trust it (see the `dev-issuer` feature).
+#[cfg(any(test, feature = "dev-issuer"))]
+pub mod dev_issuer {
+    use super::{License, Tier};
+    use chrono::{Duration, Utc};
+    use ed25519_dalek::SigningKey;
+
+    /// Id of the licenses [`license`] issues.
+    pub const LICENSE_ID: &str = "123e4567-e89b-12d3-a456-426614174000";
+
+    /// Secret half of [`DEV_ISSUER_PUBLIC_KEY`](super::DEV_ISSUER_PUBLIC_KEY).
+    pub fn signing_key() -> SigningKey {
+        let hex = include_str!("../testdata/dev-issuer.key").trim();
+        let mut seed = [0u8; 32];
+        for (i, byte) in seed.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        SigningKey::from_bytes(&seed)
+    }
+
+    /// A key for [`LICENSE_ID`] of `tier`, expiring in `days` days.
+    pub fn license(tier: Tier, days: i64) -> String {
+        License {
+            id: LICENSE_ID.to_string(),
+            tier,
+            expires: Utc::now() + Duration::days(days),
+        }
+        .sign(&signing_key())
+    }
+}
+
+#[cfg(not(any(test, feature = "dev-issuer")))]
+const _: () = assert!(
+    option_env!("BSTSEAL_LICENSE_PUBKEY").is_some(),
+    "set BSTSEAL_LICENSE_PUBKEY to the license issuer's public key (64 hex digits), \
+     or enable the `dev-issuer` feature for a build that trusts the public development key"
+);
+
+/// Parses 64 hex digits (plus trailing whitespace) at compile time.
+const fn parse_key(hex: &str) -> [u8; 32] {
+    const fn nibble(c: u8) -> u8 {
+        match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            b'A'..=b'F' => c - b'A' + 10,
+            _ => panic!("license public key must be 64 hex digits"),
+        }
+    }
+    let hex = hex.as_bytes();
+    let mut len = hex.len();
+    while len > 0 && hex[len - 1].is_ascii_whitespace() {
+        len -= 1;
+    }
+    assert!(len == 64, "license public key must be 64 hex digits");
+    let mut key = [0u8; 32];
+    let mut i = 0;
+    while i < 32 {
+        key[i] = nibble(hex[2 * i]) << 4 | nibble(hex[2 * i + 1]);
+        i += 1;
+    }
+    key
+}
+
+/// Available pricing tiers, from least to most capable (see `PRICING.md`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Tier {
+    /// A tier this build does not know; treated like [`Tier::Solo`].
+    Unknown,
     Solo,
     Startup,
-    Unknown,
+    Enterprise,
 }
 
 impl Tier {
+    /// Tiers that can be purchased, cheapest first.
+    pub const PURCHASABLE: [Tier; 3] = [Tier::Solo, Tier::Startup, Tier::Enterprise];
+
     fn from_str(s: &str) -> Self {
         match s {
             "solo" => Tier::Solo,
             "startup" => Tier::Startup,
+            "enterprise" => Tier::Enterprise,
             _ => Tier::Unknown,
         }
     }
@@ -28,43 +120,182 @@ impl Tier {
         match self {
             Tier::Solo => "solo",
             Tier::Startup => "startup",
+            Tier::Enterprise => "enterprise",
             Tier::Unknown => "unknown",
         }
     }
+
+    /// What a license of this tier unlocks.
+    pub fn capabilities(self) -> Capabilities {
+        const GIB: u64 = 1 << 30;
+        match self {
+            Tier::Unknown | Tier::Solo => Capabilities {
+                tier: self,
+                max_threads: Some(4),
+                max_archive_size: Some(2 * GIB),
+                encryption: true,
+                signing: false,
+                high_ratio: false,
+            },
+            Tier::Startup => Capabilities {
+                tier: self,
+                max_threads: Some(32),
+                max_archive_size: Some(64 * GIB),
+                encryption: true,
+                signing: true,
+                high_ratio: true,
+            },
+            Tier::Enterprise => Capabilities {
+                tier: self,
+                max_threads: None,
+                max_archive_size: None,
+                encryption: true,
+                signing: true,
+                high_ratio: true,
+            },
+        }
+    }
+}
+
+impl core::fmt::Display for Tier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Optional codec features that depend on the license tier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Encryption,
+    Signing,
+    HighRatio,
+}
+
+impl core::fmt::Display for Feature {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Feature::Encryption => "encryption",
+            Feature::Signing => "signing",
+            Feature::HighRatio => "high-ratio compression",
+        })
+    }
+}
+
+/// Limits and features granted by a license tier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub tier: Tier,
+    /// Worker threads for parallel coding; `None` is unlimited.
+    pub max_threads: Option<usize>,
+    /// Largest archive that may be written, in bytes; `None` is unlimited.
+    pub max_archive_size: Option<u64>,
+    pub encryption: bool,
+    pub signing: bool,
+    /// Modes that trade speed for ratio, such as solid archives.
+    pub high_ratio: bool,
+}
+
+/// A request the licensed tier does not cover.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+pub enum CapabilityError {
+    #[error("{feature} requires the {required} tier (licensed: {tier})")]
+    Feature {
+        feature: Feature,
+        tier: Tier,
+        required: Tier,
+    },
+    #[error("archive of {size} bytes exceeds the {limit}-byte limit of the {tier} tier; {required} or above is required")]
+    ArchiveTooLarge {
+        size: u64,
+        limit: u64,
+        tier: Tier,
+        required: Tier,
+    },
+}
+
+impl Capabilities {
+    pub fn allows(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::Encryption => self.encryption,
+            Feature::Signing => self.signing,
+            Feature::HighRatio => self.high_ratio,
+        }
+    }
+
+    /// Fails with the cheapest tier that allows `feature` if this one does not.
+    pub fn require(&self, feature: Feature) -> Result<(), CapabilityError> {
+        if self.allows(feature) {
+            return Ok(());
+        }
+        Err(CapabilityError::Feature {
+            feature,
+            tier: self.tier,
+            required: Self::cheapest(|caps| caps.allows(feature)),
+        })
+    }
+
+    /// Fails if an archive of `size` bytes is above this tier's limit.
+    pub fn check_archive_size(&self, size: u64) -> Result<(), CapabilityError> {
+        match self.max_archive_size {
+            Some(limit) if size > limit => Err(CapabilityError::ArchiveTooLarge {
+                size,
+                limit,
+                tier: self.tier,
+                required: Self::cheapest(|caps| caps.max_archive_size.is_none_or(|l| size <= l)),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn cheapest(allowed: impl Fn(&Capabilities) -> bool) -> Tier {
+        Tier::PURCHASABLE
+            .into_iter()
+            .find(|tier| allowed(&tier.capabilities()))
+            .unwrap_or(Tier::Enterprise)
+    }
 }
 
 /// Errors that can occur during license verification.
-#[derive(Debug, thiserror::Error, Clone)]
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
 pub enum LicenseError {
     #[error("license string format is invalid")]
     Format,
     #[error("license signature mismatch")]
     Signature,
+    #[cfg(feature = "legacy-hmac")]
     #[error("license secret not configured (env LICENSE_SECRET or compile-time variable)")]
     MissingSecret,
+    #[error("legacy HMAC license keys are not accepted by this build")]
+    Legacy,
     #[error("license key not provided (env BSTSEAL_LICENSE or runtime call)")]
     MissingKey,
     #[error("license key expired")] Expired,
+    #[error("license key has been revoked")]
+    Revoked,
+    #[error("revocation list is unreadable, malformed or not signed by the issuer")]
+    RevocationList,
 }
 
-static RUNTIME_SECRET: OnceCell<String> = OnceCell::new();
-static RUNTIME_LICENSE: OnceCell<String> = OnceCell::new();
+#[cfg(feature = "legacy-hmac")]
+static RUNTIME_SECRET: RwLock<Option<String>> = RwLock::new(None);
 
 /// Allow libraries / binaries that link to bstseal-core to set the shared
-/// secret at runtime (e.g. via FFI).
-/// Returns `true` if the secret was set, `false` if it was already set before.
-/// Set shared HMAC secret at runtime.
-pub fn set_license_secret<S: Into<String>>(secret: S) -> bool {
-    RUNTIME_SECRET.set(secret.into()).is_ok()
+/// secret for legacy HMAC licenses at runtime (e.g. via FFI).
+/// Replaces any earlier secret and re-checks the active license.
+#[cfg(feature = "legacy-hmac")]
+pub fn set_license_secret<S: Into<String>>(secret: S) -> Result<Tier, LicenseError> {
+    *RUNTIME_SECRET.write().unwrap() = Some(secret.into());
+    license_manager().reload()
 }
 
 /// Obtain license secret from (in order):
 /// 1. Runtime call [`set_license_secret`]  
 /// 2. Environment variable `LICENSE_SECRET`  
 /// 3. Compile-time variable `LICENSE_SECRET` (provided via `cargo rustc --cfg`)
+#[cfg(feature = "legacy-hmac")]
 fn get_secret() -> Result<String, LicenseError> {
-    if let Some(s) = RUNTIME_SECRET.get() {
-        return Ok(s.clone());
+    if let Some(s) = RUNTIME_SECRET.read().unwrap().clone() {
+        return Ok(s);
     }
     if let Ok(env) = std::env::var("LICENSE_SECRET") {
         return Ok(env);
@@ -75,34 +306,175 @@ fn get_secret() -> Result<String, LicenseError> {
     Err(LicenseError::MissingSecret)
 }
 
-/// Get license string from runtime, env, or error.
-fn get_license() -> Result<String, LicenseError> {
-    use std::fs;
-    if let Some(k) = RUNTIME_LICENSE.get() {
-        return Ok(k.clone());
+/// Checks a legacy `base64url(HMAC_SHA256(data, LICENSE_SECRET))` signature.
+#[cfg(feature = "legacy-hmac")]
+fn verify_hmac(data: &str, sig: &[u8]) -> Result<(), LicenseError> {
+    use hmac::{Hmac, Mac};
+    let secret = get_secret()?;
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|_| LicenseError::MissingSecret)?;
+    mac.update(data.as_bytes());
+    mac.verify_slice(sig).map_err(|_| LicenseError::Signature)
+}
+
+#[cfg(not(feature = "legacy-hmac"))]
+fn verify_hmac(_data: &str, _sig: &[u8]) -> Result<(), LicenseError> {
+    Err(LicenseError::Legacy)
+}
+
+/// Checks an Ed25519 signature over [`LICENSE_CONTEXT`] `|| data` against
+/// `issuer`.
+fn verify_ed25519(issuer: &[u8; 32], data: &str, sig: &[u8]) -> Result<(), LicenseError> {
+    let sig = Signature::from_slice(sig).map_err(|_| LicenseError::Format)?;
+    if !signed_by(issuer, LICENSE_CONTEXT, data.as_bytes(), &sig) {
+        return Err(LicenseError::Signature);
     }
-    if let Ok(env) = std::env::var("BSTSEAL_LICENSE") {
-        return Ok(env);
+    Ok(())
+}
+
+/// Whether `sig` is the [`ISSUER_PUBLIC_KEY`] signature over `context || data`.
+fn issuer_signed(context: &[u8], data: &[u8], sig: &Signature) -> bool {
+    signed_by(&ISSUER_PUBLIC_KEY, context, data, sig)
+}
+
+fn signed_by(issuer: &[u8; 32], context: &[u8], data: &[u8], sig: &Signature) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(issuer) else {
+        return false;
+    };
+    let message = [context, data].concat();
+    key.verify_strict(&message, sig).is_ok()
+}
+
+/// Where the active license key was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LicenseSource {
+    /// [`set_license_key`].
+    Runtime,
+    /// The `BSTSEAL_LICENSE` environment variable.
+    Env,
+    /// A key file, normally [`license_path`].
+    File(PathBuf),
+}
+
+impl core::fmt::Display for LicenseSource {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LicenseSource::Runtime => f.write_str("runtime"),
+            LicenseSource::Env => f.write_str("env BSTSEAL_LICENSE"),
+            LicenseSource::File(path) => write!(f, "{}", path.display()),
+        }
     }
-    // fallback to ~/.bstseal/license
-    if let Some(home) = dirs::home_dir() {
-        let path = home.join(".bstseal").join("license");
-        if let Ok(data) = fs::read_to_string(path) {
+}
+
+/// File that `bstseal login` stores the key in: `~/.bstseal/license`.
+pub fn license_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("license"))
+}
+
+/// `~/.bstseal`, home of the stored key and other license state.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".bstseal"))
+}
+
+/// Get license string from `runtime`, env, or ~/.bstseal/license.
+fn get_license(runtime: Option<String>) -> Result<(String, LicenseSource), LicenseError> {
+    let env = std::env::var("BSTSEAL_LICENSE").ok();
+    find_license(runtime, env, license_path())
+}
+
+/// The runtime key, else the environment's, else a non-empty key file.
+fn find_license(
+    runtime: Option<String>,
+    env: Option<String>,
+    path: Option<PathBuf>,
+) -> Result<(String, LicenseSource), LicenseError> {
+    if let Some(k) = runtime {
+        return Ok((k, LicenseSource::Runtime));
+    }
+    if let Some(env) = env {
+        return Ok((env, LicenseSource::Env));
+    }
+    if let Some(path) = path {
+        if let Ok(data) = std::fs::read_to_string(&path) {
             let trimmed = data.trim();
             if !trimmed.is_empty() {
-                return Ok(trimmed.to_string());
+                return Ok((trimmed.to_string(), LicenseSource::File(path)));
             }
         }
     }
     Err(LicenseError::MissingKey)
 }
 
+/// Warn when a license expires in less than this many days.
+pub const EXPIRY_WARNING_DAYS: i64 = 14;
+
+/// The signed fields of a license key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct License {
+    pub id: String,
+    pub tier: Tier,
+    pub expires: DateTime<Utc>,
+}
 
-/// Verify a license string and return the encoded tier on success.
+impl License {
+    /// Whole days until expiry; negative once expired.
+    pub fn days_left(&self) -> i64 {
+        (self.expires - Utc::now()).num_days()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Utc::now() > self.expires
+    }
+
+    /// Still valid, but for less than [`EXPIRY_WARNING_DAYS`].
+    pub fn expires_soon(&self) -> bool {
+        !self.is_expired()
+            && self.expires - Utc::now() < chrono::Duration::days(EXPIRY_WARNING_DAYS)
+    }
+
+    /// Decodes the fields of a key without checking its signature.
+    pub fn decode(license: &str) -> Result<License, LicenseError> {
+        split_license(license).map(|(fields, _, _)| fields)
+    }
+
+    /// Issues the key for these fields, signed with the issuer's `key`.
+    /// `expires` is written to the second.
+    pub fn sign(&self, key: &SigningKey) -> String {
+        let expires = self
+            .expires
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let data = format!("{}.{}.{expires}", self.id, self.tier);
+        let sig = key.sign(&[LICENSE_CONTEXT, data.as_bytes()].concat());
+        format!("{data}.{}", URL_SAFE_NO_PAD.encode(sig.to_bytes()))
+    }
+}
+
+/// Checks the signature of a license key and returns its fields, whether or
+/// not it has expired.
 ///
-/// License format: `<uuid>.<tier>.<signature>` where
-/// `signature = base64url(HMAC_SHA256("<uuid>.<tier>", LICENSE_SECRET))`
-pub fn verify_license(license: &str) -> Result<Tier, LicenseError> {
+/// License format: `<uuid>.<tier>.<expires>.<signature>` where `expires` is
+/// RFC 3339 and `signature` is the base64url Ed25519 signature of
+/// [`LICENSE_CONTEXT`] followed by `<uuid>.<tier>.<expires>`. Legacy keys
+/// carrying a 32-byte HMAC-SHA256 instead are only accepted with the
+/// `legacy-hmac` feature.
+pub fn parse_license(license: &str) -> Result<License, LicenseError> {
+    parse_license_with(license, &ISSUER_PUBLIC_KEY)
+}
+
+/// Same as [`parse_license`], but checks Ed25519 signatures against `issuer`
+/// instead of [`ISSUER_PUBLIC_KEY`].
+pub fn parse_license_with(license: &str, issuer: &[u8; 32]) -> Result<License, LicenseError> {
+    let (fields, data, sig) = split_license(license)?;
+    match sig.len() {
+        ed25519_dalek::SIGNATURE_LENGTH => verify_ed25519(issuer, &data, &sig)?,
+        32 => verify_hmac(&data, &sig)?,
+        _ => return Err(LicenseError::Format),
+    }
+    Ok(fields)
+}
+
+/// Splits a key into its fields, the signed data and the raw signature.
+fn split_license(license: &str) -> Result<(License, String, Vec<u8>), LicenseError> {
     let parts: Vec<&str> = license.split('.').collect();
     if parts.len() < 4 {
         return Err(LicenseError::Format);
@@ -113,74 +485,315 @@ pub fn verify_license(license: &str) -> Result<Tier, LicenseError> {
     let expires_iso = parts[2..parts.len()-1].join(".");
 
     let data = format!("{uuid_part}.{tier_str}.{expires_iso}");
-    let secret = get_secret()?;
-
-    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
-        .map_err(|_| LicenseError::MissingSecret)?;
-    mac.update(data.as_bytes());
-    let expected_sig = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
-
-    if expected_sig != sig_provided {
-        return Err(LicenseError::Signature);
-    }
-
-    // expiry check
-    let expires_at = chrono::DateTime::parse_from_rfc3339(&expires_iso)
+    let sig = URL_SAFE_NO_PAD
+        .decode(sig_provided)
+        .map_err(|_| LicenseError::Format)?;
+    let expires = DateTime::parse_from_rfc3339(&expires_iso)
         .map_err(|_| LicenseError::Format)?
-        .with_timezone(&chrono::Utc);
-    if chrono::Utc::now() > expires_at {
+        .with_timezone(&Utc);
+    let fields = License {
+        id: uuid_part.to_string(),
+        tier: Tier::from_str(tier_str),
+        expires,
+    };
+    Ok((fields, data, sig))
+}
+
+/// Verify a license string and return the encoded tier on success. See
+/// [`parse_license`] for the format; [`check_license`] also consults the
+/// revocation list.
+pub fn verify_license(license: &str) -> Result<Tier, LicenseError> {
+    let license = parse_license(license)?;
+    if license.is_expired() {
         return Err(LicenseError::Expired);
     }
+    Ok(license.tier)
+}
 
-    Ok(Tier::from_str(tier_str))
+/// The active license key, where it came from and what it grants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LicenseInfo {
+    pub key: String,
+    pub source: LicenseSource,
+    pub license: License,
 }
 
-use once_cell::sync::Lazy;
-static LICENSE_CHECK: Lazy<Result<Tier, LicenseError>> = Lazy::new(|| {
-    let lic = get_license()?;
-    verify_license(&lic)
-});
+/// Looks up and checks the active license key, re-reading its source. Unlike
+/// [`ensure_license_valid`], an expired key is returned rather than rejected.
+pub fn license_info() -> Result<LicenseInfo, LicenseError> {
+    let (key, source) = get_license(license_manager().runtime_key())?;
+    let license = parse_license(&key)?;
+    Ok(LicenseInfo {
+        key,
+        source,
+        license,
+    })
+}
+
+/// Like [`verify_license`], but also rejects keys on the revocation list
+/// (see [`revocation`]). Returns the license fields.
+pub fn check_license(license: &str) -> Result<License, LicenseError> {
+    let license = parse_license(license)?;
+    if license.is_expired() {
+        return Err(LicenseError::Expired);
+    }
+    if revocation::load()?.is_some_and(|list| list.contains(&license.id)) {
+        return Err(LicenseError::Revoked);
+    }
+    Ok(license)
+}
 
 /// Ensure license was verified successfully; returns Tier or error.
+/// The result is cached by [`license_manager`] and re-checked periodically.
 pub fn ensure_license_valid() -> Result<Tier, LicenseError> {
-    (*LICENSE_CHECK).clone()
+    license_manager().check()
+}
+
+/// Capabilities of the verified license; fails like [`ensure_license_valid`].
+pub fn capabilities() -> Result<Capabilities, LicenseError> {
+    ensure_license_valid().map(Tier::capabilities)
 }
 
-/// Set license key at runtime.
-pub fn set_license_key<S: Into<String>>(key: S) -> bool {
-    RUNTIME_LICENSE.set(key.into()).is_ok()
+/// Fails unless a valid license exists and its tier allows `feature`.
+pub fn require(feature: Feature) -> anyhow::Result<()> {
+    capabilities()?.require(feature)?;
+    Ok(())
+}
+
+/// Set license key at runtime, replacing any earlier one, and re-check it.
+pub fn set_license_key<S: Into<String>>(key: S) -> Result<Tier, LicenseError> {
+    license_manager().set_key(Some(key.into()))
+}
+
+/// Re-reads the license key and revocation list now instead of waiting for
+/// the next periodic check.
+pub fn reload_license() -> Result<Tier, LicenseError> {
+    license_manager().reload()
+}
+
+/// Re-checks the license periodically on a background thread, see
+/// [`LicenseManager::watch`].
+#[cfg(not(target_arch = "wasm32"))]
+pub fn watch_license() {
+    license_manager().watch()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::dev_issuer::{signing_key as dev_issuer, LICENSE_ID as UUID};
     use super::*;
-    use hmac::Mac;
 
     /// helper to generate license inside tests
-    fn make_license(tier: &str, secret: &str) -> String {
-        use chrono::{Duration, Utc};
-        let uuid = "123e4567-e89b-12d3-a456-426614174000";
-        let expires = (Utc::now() + Duration::days(365)).to_rfc3339();
-        let data = format!("{uuid}.{tier}.{expires}");
-        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
-        mac.update(data.as_bytes());
-        let sig = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
-        format!("{data}.{sig}")
+    fn make_license(tier: &str, days: i64, key: &SigningKey) -> String {
+        License {
+            id: UUID.to_string(),
+            tier: Tier::from_str(tier),
+            expires: Utc::now() + chrono::Duration::days(days),
+        }
+        .sign(key)
     }
 
     #[test]
     fn verify_roundtrip() {
-        let secret = "abc";
-        set_license_secret(secret.to_string());
-        let lic = make_license("solo", secret);
+        assert_eq!(ISSUER_PUBLIC_KEY, dev_issuer().verifying_key().to_bytes());
+        let lic = make_license("solo", 365, &dev_issuer());
         let tier = verify_license(&lic).unwrap();
         assert_eq!(tier, Tier::Solo);
+        let lic = make_license("startup", -1, &dev_issuer());
+        assert!(matches!(verify_license(&lic), Err(LicenseError::Expired)));
+    }
+
+    #[test]
+    fn runtime_keys_win_over_env_and_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("license");
+        let file = || Some(path.clone());
+        let key = |s: &str| Some(s.to_string());
+        assert_eq!(
+            find_license(None, None, file()),
+            Err(LicenseError::MissingKey)
+        );
+        std::fs::write(&path, " \n").unwrap();
+        assert_eq!(
+            find_license(None, None, file()),
+            Err(LicenseError::MissingKey)
+        );
+        std::fs::write(&path, "from-file\n").unwrap();
+        assert_eq!(
+            find_license(None, None, file()),
+            Ok(("from-file".into(), LicenseSource::File(path.clone())))
+        );
+        assert_eq!(
+            find_license(None, key("from-env"), file()),
+            Ok(("from-env".into(), LicenseSource::Env))
+        );
+        assert_eq!(
+            find_license(key("runtime"), key("from-env"), file()),
+            Ok(("runtime".into(), LicenseSource::Runtime))
+        );
     }
 
     #[test]
     fn fail_on_wrong_sig() {
-        set_license_secret("abc".to_string());
         let lic = "bad.license.signature";
         assert!(verify_license(lic).is_err());
+
+        let other = SigningKey::from_bytes(&[7; 32]);
+        let lic = make_license("startup", 365, &other);
+        assert!(matches!(verify_license(&lic), Err(LicenseError::Signature)));
+
+        // Upgrading the tier invalidates the signature.
+        let lic = make_license("solo", 365, &dev_issuer()).replacen(".solo.", ".startup.", 1);
+        assert!(matches!(verify_license(&lic), Err(LicenseError::Signature)));
+    }
+
+    #[test]
+    fn issued_keys_roundtrip() {
+        let license = License {
+            id: "0b6f2c55-1a7e-4a55-9a0d-3f0e1c2b4d6a".to_string(),
+            tier: Tier::Enterprise,
+            expires: DateTime::parse_from_rfc3339("2031-05-06T07:08:09Z")
+                .unwrap()
+                .with_timezone(&Utc),
+        };
+        let key = license.sign(&dev_issuer());
+        assert!(key
+            .starts_with("0b6f2c55-1a7e-4a55-9a0d-3f0e1c2b4d6a.enterprise.2031-05-06T07:08:09Z."));
+        assert_eq!(verify_license(&key).unwrap(), Tier::Enterprise);
+        assert_eq!(parse_license(&key).unwrap(), license);
+
+        let other = SigningKey::from_bytes(&[3; 32]);
+        let foreign = license.sign(&other);
+        assert_eq!(License::decode(&foreign).unwrap(), license);
+        assert!(matches!(
+            parse_license(&foreign),
+            Err(LicenseError::Signature)
+        ));
+        let issuer = other.verifying_key().to_bytes();
+        assert_eq!(parse_license_with(&foreign, &issuer).unwrap(), license);
+        assert!(License::decode("not-a-key").is_err());
+    }
+
+    #[test]
+    fn capabilities_grow_with_tier() {
+        let solo = Tier::Solo.capabilities();
+        assert_eq!(Tier::Unknown.capabilities().max_threads, solo.max_threads);
+        assert!(solo.require(Feature::Encryption).is_ok());
+        let err = solo.require(Feature::Signing).unwrap_err();
+        assert_eq!(
+            err,
+            CapabilityError::Feature {
+                feature: Feature::Signing,
+                tier: Tier::Solo,
+                required: Tier::Startup,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "signing requires the startup tier (licensed: solo)"
+        );
+        assert!(Tier::Startup
+            .capabilities()
+            .require(Feature::HighRatio)
+            .is_ok());
+
+        assert!(solo.check_archive_size(1 << 30).is_ok());
+        let err = solo.check_archive_size(10 << 30).unwrap_err();
+        assert!(matches!(
+            err,
+            CapabilityError::ArchiveTooLarge {
+                required: Tier::Startup,
+                ..
+            }
+        ));
+        let err = Tier::Startup
+            .capabilities()
+            .check_archive_size(1 << 40)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            CapabilityError::ArchiveTooLarge {
+                required: Tier::Enterprise,
+                ..
+            }
+        ));
+        assert!(Tier::Enterprise
+            .capabilities()
+            .check_archive_size(u64::MAX)
+            .is_ok());
+        assert!(Tier::Solo < Tier::Startup && Tier::Startup < Tier::Enterprise);
+    }
+
+    #[test]
+    fn license_info_reports_source_and_expiry() {
+        let key = make_license("startup", 10, &dev_issuer());
+        assert_eq!(set_license_key(key.clone()), Ok(Tier::Startup));
+        let info = license_info().unwrap();
+        assert_eq!(info.key, key);
+        assert_eq!(info.source, LicenseSource::Runtime);
+        assert_eq!(info.license.id, UUID);
+        assert_eq!(info.license.tier, Tier::Startup);
+        assert_eq!(info.license.days_left(), 9);
+        assert!(info.license.expires_soon());
+
+        let expired = parse_license(&make_license("solo", -3, &dev_issuer())).unwrap();
+        assert!(expired.is_expired() && !expired.expires_soon());
+        assert_eq!(expired.days_left(), -3);
+        assert!(!parse_license(&make_license("solo", 30, &dev_issuer()))
+            .unwrap()
+            .expires_soon());
+    }
+
+    #[test]
+    fn check_license_rejects_revoked_keys() {
+        let signed = RevocationList::sign(["a", "b"], &dev_issuer());
+        assert_eq!(RevocationList::parse(&signed).unwrap().len(), 2);
+
+        // The list is process-wide: revoke an id no other test uses.
+        let revoked = "5d1c7e0a-3b9f-4c2d-8e6a-7f0b1c2d3e4f";
+        let list = RevocationList::sign([revoked], &dev_issuer());
+        assert!(set_revocation_list(list));
+        let key = License {
+            id: revoked.to_string(),
+            tier: Tier::Solo,
+            expires: Utc::now() + chrono::Duration::days(30),
+        }
+        .sign(&dev_issuer());
+        assert_eq!(verify_license(&key).unwrap(), Tier::Solo);
+        assert!(check_license(&make_license("solo", 30, &dev_issuer())).is_ok());
+        assert!(matches!(check_license(&key), Err(LicenseError::Revoked)));
+    }
+
+    fn make_legacy_license(secret: &str) -> String {
+        use hmac::{Hmac, Mac};
+        let expires = (chrono::Utc::now() + chrono::Duration::days(365)).to_rfc3339();
+        let data = format!("{UUID}.solo.{expires}");
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(data.as_bytes());
+        let sig = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{data}.{sig}")
+    }
+
+    #[cfg(feature = "legacy-hmac")]
+    #[test]
+    fn legacy_hmac_roundtrip() {
+        let _ = set_license_secret("abc".to_string());
+        assert_eq!(
+            verify_license(&make_legacy_license("abc")).unwrap(),
+            Tier::Solo
+        );
+        assert!(matches!(
+            verify_license(&make_legacy_license("other")),
+            Err(LicenseError::Signature)
+        ));
+    }
+
+    #[cfg(not(feature = "legacy-hmac"))]
+    #[test]
+    fn legacy_hmac_is_rejected() {
+        assert!(matches!(
+            verify_license(&make_legacy_license("abc")),
+            Err(LicenseError::Legacy)
+        ));
     }
 }
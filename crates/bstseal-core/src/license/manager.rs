@@ -0,0 +1,275 @@
+//! The active license, re-checked while the process runs.
+//!
+//! [`license_manager`] owns the runtime key and caches the result of the last
+//! check. The cache is refreshed from the key sources (runtime key,
+//! `BSTSEAL_LICENSE`, `~/.bstseal/license`) and the revocation list after
+//! [`LicenseManager::set_recheck_interval`] has passed or on
+//! [`LicenseManager::reload`], and expiry is checked on every call, so a
+//! renewed, expired or revoked key takes effect without a restart.
+//!
+//! Checks run when the codec is used, so listeners registered with
+//! [`LicenseManager::on_change`] hear nothing from a process that is idle.
+//! [`LicenseManager::watch`] re-checks on a background thread instead.
+
+use super::{check_license, get_license, License, LicenseError, Tier};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// How long a check result is reused before the key sources are read again.
+pub const DEFAULT_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+type Listener = Arc<dyn Fn(&Result<License, LicenseError>) + Send + Sync>;
+
+/// Holds the runtime license key and the cached result of checking the
+/// active key.
+pub struct LicenseManager {
+    runtime_key: RwLock<Option<String>>,
+    state: Mutex<State>,
+    listeners: Mutex<Vec<Listener>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    watching: AtomicBool,
+}
+
+struct State {
+    status: Result<License, LicenseError>,
+    // Wall-clock time: `Instant` is unavailable on wasm32-unknown-unknown.
+    checked_at: Option<DateTime<Utc>>,
+    interval: Duration,
+}
+
+static MANAGER: Lazy<LicenseManager> = Lazy::new(LicenseManager::new);
+
+/// The process-wide manager behind [`ensure_license_valid`](super::ensure_license_valid).
+pub fn license_manager() -> &'static LicenseManager {
+    &MANAGER
+}
+
+impl Default for LicenseManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LicenseManager {
+    pub fn new() -> Self {
+        Self {
+            runtime_key: RwLock::new(None),
+            state: Mutex::new(State {
+                status: Err(LicenseError::MissingKey),
+                checked_at: None,
+                interval: DEFAULT_RECHECK_INTERVAL,
+            }),
+            listeners: Mutex::new(Vec::new()),
+            #[cfg(not(target_arch = "wasm32"))]
+            watching: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the tier of the active license, re-reading the key sources if
+    /// the cached result is older than the recheck interval.
+    pub fn check(&self) -> Result<Tier, LicenseError> {
+        let cached = {
+            let state = self.state.lock().unwrap();
+            match state.checked_at {
+                Some(at)
+                    if (Utc::now() - at)
+                        .to_std()
+                        .is_ok_and(|age| age < state.interval) =>
+                {
+                    Some(state.status.clone())
+                }
+                _ => None,
+            }
+        };
+        match cached {
+            Some(Ok(license)) if license.is_expired() => {
+                self.update(Err(LicenseError::Expired)).map(|l| l.tier)
+            }
+            Some(status) => status.map(|license| license.tier),
+            None => self.reload(),
+        }
+    }
+
+    /// Reads and checks the active key now.
+    pub fn reload(&self) -> Result<Tier, LicenseError> {
+        let runtime = self.runtime_key.read().unwrap().clone();
+        let status = get_license(runtime).and_then(|(key, _)| check_license(&key));
+        self.update(status).map(|license| license.tier)
+    }
+
+    /// The result of the last check, without re-checking.
+    pub fn status(&self) -> Result<License, LicenseError> {
+        self.state.lock().unwrap().status.clone()
+    }
+
+    /// Replaces the runtime key (`None` falls back to env and file) and
+    /// re-checks.
+    pub fn set_key(&self, key: Option<String>) -> Result<Tier, LicenseError> {
+        *self.runtime_key.write().unwrap() = key;
+        self.reload()
+    }
+
+    pub(super) fn runtime_key(&self) -> Option<String> {
+        self.runtime_key.read().unwrap().clone()
+    }
+
+    pub fn set_recheck_interval(&self, interval: Duration) {
+        self.state.lock().unwrap().interval = interval;
+    }
+
+    /// Calls `listener` with the new result whenever a check changes it:
+    /// a key is renewed, replaced, expires or is revoked.
+    pub fn on_change(
+        &self,
+        listener: impl Fn(&Result<License, LicenseError>) + Send + Sync + 'static,
+    ) {
+        self.listeners.lock().unwrap().push(Arc::new(listener));
+    }
+
+    /// Re-checks the license every recheck interval on a background thread,
+    /// so [`on_change`](Self::on_change) listeners are called when a key
+    /// expires or is revoked even if nothing is encoded or decoded. Later
+    /// calls do nothing. Unavailable on wasm32, which has no threads.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch(&'static self) {
+        if self.watching.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        std::thread::Builder::new()
+            .name("bstseal-license".into())
+            .spawn(move || loop {
+                let interval = self.state.lock().unwrap().interval;
+                std::thread::sleep(interval);
+                let _ = self.check();
+            })
+            .expect("failed to spawn license watcher");
+    }
+
+    /// Stores `status` and notifies listeners if it differs from the last one.
+    fn update(&self, status: Result<License, LicenseError>) -> Result<License, LicenseError> {
+        let changed = {
+            let mut state = self.state.lock().unwrap();
+            let changed = state.checked_at.is_none() || state.status != status;
+            state.status = status.clone();
+            state.checked_at = Some(Utc::now());
+            changed
+        };
+        // Listeners run without any lock so they may call back in.
+        if changed {
+            let listeners = self.listeners.lock().unwrap().clone();
+            for listener in listeners {
+                listener(&status);
+            }
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::dev_issuer::signing_key as dev_issuer;
+    use super::*;
+    use std::sync::Arc;
+
+    fn key(tier: Tier, valid_for: chrono::Duration) -> String {
+        License {
+            id: "0b6f2c55-1a7e-4a55-9a0d-3f0e1c2b4d6a".to_string(),
+            tier,
+            expires: Utc::now() + valid_for,
+        }
+        .sign(&dev_issuer())
+    }
+
+    fn recording(manager: &LicenseManager) -> Arc<Mutex<Vec<Result<Tier, LicenseError>>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        manager.on_change(move |status| {
+            sink.lock()
+                .unwrap()
+                .push(status.as_ref().map(|l| l.tier).map_err(Clone::clone))
+        });
+        events
+    }
+
+    #[test]
+    fn keys_can_be_replaced_at_runtime() {
+        let manager = LicenseManager::new();
+        let events = recording(&manager);
+        let days = chrono::Duration::days(30);
+
+        assert_eq!(manager.set_key(Some(key(Tier::Solo, days))), Ok(Tier::Solo));
+        assert_eq!(manager.check(), Ok(Tier::Solo));
+        assert_eq!(
+            manager.set_key(Some("bad.key.x.y".into())),
+            Err(LicenseError::Format)
+        );
+        // A failed check is not cached forever.
+        assert_eq!(
+            manager.set_key(Some(key(Tier::Startup, days))),
+            Ok(Tier::Startup)
+        );
+        // Unchanged results do not notify.
+        assert_eq!(manager.reload(), Ok(Tier::Startup));
+        assert_eq!(
+            *events.lock().unwrap(),
+            [Ok(Tier::Solo), Err(LicenseError::Format), Ok(Tier::Startup)]
+        );
+    }
+
+    #[test]
+    fn listeners_may_call_back_in() {
+        let manager: &'static LicenseManager = Box::leak(Box::new(LicenseManager::new()));
+        let events = recording(manager);
+        manager.on_change(move |_| {
+            manager.on_change(|_| {});
+            let _ = manager.reload();
+            let _ = manager.check();
+        });
+        let days = chrono::Duration::days(30);
+        assert_eq!(manager.set_key(Some(key(Tier::Solo, days))), Ok(Tier::Solo));
+        assert_eq!(
+            manager.set_key(Some(key(Tier::Startup, days))),
+            Ok(Tier::Startup)
+        );
+        assert_eq!(*events.lock().unwrap(), [Ok(Tier::Solo), Ok(Tier::Startup)]);
+        assert_eq!(manager.listeners.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn watched_licenses_expire_without_being_used() {
+        let manager: &'static LicenseManager = Box::leak(Box::new(LicenseManager::new()));
+        let events = recording(manager);
+        let short = key(Tier::Startup, chrono::Duration::seconds(2));
+        assert_eq!(manager.set_key(Some(short)), Ok(Tier::Startup));
+        manager.set_recheck_interval(Duration::from_millis(50));
+        manager.watch();
+        manager.watch();
+        std::thread::sleep(Duration::from_millis(2500));
+        assert_eq!(
+            *events.lock().unwrap(),
+            [Ok(Tier::Startup), Err(LicenseError::Expired)]
+        );
+    }
+
+    #[test]
+    fn expiry_is_noticed_between_rechecks() {
+        let manager = LicenseManager::new();
+        manager.set_recheck_interval(Duration::from_secs(3600));
+        let events = recording(&manager);
+
+        // Expiry is signed in whole seconds, so this lapses within 1-2s.
+        let short = key(Tier::Startup, chrono::Duration::seconds(2));
+        assert_eq!(manager.set_key(Some(short)), Ok(Tier::Startup));
+        std::thread::sleep(Duration::from_millis(2100));
+        assert_eq!(manager.check(), Err(LicenseError::Expired));
+        assert_eq!(manager.status(), Err(LicenseError::Expired));
+        assert_eq!(
+            *events.lock().unwrap(),
+            [Ok(Tier::Startup), Err(LicenseError::Expired)]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Signed lists of revoked license ids.
+//!
+//! A list is UTF-8 text: one license UUID per line (blank lines and `#`
+//! comments are ignored), followed by a last line
+//! `signature: <base64url>` holding the issuer's Ed25519 signature of
+//! [`REVOCATION_CONTEXT`] followed by every byte before that line.
+//!
+//! ```text
+//! # bstseal revocation list
+//! 123e4567-e89b-12d3-a456-426614174000
+//! signature: 3q2-7w...
+//! ```
+//!
+//! The active list is the first of: [`set_revocation_list`], the file named
+//! by env `BSTSEAL_REVOCATION_LIST`, or `~/.bstseal/revocations`. Without a
+//! list nothing is revoked; a list file that does not verify fails every
+//! check, while a runtime list that does not verify is refused.
+
+use super::{config_dir, issuer_signed, license_manager, LicenseError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::RwLock;
+
+/// Domain separator prepended to the list body before signing.
+pub const REVOCATION_CONTEXT: &[u8] = b"bstseal revocations v1\x00";
+
+const SIGNATURE_PREFIX: &str = "signature:";
+
+static RUNTIME_LIST: RwLock<Option<String>> = RwLock::new(None);
+
+/// Revoked license ids, verified against the issuer key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RevocationList {
+    ids: BTreeSet<String>,
+}
+
+impl RevocationList {
+    /// Parses `text` and checks its signature.
+    pub fn parse(text: &str) -> Result<Self, LicenseError> {
+        let text = text.trim_end();
+        let start = text.rfind('\n').map_or(0, |i| i + 1);
+        let (body, last) = text.split_at(start);
+        let sig = last
+            .strip_prefix(SIGNATURE_PREFIX)
+            .and_then(|sig| URL_SAFE_NO_PAD.decode(sig.trim()).ok())
+            .and_then(|sig| Signature::from_slice(&sig).ok())
+            .ok_or(LicenseError::RevocationList)?;
+        if !issuer_signed(REVOCATION_CONTEXT, body.as_bytes(), &sig) {
+            return Err(LicenseError::RevocationList);
+        }
+        let ids = body
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_owned)
+            .collect();
+        Ok(Self { ids })
+    }
+
+    /// Reads and verifies the list in `path`.
+    pub fn read(path: &Path) -> Result<Self, LicenseError> {
+        let text = std::fs::read_to_string(path).map_err(|_| LicenseError::RevocationList)?;
+        Self::parse(&text)
+    }
+
+    /// Writes a list revoking `ids`, signed with the issuer's `key`.
+    pub fn sign<'a>(ids: impl IntoIterator<Item = &'a str>, key: &SigningKey) -> String {
+        let mut body = String::from("# bstseal revocation list\n");
+        for id in ids {
+            body.push_str(id);
+            body.push('\n');
+        }
+        let sig = key.sign(&[REVOCATION_CONTEXT, body.as_bytes()].concat());
+        format!(
+            "{body}{SIGNATURE_PREFIX} {}\n",
+            URL_SAFE_NO_PAD.encode(sig.to_bytes())
+        )
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// Sets the revocation list text at runtime (e.g. fetched by the host
+/// application), replacing any earlier one, and re-checks the active license.
+/// Returns `false` and keeps the earlier list if this one does not verify,
+/// so that a bad download does not fail every license check.
+pub fn set_revocation_list<S: Into<String>>(list: S) -> bool {
+    let list = list.into();
+    if RevocationList::parse(&list).is_err() {
+        return false;
+    }
+    *RUNTIME_LIST.write().unwrap() = Some(list);
+    let _ = license_manager().reload();
+    true
+}
+
+/// The active list, or `None` if there is none.
+pub(super) fn load() -> Result<Option<RevocationList>, LicenseError> {
+    if let Some(text) = RUNTIME_LIST.read().unwrap().as_deref() {
+        return RevocationList::parse(text).map(Some);
+    }
+    if let Some(path) = std::env::var_os("BSTSEAL_REVOCATION_LIST") {
+        return RevocationList::read(Path::new(&path)).map(Some);
+    }
+    match config_dir().map(|dir| dir.join("revocations")) {
+        Some(path) if path.exists() => RevocationList::read(&path).map(Some),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::dev_issuer::signing_key as dev_issuer;
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../../testdata/revocations.txt");
+    const REVOKED: &str = "123e4567-e89b-12d3-a456-426614174000";
+
+    #[test]
+    fn fixture_lists_revoked_ids() {
+        let list = RevocationList::parse(FIXTURE).unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list.contains(REVOKED));
+        assert!(!list.contains("11111111-2222-3333-4444-555555555555"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("revocations");
+        std::fs::write(&path, FIXTURE).unwrap();
+        assert_eq!(RevocationList::read(&path).unwrap(), list);
+        assert!(matches!(
+            RevocationList::read(&dir.path().join("missing")),
+            Err(LicenseError::RevocationList)
+        ));
+    }
+
+    #[test]
+    fn tampered_or_foreign_lists_are_rejected() {
+        // Un-revoking a key breaks the signature.
+        let tampered = FIXTURE.replace(REVOKED, "# removed");
+        assert!(matches!(
+            RevocationList::parse(&tampered),
+            Err(LicenseError::RevocationList)
+        ));
+        let unsigned = FIXTURE
+            .lines()
+            .filter(|l| !l.starts_with("signature"))
+            .collect::<Vec<_>>();
+        assert!(RevocationList::parse(&unsigned.join("\n")).is_err());
+        let foreign = RevocationList::sign([REVOKED], &SigningKey::from_bytes(&[9; 32]));
+        assert!(RevocationList::parse(&foreign).is_err());
+    }
+
+    #[test]
+    fn rejected_runtime_lists_keep_the_previous_one() {
+        // The list is process-wide: revoke an id no other test uses.
+        let revoked = "e8a4f2b6-0c1d-4e3f-9a5b-6c7d8e9f0a1b";
+        let list = RevocationList::sign([revoked], &dev_issuer());
+        assert!(set_revocation_list(list.as_str()));
+        assert!(!set_revocation_list(list.replace(revoked, "# removed")));
+        assert!(!set_revocation_list("not a list"));
+        assert!(load().unwrap().unwrap().contains(revoked));
+    }
+}
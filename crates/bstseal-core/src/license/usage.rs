@@ -0,0 +1,405 @@
+//! Local usage metering per license.
+//!
+//! With the `metering` feature every successful `encode_parallel` and
+//! `decode_parallel` call appends a record to a ledger, by default
+//! `~/.bstseal/usage.ledger` (env `BSTSEAL_USAGE_LEDGER` overrides it).
+//! Applications that code many pieces for one user-visible operation, such as
+//! packing an archive, wrap it in a [`batch`] to write one record per
+//! operation instead. Each line holds the time, license id, operation and
+//! original byte count, and a Blake3 hash chained over the previous line's
+//! hash:
+//!
+//! ```text
+//! 2026-10-18T09:30:00Z 123e4567-e89b-12d3-a456-426614174000 encode 1048576 9f2c...
+//! ```
+//!
+//! Editing, reordering or removing a line (other than the last) breaks the
+//! chain, which [`Ledger::records`] reports as [`UsageError::Tampered`].
+//! Nothing is sent anywhere; the ledger is only read by
+//! `bstseal license usage`.
+
+use super::{config_dir, license_manager};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Blake3 key-derivation context for the hash chain.
+const CHAIN_CONTEXT: &str = "bstseal usage ledger v1";
+
+/// What a record counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Encode,
+    Decode,
+}
+
+impl Operation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operation::Encode => "encode",
+            Operation::Decode => "decode",
+        }
+    }
+}
+
+impl core::fmt::Display for Operation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Errors reading or writing the usage ledger.
+#[derive(Debug, thiserror::Error)]
+pub enum UsageError {
+    #[error("usage ledger I/O failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("usage ledger line {line} is malformed")]
+    Malformed { line: usize },
+    #[error("usage ledger hash chain is broken at line {line}")]
+    Tampered { line: usize },
+    #[error("usage ledger does not end in a complete record")]
+    Incomplete,
+}
+
+/// One ledger line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub time: DateTime<Utc>,
+    pub license: String,
+    pub operation: Operation,
+    /// Original (uncompressed) bytes.
+    pub bytes: u64,
+    /// Chain hash over the previous record's hash and this record.
+    pub hash: blake3::Hash,
+}
+
+impl Record {
+    /// The line without its hash.
+    fn body(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            self.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.license,
+            self.operation,
+            self.bytes
+        )
+    }
+
+    fn chain(prev: &blake3::Hash, body: &str) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new_derive_key(CHAIN_CONTEXT);
+        hasher.update(prev.as_bytes());
+        hasher.update(body.as_bytes());
+        hasher.finalize()
+    }
+
+    fn parse(line: &str, number: usize) -> Result<Self, UsageError> {
+        let malformed = || UsageError::Malformed { line: number };
+        let fields: Vec<&str> = line.split(' ').collect();
+        let [time, license, operation, bytes, hash] = fields[..] else {
+            return Err(malformed());
+        };
+        Ok(Record {
+            time: DateTime::parse_from_rfc3339(time)
+                .map_err(|_| malformed())?
+                .with_timezone(&Utc),
+            license: license.to_owned(),
+            operation: match operation {
+                "encode" => Operation::Encode,
+                "decode" => Operation::Decode,
+                _ => return Err(malformed()),
+            },
+            bytes: bytes.parse().map_err(|_| malformed())?,
+            hash: blake3::Hash::from_hex(hash).map_err(|_| malformed())?,
+        })
+    }
+}
+
+/// Totals for one license.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub encodes: u64,
+    pub decodes: u64,
+    pub bytes_encoded: u64,
+    pub bytes_decoded: u64,
+}
+
+impl Usage {
+    pub fn operations(&self) -> u64 {
+        self.encodes + self.decodes
+    }
+}
+
+/// An append-only usage ledger file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ledger {
+    path: PathBuf,
+}
+
+impl Ledger {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `BSTSEAL_USAGE_LEDGER`, else `~/.bstseal/usage.ledger`.
+    pub fn locate() -> Option<Self> {
+        if let Some(path) = std::env::var_os("BSTSEAL_USAGE_LEDGER") {
+            return Some(Self::new(path));
+        }
+        config_dir().map(|dir| Self::new(dir.join("usage.ledger")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends a record, holding an exclusive lock on the file so that
+    /// concurrent processes keep the chain intact.
+    pub fn append(
+        &self,
+        license: &str,
+        operation: Operation,
+        bytes: u64,
+    ) -> Result<Record, UsageError> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        file.lock()?;
+        let prev = last_hash(&mut file)?;
+        let mut record = Record {
+            time: Utc::now(),
+            license: license.to_owned(),
+            operation,
+            bytes,
+            hash: prev,
+        };
+        let body = record.body();
+        record.hash = Record::chain(&prev, &body);
+        file.write_all(format!("{body} {}\n", record.hash.to_hex()).as_bytes())?;
+        Ok(record)
+    }
+
+    /// Reads every record, checking the hash chain. A missing ledger has no
+    /// records.
+    pub fn records(&self) -> Result<Vec<Record>, UsageError> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut prev = blake3::Hash::from_bytes([0; 32]);
+        let mut records = Vec::new();
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let record = Record::parse(&line?, n + 1)?;
+            if Record::chain(&prev, &record.body()) != record.hash {
+                return Err(UsageError::Tampered { line: n + 1 });
+            }
+            prev = record.hash;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Verified totals per license id.
+    pub fn summary(&self) -> Result<BTreeMap<String, Usage>, UsageError> {
+        let mut totals = BTreeMap::<String, Usage>::new();
+        for record in self.records()? {
+            let usage = totals.entry(record.license).or_default();
+            match record.operation {
+                Operation::Encode => {
+                    usage.encodes += 1;
+                    usage.bytes_encoded += record.bytes;
+                }
+                Operation::Decode => {
+                    usage.decodes += 1;
+                    usage.bytes_decoded += record.bytes;
+                }
+            }
+        }
+        Ok(totals)
+    }
+}
+
+/// Hash of the last line, or the all-zero chain start for an empty file.
+fn last_hash(file: &mut File) -> Result<blake3::Hash, UsageError> {
+    // Records are well under 256 bytes, so the tail holds the last one.
+    let len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(len.saturating_sub(256)))?;
+    let mut tail = String::new();
+    file.read_to_string(&mut tail)?;
+    match tail.lines().last() {
+        None => Ok(blake3::Hash::from_bytes([0; 32])),
+        Some(line) => line
+            .rsplit(' ')
+            .next()
+            .and_then(|hash| blake3::Hash::from_hex(hash).ok())
+            .ok_or(UsageError::Incomplete),
+    }
+}
+
+thread_local! {
+    /// Totals of the open [`batch`] on this thread.
+    static BATCH: RefCell<Option<Vec<(Operation, u64)>>> = const { RefCell::new(None) };
+}
+
+/// Open batch of records; see [`batch`].
+#[must_use = "the batch is recorded when dropped"]
+pub struct Batch {
+    outer: bool,
+}
+
+/// Collects what [`record`] is called with on this thread until the returned
+/// guard is dropped, then records one total per operation. Batches opened
+/// inside another one join it.
+pub fn batch() -> Batch {
+    BATCH.with(|batch| {
+        let mut batch = batch.borrow_mut();
+        let outer = batch.is_none();
+        if outer {
+            *batch = Some(Vec::new());
+        }
+        Batch { outer }
+    })
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        if !self.outer {
+            return;
+        }
+        let totals = BATCH.with(|batch| batch.borrow_mut().take());
+        for (operation, bytes) in totals.unwrap_or_default() {
+            append(operation, bytes);
+        }
+    }
+}
+
+/// Records the totals of the open [`batch`] on this thread now and starts
+/// them over, for callers that exit the process without dropping the batch.
+pub fn flush() {
+    let totals = BATCH.with(|batch| batch.borrow_mut().as_mut().map(std::mem::take));
+    for (operation, bytes) in totals.unwrap_or_default() {
+        append(operation, bytes);
+    }
+}
+
+/// Records `bytes` of `operation` against the active license in the default
+/// ledger, or adds them to the open [`batch`]. Does nothing without a valid
+/// license; ledger failures are logged, not returned, so metering never fails
+/// the operation itself.
+pub fn record(operation: Operation, bytes: u64) {
+    let batched = BATCH.with(|batch| {
+        let mut batch = batch.borrow_mut();
+        let Some(totals) = batch.as_mut() else {
+            return false;
+        };
+        match totals.iter_mut().find(|(op, _)| *op == operation) {
+            Some((_, total)) => *total += bytes,
+            None => totals.push((operation, bytes)),
+        }
+        true
+    });
+    if !batched {
+        append(operation, bytes);
+    }
+}
+
+fn append(operation: Operation, bytes: u64) {
+    let Ok(license) = license_manager().status() else {
+        return;
+    };
+    let Some(ledger) = Ledger::locate() else {
+        return;
+    };
+    if let Err(e) = ledger.append(&license.id, operation, bytes) {
+        log::warn!("{}: {e}", ledger.path().display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "123e4567-e89b-12d3-a456-426614174000";
+
+    #[test]
+    fn ledger_sums_usage_per_license() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = Ledger::new(dir.path().join("nested/usage.ledger"));
+        assert!(ledger.summary().unwrap().is_empty());
+
+        ledger.append(ID, Operation::Encode, 1000).unwrap();
+        ledger.append(ID, Operation::Decode, 400).unwrap();
+        ledger.append(ID, Operation::Encode, 24).unwrap();
+        ledger.append("other", Operation::Decode, 7).unwrap();
+
+        assert_eq!(ledger.records().unwrap().len(), 4);
+        let summary = ledger.summary().unwrap();
+        let usage = summary[ID];
+        assert_eq!(
+            (usage.encodes, usage.decodes, usage.operations()),
+            (2, 1, 3)
+        );
+        assert_eq!((usage.bytes_encoded, usage.bytes_decoded), (1024, 400));
+        assert_eq!(summary["other"].bytes_decoded, 7);
+    }
+
+    #[test]
+    fn batches_total_each_operation() {
+        let totals = || BATCH.with(|batch| batch.borrow().clone());
+        let outer = batch();
+        record(Operation::Encode, 10);
+        record(Operation::Decode, 3);
+        {
+            let _inner = batch();
+            record(Operation::Encode, 5);
+        }
+        assert_eq!(
+            totals(),
+            Some(vec![(Operation::Encode, 15), (Operation::Decode, 3)])
+        );
+        flush();
+        assert_eq!(totals(), Some(Vec::new()));
+        record(Operation::Decode, 4);
+        assert_eq!(totals(), Some(vec![(Operation::Decode, 4)]));
+        drop(outer);
+        assert_eq!(totals(), None);
+    }
+
+    #[test]
+    fn edited_or_removed_lines_break_the_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = Ledger::new(dir.path().join("usage.ledger"));
+        for bytes in [10, 20, 30] {
+            ledger.append(ID, Operation::Encode, bytes).unwrap();
+        }
+        let text = std::fs::read_to_string(ledger.path()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        let edited = text.replacen(" encode 20 ", " encode 2 ", 1);
+        std::fs::write(ledger.path(), edited).unwrap();
+        assert!(matches!(
+            ledger.records(),
+            Err(UsageError::Tampered { line: 2 })
+        ));
+
+        std::fs::write(ledger.path(), format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(matches!(
+            ledger.records(),
+            Err(UsageError::Tampered { line: 2 })
+        ));
+
+        std::fs::write(ledger.path(), "garbage\n").unwrap();
+        assert!(matches!(
+            ledger.records(),
+            Err(UsageError::Malformed { line: 1 })
+        ));
+    }
+}
@@ -0,0 +1,59 @@
+//! Resource limits and typed errors for decoding untrusted input.
+//!
+//! Every size read from an encoded stream is checked against [`DecodeLimits`]
+//! *before* memory is reserved for it, so a tiny hostile input cannot request
+//! a huge allocation. Structural problems (bad code tables, short bitstreams,
+//! garbage after a block) are reported as [`DecodeError`] values, which can be
+//! recovered from an `anyhow::Error` with `downcast_ref::<DecodeError>()`.
+
+use thiserror::Error;
+
+/// Default upper bound for the decoded size of a single block (16 MiB).
+///
+/// Blocks produced by [`crate::encode::encode_parallel`] never exceed
+/// [`crate::block_coder::BLOCK_SIZE`]; the larger default leaves room for
+/// callers that use [`crate::block_coder::encode_block`] directly.
+pub const DEFAULT_MAX_BLOCK_SIZE: usize = 16 << 20;
+
+/// Limits applied while decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum total number of decoded bytes.
+    pub max_output_size: usize,
+    /// Maximum number of decoded bytes in a single block.
+    pub max_block_size: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_output_size: usize::MAX,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+        }
+    }
+}
+
+/// Reasons an encoded block or stream is rejected.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("block is empty")]
+    EmptyBlock,
+    #[error("unknown block type: {0}")]
+    UnknownBlockType(u8),
+    #[error("malformed varint")]
+    BadVarint,
+    #[error("block length {len} exceeds remaining input ({remaining} bytes)")]
+    IncompleteBlock { len: u64, remaining: usize },
+    #[error("block size {size} exceeds limit of {limit} bytes")]
+    BlockTooLarge { size: u64, limit: usize },
+    #[error("decoded output exceeds limit of {limit} bytes")]
+    OutputTooLarge { limit: usize },
+    #[error("invalid Huffman code table: {0}")]
+    InvalidCodeTable(&'static str),
+    #[error("bitstream ended after {decoded} of {expected} symbols")]
+    Truncated { decoded: usize, expected: usize },
+    #[error("padding bits at end of bitstream are not zero")]
+    NonZeroPadding,
+    #[error("{0} bytes of trailing data after bitstream")]
+    TrailingData(usize),
+}
@@ -0,0 +1,399 @@
+//! Verified random access through a Bao outboard tree.
+//!
+//! A plain frame's footer is `blake3(header ‖ payload)`, which is also the
+//! root of the Bao tree over the same bytes. [`outboard`] stores the inner
+//! tree nodes in a separate file (the standard Bao outboard encoding), and
+//! [`decode_range`] uses it to fetch and check only the 1 KiB chunks that
+//! hold the blocks a byte range touches — a client can read a few ranges of
+//! a large sealed file from a CDN without downloading the rest.
+//!
+//! Range access needs a frame sealed with
+//! [`SealOptions::block_index`](crate::seal::SealOptions::block_index) so
+//! blocks can be located from the header alone. Keyed frames are not
+//! supported: their footer is a MAC, not a tree root.
+
+#[cfg(feature = "encryption")]
+use crate::crypto::{BlockCipher, CryptoError};
+#[cfg(feature = "encryption")]
+use crate::frame::KEY_CHECK_SIZE;
+use crate::frame::{FrameError, FrameHeader, FLAG_ENCRYPTED, FLAG_KEYED};
+use crate::integrity::{self, Checksum, IntegrityError, HASH_SIZE};
+use crate::limits::DecodeError;
+use crate::seal::OpenOptions;
+use crate::{block_coder, utils};
+use anyhow::Result;
+use bao::decode::SliceDecoder;
+use bao::encode::SliceExtractor;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use thiserror::Error;
+
+/// Bytes read for the header on the first try; doubled until it parses.
+const HEADER_PROBE: u64 = 256;
+
+/// Reasons a frame cannot be read through its outboard tree.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum OutboardError {
+    #[error("keyed frames have no Bao root; their footer is a MAC")]
+    Keyed,
+    #[error("{0} footers are not a Bao root; only Blake3 is")]
+    UnsupportedChecksum(Checksum),
+    #[error("frame has no block index")]
+    NotIndexed,
+    #[error("range {start}..{end} is outside the decoded size of {len} bytes")]
+    OutOfRange { start: u64, end: u64, len: u64 },
+    #[error("data or outboard does not match the root hash")]
+    Corrupt,
+    #[error("block index does not match the payload")]
+    BadIndex,
+}
+
+/// Builds the Bao outboard tree for a sealed frame. The frame is verified
+/// first; a signature trailer is ignored.
+pub fn outboard(sealed: &[u8]) -> Result<Vec<u8>> {
+    #[cfg(feature = "signing")]
+    let sealed = crate::signature::strip_signature(sealed);
+    let (header, _) = integrity::verify_frame(sealed, None).map_err(|e| match e {
+        IntegrityError::KeyRequired => OutboardError::Keyed.into(),
+        e => anyhow::Error::from(e),
+    })?;
+    let checksum = header.map_or(Checksum::Blake3, |h| h.checksum());
+    if checksum != Checksum::Blake3 {
+        return Err(OutboardError::UnsupportedChecksum(checksum).into());
+    }
+    let (tree, _root) = bao::encode::outboard(&sealed[..sealed.len() - HASH_SIZE]);
+    Ok(tree)
+}
+
+/// Reads the root hash from the end of a sealed frame. Any suffix that
+/// includes the footer (and signature trailer, if present) is enough.
+pub fn root(tail: &[u8]) -> Result<blake3::Hash> {
+    #[cfg(feature = "signing")]
+    let tail = crate::signature::strip_signature(tail);
+    let start = tail
+        .len()
+        .checked_sub(HASH_SIZE)
+        .ok_or(IntegrityError::TooSmall)?;
+    let footer: [u8; HASH_SIZE] = tail[start..].try_into().expect("footer size");
+    Ok(footer.into())
+}
+
+/// Decodes `range` of the original data from an indexed frame, reading and
+/// verifying only the header and the blocks the range touches.
+///
+/// `root` must come from a trusted source, e.g. the footer of a frame
+/// whose signature was checked. `sealed` is the frame (a trailing footer
+/// or signature is not read); `outboard` is the tree from [`outboard`].
+/// Encrypted frames need `options.decryption`.
+pub fn decode_range<R: Read + Seek, O: Read + Seek>(
+    mut sealed: R,
+    mut outboard: O,
+    root: &blake3::Hash,
+    range: Range<u64>,
+    options: &OpenOptions,
+) -> Result<Vec<u8>> {
+    #[cfg(all(feature = "licensing", not(any(test, fuzzing))))]
+    crate::license::ensure_license_valid()?;
+    if options.mac_key.is_some() {
+        return Err(OutboardError::Keyed.into());
+    }
+
+    // The tree starts with the content length; slices that disagree with it
+    // fail verification.
+    let mut len = [0u8; 8];
+    outboard.seek(SeekFrom::Start(0))?;
+    outboard.read_exact(&mut len)?;
+    let content_len = u64::from_le_bytes(len);
+    let mut fetch = |start: u64, len: u64| -> Result<Vec<u8>> {
+        sealed.seek(SeekFrom::Start(0))?;
+        outboard.seek(SeekFrom::Start(0))?;
+        let extractor = SliceExtractor::new_outboard(&mut sealed, &mut outboard, start, len);
+        let mut out = Vec::new();
+        SliceDecoder::new(extractor, root, start, len)
+            .read_to_end(&mut out)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                    OutboardError::Corrupt.into()
+                }
+                _ => anyhow::Error::from(e),
+            })?;
+        Ok(out)
+    };
+
+    let mut probe = HEADER_PROBE.min(content_len);
+    let (header_bytes, header, header_len) = loop {
+        let bytes = fetch(0, probe)?;
+        match FrameHeader::parse(&bytes) {
+            Ok(Some((header, len))) => break (bytes, header, len),
+            Err(FrameError::Truncated) if probe < content_len => {
+                probe = (probe * 2).min(content_len);
+            }
+            Ok(None) => return Err(OutboardError::NotIndexed.into()),
+            Err(e) => return Err(IntegrityError::from(e).into()),
+        }
+    };
+    if header.has(FLAG_KEYED) {
+        return Err(OutboardError::Keyed.into());
+    }
+    if header.checksum() != Checksum::Blake3 {
+        return Err(OutboardError::UnsupportedChecksum(header.checksum()).into());
+    }
+    let index = header.index.as_ref().ok_or(OutboardError::NotIndexed)?;
+    if range.start > range.end || range.end > index.decoded_len {
+        return Err(OutboardError::OutOfRange {
+            start: range.start,
+            end: range.end,
+            len: index.decoded_len,
+        }
+        .into());
+    }
+    if range.end - range.start > options.limits.max_output_size as u64 {
+        return Err(DecodeError::OutputTooLarge {
+            limit: options.limits.max_output_size,
+        }
+        .into());
+    }
+
+    #[cfg(feature = "encryption")]
+    let cipher = match (&header.encryption, options.decryption) {
+        (Some(params), Some(secret)) => {
+            let aad = &header_bytes[..header_len - KEY_CHECK_SIZE];
+            Some(BlockCipher::for_opening(secret, params, aad)?)
+        }
+        (Some(_), None) => return Err(CryptoError::SecretRequired.into()),
+        (None, Some(_)) => return Err(CryptoError::NotEncrypted.into()),
+        (None, None) => None,
+    };
+    #[cfg(not(feature = "encryption"))]
+    let _ = header_bytes;
+    #[cfg(not(feature = "encryption"))]
+    if header.has(FLAG_ENCRYPTED) {
+        anyhow::bail!("frame is encrypted, but encryption support is not compiled in");
+    }
+    if range.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Every block but the last decodes to `block_size` bytes; an encrypted
+    // frame of empty input still holds one (empty) record.
+    let block_size = u64::from(index.block_size);
+    if block_size == 0 {
+        return Err(OutboardError::BadIndex.into());
+    }
+    let blocks = index.decoded_len.div_ceil(block_size);
+    let records = if header.has(FLAG_ENCRYPTED) {
+        blocks.max(1)
+    } else {
+        blocks
+    };
+    if index.offsets.len() as u64 != records {
+        return Err(OutboardError::BadIndex.into());
+    }
+    let first = (range.start / block_size) as usize;
+    let last = ((range.end - 1) / block_size) as usize;
+    let payload_len = content_len
+        .checked_sub(header_len as u64)
+        .ok_or(OutboardError::BadIndex)?;
+    let record = |i| index.record(i, payload_len).ok_or(OutboardError::BadIndex);
+    let start = record(first)?.start;
+    let end = record(last)?.end;
+    let bytes = fetch(header_len as u64 + start, end - start)?;
+    if bytes.len() as u64 != end - start {
+        return Err(OutboardError::Corrupt.into());
+    }
+
+    let mut out = Vec::new();
+    for i in first..=last {
+        let r = record(i)?;
+        let body = &bytes[(r.start - start) as usize..(r.end - start) as usize];
+        let (len, varint_len) = utils::read_varint_u64(body).ok_or(DecodeError::BadVarint)?;
+        if varint_len as u64 + len != body.len() as u64 {
+            return Err(OutboardError::BadIndex.into());
+        }
+        let block = &body[varint_len..];
+        #[cfg(feature = "encryption")]
+        let opened;
+        #[cfg(feature = "encryption")]
+        let block = match &cipher {
+            Some(cipher) => {
+                opened = cipher.open_block(i as u64, i as u64 + 1 == records, block)?;
+                &opened[..]
+            }
+            None => block,
+        };
+        let decoded = block_coder::decode_block_with_limits(block, &options.limits)?;
+        let expected = block_size.min(index.decoded_len - i as u64 * block_size);
+        if decoded.len() as u64 != expected {
+            return Err(OutboardError::BadIndex.into());
+        }
+        out.extend_from_slice(&decoded);
+    }
+    let skip = (range.start - first as u64 * block_size) as usize;
+    out.drain(..skip);
+    out.truncate((range.end - range.start) as usize);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_coder::BLOCK_SIZE;
+    use crate::seal::{seal, SealOptions};
+    use std::io::Cursor;
+
+    fn sample() -> Vec<u8> {
+        (0..BLOCK_SIZE * 5 + 123)
+            .map(|i| (i * 7 % 251) as u8 ^ (i / 300) as u8)
+            .collect()
+    }
+
+    fn indexed(data: &[u8]) -> Vec<u8> {
+        let options = SealOptions {
+            block_index: true,
+            ..Default::default()
+        };
+        seal(data, &options).unwrap()
+    }
+
+    fn read(sealed: &[u8], tree: &[u8], range: Range<u64>) -> Result<Vec<u8>> {
+        let root = root(sealed).unwrap();
+        let options = OpenOptions::default();
+        decode_range(
+            Cursor::new(sealed),
+            Cursor::new(tree),
+            &root,
+            range,
+            &options,
+        )
+    }
+
+    fn outboard_error(result: Result<Vec<u8>>) -> OutboardError {
+        result.unwrap_err().downcast().unwrap()
+    }
+
+    #[test]
+    fn ranges_match_full_decode() {
+        let data = sample();
+        let sealed = indexed(&data);
+        let tree = outboard(&sealed).unwrap();
+        assert_eq!(
+            crate::seal::open(&sealed, &OpenOptions::default()).unwrap(),
+            data
+        );
+        let len = data.len() as u64;
+        let b = BLOCK_SIZE as u64;
+        for range in [
+            0..0,
+            0..1,
+            0..len,
+            b - 1..b + 1,
+            3 * b..4 * b,
+            len - 5..len,
+            17..17,
+        ] {
+            let expected = &data[range.start as usize..range.end as usize];
+            assert_eq!(
+                read(&sealed, &tree, range.clone()).unwrap(),
+                expected,
+                "{range:?}"
+            );
+        }
+        assert_eq!(
+            outboard_error(read(&sealed, &tree, 0..len + 1)),
+            OutboardError::OutOfRange {
+                start: 0,
+                end: len + 1,
+                len
+            }
+        );
+    }
+
+    #[test]
+    fn only_touched_blocks_are_checked() {
+        let data = sample();
+        let mut sealed = indexed(&data);
+        let tree = outboard(&sealed).unwrap();
+        // Corrupt a byte near the end of the payload (inside the last block).
+        let at = sealed.len() - HASH_SIZE - 10;
+        sealed[at] ^= 1;
+        assert_eq!(read(&sealed, &tree, 0..100).unwrap(), &data[..100]);
+        let len = data.len() as u64;
+        assert_eq!(
+            outboard_error(read(&sealed, &tree, len - 10..len)),
+            OutboardError::Corrupt
+        );
+    }
+
+    #[test]
+    #[allow(clippy::needless_update)] // `encryption` is feature-gated
+    fn unindexed_and_keyed_frames_are_rejected() {
+        let data = sample();
+        let plain = seal(&data, &SealOptions::default()).unwrap();
+        let tree = outboard(&plain).unwrap();
+        assert_eq!(
+            outboard_error(read(&plain, &tree, 0..1)),
+            OutboardError::NotIndexed
+        );
+        let keyed = seal(
+            &data,
+            &SealOptions {
+                mac_key: Some(&[3; 32]),
+                block_index: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(outboard_error(outboard(&keyed)), OutboardError::Keyed);
+        let crc = seal(
+            &data,
+            &SealOptions {
+                checksum: Checksum::Crc32c,
+                block_index: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            outboard_error(outboard(&crc)),
+            OutboardError::UnsupportedChecksum(Checksum::Crc32c)
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_ranges_decrypt_single_blocks() {
+        use crate::crypto::Secret;
+
+        let data = sample();
+        let secret = Secret::raw([4; 32]);
+        let sealed = seal(
+            &data,
+            &SealOptions {
+                encryption: Some(&secret),
+                block_index: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let tree = outboard(&sealed).unwrap();
+        let root = root(&sealed).unwrap();
+        let options = OpenOptions {
+            decryption: Some(&secret),
+            ..Default::default()
+        };
+        let len = data.len() as u64;
+        for range in [0..10, 5000..9000, len - 1..len] {
+            let got = decode_range(
+                Cursor::new(&sealed),
+                Cursor::new(&tree),
+                &root,
+                range.clone(),
+                &options,
+            )
+            .unwrap();
+            assert_eq!(got, &data[range.start as usize..range.end as usize]);
+        }
+        let err = read(&sealed, &tree, 0..10).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&CryptoError::SecretRequired));
+    }
+}
@@ -1,5 +1,6 @@
 //! Handles raw (uncompressed) data blocks.
 
+use alloc::vec::Vec;
 use anyhow::Result;
 
 /// Returns the input data as a Vec. Length is handled by the caller.
@@ -0,0 +1,470 @@
+//! Sealed streams (`.bsc`): compressed payload plus integrity footer, with an
+//! optional keyed MAC and optional encryption.
+//!
+//! [`seal`] writes a legacy headerless frame when no option is set, so its
+//! output stays readable by older decoders. [`open`] accepts every frame
+//! kind described in [`crate::frame`].
+
+use crate::block_coder::BLOCK_SIZE;
+#[cfg(feature = "encryption")]
+use crate::crypto::{BlockCipher, CryptoError, Secret};
+use crate::encode::{decode_parallel_with_limits, encode_parallel};
+#[cfg(feature = "encryption")]
+use crate::frame::KEY_CHECK_SIZE;
+use crate::frame::{
+    BlockIndex, FrameError, FrameHeader, FLAG_CHECKSUM, FLAG_CONTENT_HASH, FLAG_ENCRYPTED,
+    FLAG_INDEXED, FLAG_KEYED,
+};
+use crate::integrity::{self, Checksum, Hasher, IntegrityError, KEY_SIZE};
+use crate::limits::DecodeLimits;
+#[cfg(feature = "signing")]
+use crate::signature::{self, VerifyingKey};
+use anyhow::Result;
+
+/// How [`seal`] protects a frame.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SealOptions<'a> {
+    /// Seal with `blake3::keyed_hash` instead of a plain digest.
+    pub mac_key: Option<&'a [u8; KEY_SIZE]>,
+    /// Encrypt every block with XChaCha20-Poly1305.
+    #[cfg(feature = "encryption")]
+    pub encryption: Option<&'a Secret>,
+    /// Store a [`BlockIndex`] in the header so single blocks can be located
+    /// without reading the whole payload (see [`crate::outboard`]).
+    pub block_index: bool,
+    /// Footer algorithm for unkeyed frames; keyed frames always use Blake3.
+    pub checksum: Checksum,
+    /// Store `blake3(data)` in the header; [`open`] checks it after decoding
+    /// and [`crate::frame::peek`] reads it without decoding. It is not
+    /// encrypted, so it reveals whether an encrypted frame holds known data.
+    pub content_hash: bool,
+}
+
+/// What [`open`] needs to verify and decode a frame.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenOptions<'a> {
+    /// Required for keyed frames; with a key, unkeyed frames are rejected.
+    pub mac_key: Option<&'a [u8; KEY_SIZE]>,
+    /// Required for encrypted frames; with a secret, unencrypted frames are
+    /// rejected.
+    #[cfg(feature = "encryption")]
+    pub decryption: Option<&'a Secret>,
+    /// Require a valid signature trailer by this key. Without it, a trailer
+    /// is stripped but not checked.
+    #[cfg(feature = "signing")]
+    pub verifying_key: Option<&'a VerifyingKey>,
+    pub limits: DecodeLimits,
+}
+
+/// Compresses `data` and seals it as described by `options`.
+pub fn seal(data: &[u8], options: &SealOptions) -> Result<Vec<u8>> {
+    #[cfg(all(feature = "licensing", feature = "encryption", not(any(test, fuzzing))))]
+    if options.encryption.is_some() {
+        crate::license::require(crate::license::Feature::Encryption)?;
+    }
+    let compressed = encode_parallel(data)?;
+    let mut header = footer_header(options)?;
+    if options.content_hash {
+        header.flags |= FLAG_CONTENT_HASH;
+        header.content_hash = Some(*blake3::hash(data).as_bytes());
+    }
+
+    #[cfg(feature = "encryption")]
+    let (payload, cipher) = match options.encryption {
+        Some(secret) => {
+            let (params, cipher) = BlockCipher::for_sealing(secret)?;
+            header.flags |= FLAG_ENCRYPTED;
+            header.encryption = Some(params);
+            let mut records = Vec::with_capacity(compressed.len() + compressed.len() / 256 + 16);
+            cipher.encrypt_blocks(&compressed, &mut records)?;
+            (records, Some(cipher))
+        }
+        None => (compressed, None),
+    };
+    #[cfg(not(feature = "encryption"))]
+    let payload = compressed;
+
+    if options.block_index {
+        header.flags |= FLAG_INDEXED;
+        header.index = Some(BlockIndex::build(
+            &payload,
+            data.len() as u64,
+            BLOCK_SIZE as u32,
+        )?);
+    }
+    if header.flags == 0 {
+        return Ok(integrity::add_footer(&payload));
+    }
+
+    let mut out =
+        Vec::with_capacity(header.encoded_len() + payload.len() + options.checksum.size());
+    header.write(&mut out);
+    #[cfg(feature = "encryption")]
+    if let Some(cipher) = cipher {
+        let aad_len = out.len() - KEY_CHECK_SIZE;
+        let key_check = cipher.key_check(&out[..aad_len]);
+        out[aad_len..].copy_from_slice(&key_check);
+    }
+    out.extend_from_slice(&payload);
+    integrity::push_footer(&mut out, options.mac_key, options.checksum);
+    Ok(out)
+}
+
+/// The header fields that choose the footer.
+fn footer_header(options: &SealOptions) -> Result<FrameHeader, IntegrityError> {
+    let mut header = FrameHeader::default();
+    if options.mac_key.is_some() {
+        header.flags |= FLAG_KEYED;
+    }
+    if options.checksum != Checksum::Blake3 {
+        if options.mac_key.is_some() {
+            return Err(FrameError::KeyedChecksum.into());
+        }
+        header.flags |= FLAG_CHECKSUM;
+        header.checksum = Some(options.checksum);
+    }
+    Ok(header)
+}
+
+/// [`seal`] for data that arrives in pieces: each piece is compressed and
+/// returned right away, so memory use does not depend on the total size.
+/// The output of [`start`](Self::start), every [`update`](Self::update) and
+/// [`finish`](Self::finish), concatenated, equals `seal` of all pieces.
+pub(crate) struct Sealer {
+    hasher: Hasher,
+}
+
+impl Sealer {
+    /// Whether `options` can be sealed in pieces. Content hashes and block
+    /// indexes go in the header and need all data first; encrypted frames
+    /// are not supported.
+    pub(crate) fn supports(options: &SealOptions) -> bool {
+        #[cfg(feature = "encryption")]
+        if options.encryption.is_some() {
+            return false;
+        }
+        !options.content_hash && !options.block_index
+    }
+
+    /// Returns the sealer and the frame header to write first.
+    pub(crate) fn start(options: &SealOptions) -> Result<(Self, Vec<u8>)> {
+        debug_assert!(Self::supports(options));
+        let header = footer_header(options)?;
+        let mut hasher = match options.mac_key {
+            Some(key) => Hasher::new_keyed(key),
+            None => Hasher::new(options.checksum),
+        };
+        let mut out = Vec::new();
+        // Without flags this is a legacy frame, which has no header.
+        if header.flags != 0 {
+            header.write(&mut out);
+            hasher.update(&out);
+        }
+        Ok((Self { hasher }, out))
+    }
+
+    /// Compresses the next piece. All but the last must be a multiple of
+    /// [`BLOCK_SIZE`] long.
+    pub(crate) fn update(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let payload = encode_parallel(data)?;
+        self.hasher.update(&payload);
+        Ok(payload)
+    }
+
+    /// Returns the footer.
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.hasher.finalize().as_bytes().to_vec()
+    }
+}
+
+/// Verifies and decodes a frame produced by [`seal`] (or by
+/// `integrity::add_footer` over an `encode_parallel` stream).
+pub fn open(data: &[u8], options: &OpenOptions) -> Result<Vec<u8>> {
+    #[cfg(feature = "signing")]
+    let data = match options.verifying_key {
+        Some(key) => signature::verify_signature(data, key)?,
+        None => signature::strip_signature(data),
+    };
+    let (header, payload) = integrity::verify_frame(data, options.mac_key)?;
+
+    #[cfg(feature = "encryption")]
+    let decrypted = match (
+        header.as_ref().and_then(|h| h.encryption),
+        options.decryption,
+    ) {
+        (Some(params), Some(secret)) => {
+            let header_len = header.as_ref().map_or(0, FrameHeader::encoded_len);
+            let aad = &data[..header_len - KEY_CHECK_SIZE];
+            let cipher = BlockCipher::for_opening(secret, &params, aad)?;
+            Some(cipher.decrypt_blocks(payload)?)
+        }
+        (Some(_), None) => return Err(CryptoError::SecretRequired.into()),
+        (None, Some(_)) => return Err(CryptoError::NotEncrypted.into()),
+        (None, None) => None,
+    };
+    #[cfg(feature = "encryption")]
+    let payload = decrypted.as_deref().unwrap_or(payload);
+    #[cfg(not(feature = "encryption"))]
+    if header.as_ref().is_some_and(|h| h.has(FLAG_ENCRYPTED)) {
+        anyhow::bail!("frame is encrypted, but encryption support is not compiled in");
+    }
+
+    let decoded = decode_parallel_with_limits(payload, &options.limits)?;
+    if let Some(header) = &header {
+        integrity::check_content(header, &decoded)?;
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrity::HASH_SIZE;
+
+    const DATA: &[u8] = b"sealed frames keep their payload intact, sealed frames keep ...";
+
+    #[test]
+    fn plain_frames_are_legacy_compatible() {
+        let sealed = seal(DATA, &SealOptions::default()).unwrap();
+        let payload = integrity::verify_footer(&sealed).unwrap();
+        assert_eq!(crate::decode_parallel(payload).unwrap(), DATA);
+        assert_eq!(open(&sealed, &OpenOptions::default()).unwrap(), DATA);
+    }
+
+    #[test]
+    fn sealing_in_pieces_matches_seal() {
+        let key = [7u8; KEY_SIZE];
+        let data = DATA.repeat(200);
+        for options in [
+            SealOptions::default(),
+            SealOptions {
+                mac_key: Some(&key),
+                ..Default::default()
+            },
+            SealOptions {
+                checksum: Checksum::Crc32c,
+                ..Default::default()
+            },
+        ] {
+            assert!(Sealer::supports(&options));
+            let (mut sealer, mut out) = Sealer::start(&options).unwrap();
+            for piece in data.chunks(2 * BLOCK_SIZE) {
+                out.extend(sealer.update(piece).unwrap());
+            }
+            out.extend(sealer.finish());
+            assert_eq!(out, seal(&data, &options).unwrap());
+        }
+        assert!(!Sealer::supports(&SealOptions {
+            content_hash: true,
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    #[allow(clippy::needless_update)] // `encryption` fields are feature-gated
+    fn keyed_frames_roundtrip() {
+        let key = [5u8; KEY_SIZE];
+        let sealed = seal(
+            DATA,
+            &SealOptions {
+                mac_key: Some(&key),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let options = OpenOptions {
+            mac_key: Some(&key),
+            ..Default::default()
+        };
+        assert_eq!(open(&sealed, &options).unwrap(), DATA);
+        assert!(open(&sealed, &OpenOptions::default()).is_err());
+    }
+
+    #[test]
+    fn content_hash_survives_reencoding_and_is_checked() {
+        use crate::frame::peek;
+
+        let hashed = |checksum| SealOptions {
+            content_hash: true,
+            checksum,
+            ..Default::default()
+        };
+        let a = seal(DATA, &hashed(Checksum::Blake3)).unwrap();
+        let b = seal(DATA, &hashed(Checksum::Crc32c)).unwrap();
+        assert_ne!(a, b);
+        let hash = *blake3::hash(DATA).as_bytes();
+        assert_eq!(peek(&a).unwrap().content_hash(), Some(hash));
+        assert_eq!(peek(&b).unwrap().content_hash(), Some(hash));
+        assert_eq!(open(&b, &OpenOptions::default()).unwrap(), DATA);
+
+        // A wrong content hash under a valid footer is caught after decoding.
+        let mut body = a[..a.len() - HASH_SIZE].to_vec();
+        body[5] ^= 1;
+        let forged = integrity::add_footer(&body);
+        let err = open(&forged, &OpenOptions::default()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(IntegrityError::ContentMismatch)
+        ));
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn signed_frames_open_with_and_without_key() {
+        use crate::signature::{sign, SignatureError, SigningKey};
+
+        let signing_key = SigningKey::from_bytes(&[9; 32]);
+        let key = signing_key.verifying_key();
+        let sealed = seal(DATA, &SealOptions::default()).unwrap();
+        let signed = sign(&sealed, &signing_key).unwrap();
+        assert_eq!(open(&signed, &OpenOptions::default()).unwrap(), DATA);
+        let options = OpenOptions {
+            verifying_key: Some(&key),
+            ..Default::default()
+        };
+        assert_eq!(open(&signed, &options).unwrap(), DATA);
+        let err = open(&sealed, &options).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&SignatureError::Unsigned));
+    }
+
+    #[cfg(feature = "encryption")]
+    mod encrypted {
+        use super::*;
+        use crate::block_coder::BLOCK_SIZE;
+        use crate::crypto::Argon2Params;
+
+        fn fast_password(password: &str) -> Secret {
+            Secret::password(password).with_argon2_params(Argon2Params {
+                m_cost: 64,
+                t_cost: 1,
+                p_cost: 1,
+            })
+        }
+
+        fn seal_with(data: &[u8], secret: &Secret) -> Vec<u8> {
+            let options = SealOptions {
+                encryption: Some(secret),
+                ..Default::default()
+            };
+            seal(data, &options).unwrap()
+        }
+
+        fn open_with(data: &[u8], secret: &Secret) -> Result<Vec<u8>> {
+            let options = OpenOptions {
+                decryption: Some(secret),
+                ..Default::default()
+            };
+            open(data, &options)
+        }
+
+        fn crypto_error(result: Result<Vec<u8>>) -> CryptoError {
+            result.unwrap_err().downcast::<CryptoError>().unwrap()
+        }
+
+        #[test]
+        fn raw_key_roundtrip() {
+            let secret = Secret::raw([1; 32]);
+            let data: Vec<u8> = (0..BLOCK_SIZE * 3 + 17).map(|i| (i % 7) as u8).collect();
+            for input in [&data[..], DATA, b""] {
+                let sealed = seal_with(input, &secret);
+                assert_eq!(open_with(&sealed, &secret).unwrap(), input);
+            }
+        }
+
+        #[test]
+        fn password_roundtrip_and_wrong_password() {
+            let secret = fast_password("correct horse");
+            let sealed = seal_with(DATA, &secret);
+            assert!(!sealed.windows(8).any(|w| w == &DATA[..8]));
+            assert_eq!(
+                open_with(&sealed, &fast_password("correct horse")).unwrap(),
+                DATA
+            );
+            assert_eq!(
+                crypto_error(open_with(&sealed, &fast_password("battery staple"))),
+                CryptoError::WrongKey
+            );
+            assert_eq!(
+                crypto_error(open_with(&sealed, &Secret::raw([0; 32]))),
+                CryptoError::PasswordRequired
+            );
+        }
+
+        #[test]
+        fn wrong_key_and_missing_secret_are_typed() {
+            let sealed = seal_with(DATA, &Secret::raw([1; 32]));
+            assert_eq!(
+                crypto_error(open_with(&sealed, &Secret::raw([2; 32]))),
+                CryptoError::WrongKey
+            );
+            assert_eq!(
+                crypto_error(open(&sealed, &OpenOptions::default())),
+                CryptoError::SecretRequired
+            );
+            let plain = seal(DATA, &SealOptions::default()).unwrap();
+            assert_eq!(
+                crypto_error(open_with(&plain, &Secret::raw([1; 32]))),
+                CryptoError::NotEncrypted
+            );
+        }
+
+        #[test]
+        fn tampered_and_truncated_blocks_are_detected() {
+            let secret = Secret::raw([1; 32]);
+            let data: Vec<u8> = (0..BLOCK_SIZE * 2).map(|i| (i * 31 % 251) as u8).collect();
+            let sealed = seal_with(&data, &secret);
+            let (header, payload) = integrity::verify_frame(&sealed, None).unwrap();
+            let header_len = header.unwrap().encoded_len();
+
+            // Flip a ciphertext byte and re-hash the (unkeyed) footer.
+            let mut body = sealed[..sealed.len() - HASH_SIZE].to_vec();
+            let last = body.len() - 1;
+            body[last] ^= 1;
+            let tampered = integrity::add_footer(&body);
+            assert_eq!(
+                crypto_error(open_with(&tampered, &secret)),
+                CryptoError::BlockAuthentication { index: 1 }
+            );
+
+            // Drop the last block.
+            let (first_len, varint_len) = crate::utils::read_varint_u64(payload).unwrap();
+            let cut = header_len + varint_len + first_len as usize;
+            let truncated = integrity::add_footer(&sealed[..cut]);
+            assert_eq!(
+                crypto_error(open_with(&truncated, &secret)),
+                CryptoError::BlockAuthentication { index: 0 }
+            );
+        }
+
+        #[test]
+        fn password_derivation_is_cached() {
+            let secret = fast_password("pw");
+            let a = seal_with(b"one", &secret);
+            let b = seal_with(b"two", &secret);
+            let kdf = |frame: &[u8]| {
+                FrameHeader::parse(frame)
+                    .unwrap()
+                    .unwrap()
+                    .0
+                    .encryption
+                    .unwrap()
+            };
+            assert_eq!(kdf(&a).kdf, kdf(&b).kdf);
+            assert_ne!(kdf(&a).nonce_prefix, kdf(&b).nonce_prefix);
+        }
+
+        #[test]
+        fn hostile_kdf_params_are_rejected() {
+            let secret = fast_password("pw");
+            let mut sealed = seal_with(DATA, &secret);
+            // m_cost follows magic, flags and the KDF id.
+            sealed[6..10].copy_from_slice(&u32::MAX.to_le_bytes());
+            let body = sealed[..sealed.len() - HASH_SIZE].to_vec();
+            let sealed = integrity::add_footer(&body);
+            assert_eq!(
+                crypto_error(open_with(&sealed, &fast_password("pw"))),
+                CryptoError::KdfParamsTooLarge
+            );
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Ed25519 signatures for sealed streams and archives.
+//!
+//! A signed file is the original bytes followed by a fixed-size trailer:
+//!
+//! ```text
+//! +-----------------+--------+-----------------+------------------+
+//! | content [...]   | key_id | signature       | magic            |
+//! | (.bsc or .bsa)  | 8 B    | 64 B (Ed25519)  | "BSTSIG\0\x01"   |
+//! +-----------------+--------+-----------------+------------------+
+//! ```
+//!
+//! The signed message is [`SIGNING_CONTEXT`] followed by `blake3(content)`,
+//! so the signature covers every byte before the trailer: for a sealed
+//! stream its integrity footer, for an archive its index and the footers of
+//! all entries. `key_id` is the first 8 bytes of `blake3(public_key)`; it
+//! lets a verifier holding several keys pick the right one and report which
+//! key signed a file.
+//!
+//! What unsigned content ends with depends on its layout:
+//! * sealed streams end with their integrity footer, a digest;
+//! * archives with the index first (`BSTSEAL\0`, `BSTSEAL\x01`) end with a
+//!   sealed frame, so with its digest, or with the index if they hold no
+//!   files;
+//! * streamed archives (`BSTSEAL\x02`) end with the index offset and
+//!   `archive::MAGIC_INDEX_END` (`BSTSIDX\x01`).
+//!
+//! A digest ends with the trailer magic only by a 2^-64 chance, and the
+//! streamed archive tail never does.
+
+use alloc::vec::Vec;
+use ed25519_dalek::{Signature, Signer};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+use thiserror::Error;
+
+/// Marks the end of a signature trailer.
+pub const TRAILER_MAGIC: [u8; 8] = *b"BSTSIG\x00\x01";
+/// Size of the key ID in the trailer.
+pub const KEY_ID_SIZE: usize = 8;
+/// Size of an Ed25519 signature.
+pub const SIGNATURE_SIZE: usize = ed25519_dalek::SIGNATURE_LENGTH;
+/// Total size of the trailer.
+pub const TRAILER_SIZE: usize = KEY_ID_SIZE + SIGNATURE_SIZE + TRAILER_MAGIC.len();
+/// Domain separation prefix of the signed message.
+pub const SIGNING_CONTEXT: &[u8] = b"bstseal signature v1\x00";
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("file is not signed")]
+    Unsigned,
+    #[error("file is already signed")]
+    AlreadySigned,
+    #[error("file is signed by key {}, not by the given key", hex(.0))]
+    UnknownKey([u8; KEY_ID_SIZE]),
+    #[error("signature is invalid")]
+    Invalid,
+}
+
+/// Parsed signature trailer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trailer {
+    pub key_id: [u8; KEY_ID_SIZE],
+    pub signature: [u8; SIGNATURE_SIZE],
+}
+
+/// Short identifier of `key`: the first 8 bytes of `blake3(key)`.
+pub fn key_id(key: &VerifyingKey) -> [u8; KEY_ID_SIZE] {
+    let mut id = [0u8; KEY_ID_SIZE];
+    id.copy_from_slice(&blake3::hash(key.as_bytes()).as_bytes()[..KEY_ID_SIZE]);
+    id
+}
+
+/// Returns `data` followed by a signature trailer made with `key`.
+pub fn sign(data: &[u8], key: &SigningKey) -> Result<Vec<u8>, SignatureError> {
+    if split_signature(data).is_some() {
+        return Err(SignatureError::AlreadySigned);
+    }
+    let signature = key.sign(&message(data));
+    let mut out = Vec::with_capacity(data.len() + TRAILER_SIZE);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&key_id(&key.verifying_key()));
+    out.extend_from_slice(&signature.to_bytes());
+    out.extend_from_slice(&TRAILER_MAGIC);
+    Ok(out)
+}
+
+/// Checks the trailer of `data` against `key` and returns the content
+/// **without** trailer.
+pub fn verify_signature<'a>(
+    data: &'a [u8],
+    key: &VerifyingKey,
+) -> Result<&'a [u8], SignatureError> {
+    let (content, trailer) = split_signature(data).ok_or(SignatureError::Unsigned)?;
+    if trailer.key_id != key_id(key) {
+        return Err(SignatureError::UnknownKey(trailer.key_id));
+    }
+    let signature = Signature::from_bytes(&trailer.signature);
+    key.verify_strict(&message(content), &signature)
+        .map_err(|_| SignatureError::Invalid)?;
+    Ok(content)
+}
+
+/// Splits `data` into content and trailer if it is signed.
+pub fn split_signature(data: &[u8]) -> Option<(&[u8], Trailer)> {
+    if data.len() < TRAILER_SIZE || !data.ends_with(&TRAILER_MAGIC) {
+        return None;
+    }
+    let (content, trailer) = data.split_at(data.len() - TRAILER_SIZE);
+    let (key_id, rest) = trailer.split_at(KEY_ID_SIZE);
+    Some((
+        content,
+        Trailer {
+            key_id: key_id.try_into().ok()?,
+            signature: rest[..SIGNATURE_SIZE].try_into().ok()?,
+        },
+    ))
+}
+
+/// Returns `data` without its signature trailer, if it has one. Does
+/// **not** check the signature.
+pub fn strip_signature(data: &[u8]) -> &[u8] {
+    split_signature(data).map_or(data, |(content, _)| content)
+}
+
+fn message(content: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(SIGNING_CONTEXT.len() + blake3::OUT_LEN);
+    message.extend_from_slice(SIGNING_CONTEXT);
+    message.extend_from_slice(blake3::hash(content).as_bytes());
+    message
+}
+
+/// Lowercase hex, for key IDs in messages.
+fn hex(bytes: &[u8]) -> alloc::string::String {
+    use core::fmt::Write;
+    let mut out = alloc::string::String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(out, "{b:02x}");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keypair(seed: u8) -> (SigningKey, VerifyingKey) {
+        let sk = SigningKey::from_bytes(&[seed; 32]);
+        let vk = sk.verifying_key();
+        (sk, vk)
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let (sk, vk) = keypair(1);
+        let signed = sign(b"sealed bytes", &sk).unwrap();
+        assert_eq!(signed.len(), b"sealed bytes".len() + TRAILER_SIZE);
+        assert_eq!(verify_signature(&signed, &vk).unwrap(), b"sealed bytes");
+        assert_eq!(strip_signature(&signed), b"sealed bytes");
+        assert_eq!(split_signature(&signed).unwrap().1.key_id, key_id(&vk));
+        assert_eq!(sign(&signed, &sk), Err(SignatureError::AlreadySigned));
+    }
+
+    #[test]
+    fn rejects_other_keys_tampering_and_unsigned_data() {
+        let (sk, vk) = keypair(1);
+        let (_, other) = keypair(2);
+        let signed = sign(b"sealed bytes", &sk).unwrap();
+        assert_eq!(
+            verify_signature(&signed, &other),
+            Err(SignatureError::UnknownKey(key_id(&vk)))
+        );
+
+        let mut tampered = signed.clone();
+        tampered[0] ^= 1;
+        assert_eq!(
+            verify_signature(&tampered, &vk),
+            Err(SignatureError::Invalid)
+        );
+
+        let mut bad_sig = signed.clone();
+        bad_sig[b"sealed bytes".len() + KEY_ID_SIZE] ^= 1;
+        assert_eq!(
+            verify_signature(&bad_sig, &vk),
+            Err(SignatureError::Invalid)
+        );
+
+        assert_eq!(
+            verify_signature(b"sealed bytes", &vk),
+            Err(SignatureError::Unsigned)
+        );
+        assert_eq!(strip_signature(b"sealed bytes"), b"sealed bytes");
+    }
+}
@@ -0,0 +1,532 @@
+//! Sequential decoder for streams produced by `encode_parallel`.
+//!
+//! Unlike [`crate::encode::decode_parallel`] this needs neither threads nor
+//! the whole stream in memory: input can be fed in arbitrary chunks and every
+//! completed block is decoded immediately. Only the current partial block is
+//! buffered. Available without the `std` feature.
+//!
+//! [`VerifyingDecoder`] does the same for a sealed stream (payload plus
+//! integrity footer): it hashes bytes as they arrive and checks the footer in
+//! [`VerifyingDecoder::finish`], so a file is read only once.
+
+use crate::block_coder::{self, decode_block_with_limits};
+use crate::frame::{FrameError, FrameHeader, CONTENT_HASH_SIZE, FLAG_ENCRYPTED, FLAG_KEYED, MAGIC};
+use crate::integrity::{self, Checksum, Hasher, IntegrityError, HASH_SIZE, KEY_SIZE};
+use crate::limits::{DecodeError, DecodeLimits};
+use crate::utils;
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+
+/// Longest possible u64 varint.
+const MAX_VARINT_LEN: usize = 10;
+
+/// Incremental block-by-block decoder.
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    pending: Vec<u8>,
+    limits: DecodeLimits,
+    produced: usize,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Number of decoded bytes produced so far.
+    pub fn produced(&self) -> usize {
+        self.produced
+    }
+
+    /// Feeds the next chunk of the encoded stream and appends every block it
+    /// completes to `out`.
+    pub fn update(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        if self.pending.is_empty() {
+            let used = self.decode_blocks(input, out)?;
+            self.pending.extend_from_slice(&input[used..]);
+        } else {
+            let mut pending = core::mem::take(&mut self.pending);
+            pending.extend_from_slice(input);
+            let used = self.decode_blocks(&pending, out)?;
+            pending.drain(..used);
+            self.pending = pending;
+        }
+        Ok(())
+    }
+
+    /// Ends the stream. Fails if it stopped in the middle of a block.
+    pub fn finish(self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        match utils::read_varint_u64(&self.pending) {
+            Some((len, varint_len)) => Err(DecodeError::IncompleteBlock {
+                len,
+                remaining: self.pending.len() - varint_len,
+            }
+            .into()),
+            None => Err(DecodeError::BadVarint.into()),
+        }
+    }
+
+    /// Decodes all complete blocks at the start of `data` and returns the
+    /// number of bytes consumed.
+    fn decode_blocks(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+        let mut pos = 0;
+        while pos < data.len() {
+            let rest = &data[pos..];
+            let Some((block_len, varint_len)) = utils::read_varint_u64(rest) else {
+                if rest.len() >= MAX_VARINT_LEN {
+                    return Err(DecodeError::BadVarint.into());
+                }
+                break; // varint continues in the next chunk
+            };
+            let limit = block_coder::max_encoded_len(&self.limits);
+            if block_len > limit {
+                return Err(DecodeError::BlockTooLarge {
+                    size: block_len,
+                    limit: limit as usize,
+                }
+                .into());
+            }
+            let Some(block) = rest.get(varint_len..varint_len + block_len as usize) else {
+                break; // block continues in the next chunk
+            };
+            let size = block_coder::declared_size(block, &self.limits)? as usize;
+            if size > self.limits.max_output_size - self.produced {
+                return Err(DecodeError::OutputTooLarge {
+                    limit: self.limits.max_output_size,
+                }
+                .into());
+            }
+            out.extend_from_slice(&decode_block_with_limits(block, &self.limits)?);
+            self.produced += size;
+            pos += varint_len + block.len();
+        }
+        Ok(pos)
+    }
+}
+
+/// Bytes kept back from the end of the input: they may be the footer or a
+/// signature trailer, which must not reach the hasher or the block decoder.
+#[cfg(feature = "signing")]
+const HOLDBACK: usize = HASH_SIZE + crate::signature::TRAILER_SIZE;
+#[cfg(not(feature = "signing"))]
+const HOLDBACK: usize = HASH_SIZE;
+
+/// Incremental decoder for a sealed stream (`integrity::verify` followed by
+/// [`StreamDecoder`], in one pass).
+///
+/// Accepts legacy and keyed frames; encrypted frames need
+/// `seal::open`. A signature trailer is stripped but not checked.
+///
+/// Without [`VerifyingDecoder::withhold_output`], decoded bytes are handed
+/// out before the footer is checked: discard them if
+/// [`VerifyingDecoder::finish`] fails.
+pub struct VerifyingDecoder {
+    key: Option<[u8; KEY_SIZE]>,
+    /// Header bytes seen so far; `None` once the frame kind is known.
+    header: Option<Vec<u8>>,
+    hasher: Hasher,
+    footer_len: usize,
+    tail: Vec<u8>,
+    blocks: StreamDecoder,
+    withheld: Option<Vec<u8>>,
+    /// Hash of the output so far and the content hash it must match.
+    content: Option<(blake3::Hasher, [u8; CONTENT_HASH_SIZE])>,
+}
+
+impl Default for VerifyingDecoder {
+    fn default() -> Self {
+        Self {
+            key: None,
+            header: Some(Vec::new()),
+            hasher: Hasher::new(Checksum::Blake3),
+            footer_len: HASH_SIZE,
+            tail: Vec::new(),
+            blocks: StreamDecoder::new(),
+            withheld: None,
+            content: None,
+        }
+    }
+}
+
+impl VerifyingDecoder {
+    /// Decoder for frames with a plain digest.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decoder for frames sealed with a keyed MAC. As with
+    /// `integrity::verify`, unkeyed frames are then rejected.
+    pub fn with_key(key: &[u8; KEY_SIZE]) -> Self {
+        Self {
+            key: Some(*key),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.blocks = StreamDecoder::with_limits(limits);
+        self
+    }
+
+    /// Keeps all output until the footer has been verified and returns it
+    /// from [`VerifyingDecoder::finish`] instead of [`VerifyingDecoder::update`].
+    pub fn withhold_output(mut self, withhold: bool) -> Self {
+        self.withheld = withhold.then(Vec::new);
+        self
+    }
+
+    /// Feeds the next chunk of the sealed stream. Decoded bytes are appended
+    /// to `out` unless output is withheld.
+    pub fn update(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let total = self.tail.len() + input.len();
+        if total <= HOLDBACK {
+            self.tail.extend_from_slice(input);
+            return Ok(());
+        }
+        let release = total - HOLDBACK;
+        let from_tail = release.min(self.tail.len());
+        let tail = core::mem::take(&mut self.tail);
+        self.consume(&tail[..from_tail], out)?;
+        self.consume(&input[..release - from_tail], out)?;
+        self.tail = tail;
+        self.tail.drain(..from_tail);
+        self.tail.extend_from_slice(&input[release - from_tail..]);
+        Ok(())
+    }
+
+    /// Ends the stream and checks the footer. Withheld output is appended to
+    /// `out` only if everything verified.
+    pub fn finish(mut self, out: &mut Vec<u8>) -> Result<()> {
+        let held = core::mem::take(&mut self.tail);
+        let tail = &held[..];
+        #[cfg(feature = "signing")]
+        let tail = crate::signature::strip_signature(tail);
+        let footer = match self.header.take() {
+            // Small frames can end before their header was complete.
+            Some(mut rest) => {
+                rest.extend_from_slice(tail);
+                let parsed = FrameHeader::parse(&rest).map_err(IntegrityError::from)?;
+                let footer_len = parsed.map_or(HASH_SIZE, |(h, _)| h.checksum().size());
+                let body_len =
+                    (rest.len().checked_sub(footer_len)).ok_or(IntegrityError::TooSmall)?;
+                let parsed = FrameHeader::parse(&rest[..body_len]).map_err(IntegrityError::from)?;
+                self.start(&rest[..body_len], parsed, out)?;
+                rest.split_off(body_len)
+            }
+            None => {
+                let body_len =
+                    (tail.len().checked_sub(self.footer_len)).ok_or(IntegrityError::TooSmall)?;
+                self.consume(&tail[..body_len], out)?;
+                tail[body_len..].to_vec()
+            }
+        };
+        self.blocks.finish()?;
+        integrity::check_digest(self.hasher.finalize(), &footer)?;
+        if let Some((hasher, expected)) = &self.content {
+            if hasher.finalize() != blake3::Hash::from(*expected) {
+                return Err(IntegrityError::ContentMismatch.into());
+            }
+        }
+        if let Some(withheld) = self.withheld {
+            out.extend_from_slice(&withheld);
+        }
+        Ok(())
+    }
+
+    fn consume(&mut self, bytes: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let Some(header) = self.header.as_mut() else {
+            self.hasher.update(bytes);
+            return self.decode(bytes, out);
+        };
+        header.extend_from_slice(bytes);
+        let header = core::mem::take(header);
+        let prefix = header.len().min(MAGIC.len());
+        if header[..prefix] != MAGIC[..prefix] {
+            self.header = None;
+            return self.start(&header, None, out);
+        }
+        match FrameHeader::parse(&header) {
+            Ok(Some(parsed)) => {
+                self.header = None;
+                self.start(&header, Some(parsed), out)
+            }
+            Ok(None) | Err(FrameError::Truncated) => {
+                self.header = Some(header);
+                Ok(())
+            }
+            Err(e) => Err(IntegrityError::from(e).into()),
+        }
+    }
+
+    /// Sets up hashing once the frame kind is known and processes the bytes
+    /// buffered so far.
+    fn start(
+        &mut self,
+        buffered: &[u8],
+        header: Option<(FrameHeader, usize)>,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        let flags = header.as_ref().map_or(0, |(h, _)| h.flags);
+        if flags & FLAG_ENCRYPTED != 0 {
+            bail!("encrypted frames cannot be decoded incrementally; use seal::open");
+        }
+        let checksum = header
+            .as_ref()
+            .map_or(Checksum::Blake3, |(h, _)| h.checksum());
+        self.footer_len = checksum.size();
+        self.content = header
+            .as_ref()
+            .and_then(|(h, _)| h.content_hash)
+            .map(|hash| (blake3::Hasher::new(), hash));
+        self.hasher = match (flags & FLAG_KEYED != 0, &self.key) {
+            (true, Some(key)) => Hasher::new_keyed(key),
+            (true, None) => return Err(IntegrityError::KeyRequired.into()),
+            (false, Some(_)) => return Err(IntegrityError::NotKeyed.into()),
+            (false, None) => Hasher::new(checksum),
+        };
+        self.hasher.update(buffered);
+        let header_len = header.map_or(0, |(_, len)| len);
+        self.decode(&buffered[header_len..], out)
+    }
+
+    fn decode(&mut self, bytes: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let out = self.withheld.as_mut().unwrap_or(out);
+        let start = out.len();
+        self.blocks.update(bytes, out)?;
+        if let Some((hasher, _)) = &mut self.content {
+            hasher.update(&out[start..]);
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a complete stream on the current thread.
+pub fn decode_sequential(encoded: &[u8]) -> Result<Vec<u8>> {
+    decode_sequential_with_limits(encoded, &DecodeLimits::default())
+}
+
+/// Same as [`decode_sequential`] with explicit limits.
+pub fn decode_sequential_with_limits(encoded: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+    let mut decoder = StreamDecoder::with_limits(*limits);
+    let mut out = Vec::new();
+    decoder.update(encoded, &mut out)?;
+    decoder.finish()?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_coder::{encode_block, BLOCK_SIZE};
+
+    /// Builds a stream the same way `encode_parallel` does, without threads.
+    fn encode_stream(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for chunk in data.chunks(BLOCK_SIZE) {
+            let block = encode_block(chunk).unwrap();
+            utils::push_varint_u64(&mut out, block.len() as u64);
+            out.extend_from_slice(&block);
+        }
+        out
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut data = b"sequential stream decoder ".repeat(400);
+        data.extend((0..3000u32).map(|i| (i * 7919 % 251) as u8));
+        data
+    }
+
+    #[test]
+    fn decodes_whole_stream() {
+        let data = sample();
+        assert_eq!(decode_sequential(&encode_stream(&data)).unwrap(), data);
+        assert!(decode_sequential(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn decodes_byte_by_byte() {
+        let data = sample();
+        let encoded = encode_stream(&data);
+        let mut decoder = StreamDecoder::new();
+        let mut out = Vec::new();
+        for byte in &encoded {
+            decoder
+                .update(core::slice::from_ref(byte), &mut out)
+                .unwrap();
+        }
+        decoder.finish().unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn rejects_truncated_stream() {
+        let encoded = encode_stream(&sample());
+        let err = decode_sequential(&encoded[..encoded.len() - 3]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DecodeError>(),
+            Some(DecodeError::IncompleteBlock { .. })
+        ));
+    }
+
+    #[test]
+    fn enforces_output_limit() {
+        let data = sample();
+        let limits = DecodeLimits {
+            max_output_size: data.len() - 1,
+            ..DecodeLimits::default()
+        };
+        let err = decode_sequential_with_limits(&encode_stream(&data), &limits).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DecodeError>(),
+            Some(DecodeError::OutputTooLarge { .. })
+        ));
+    }
+
+    fn feed(mut decoder: VerifyingDecoder, sealed: &[u8], chunk: usize) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        for part in sealed.chunks(chunk) {
+            decoder.update(part, &mut out)?;
+        }
+        decoder.finish(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn verifying_decoder_accepts_all_chunkings() {
+        let data = sample();
+        let key = [4u8; KEY_SIZE];
+        let plain = integrity::add_footer(&encode_stream(&data));
+        let keyed = integrity::add_keyed_footer(&encode_stream(&data), &key);
+        for chunk in [1, 7, 33, 4096, plain.len()] {
+            assert_eq!(feed(VerifyingDecoder::new(), &plain, chunk).unwrap(), data);
+            assert_eq!(
+                feed(VerifyingDecoder::with_key(&key), &keyed, chunk).unwrap(),
+                data
+            );
+        }
+        let empty = integrity::add_footer(&[]);
+        assert!(feed(VerifyingDecoder::new(), &empty, 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn verifying_decoder_checks_content_hash() {
+        use crate::frame::{FLAG_CONTENT_HASH, MAGIC};
+
+        let data = sample();
+        let frame = |hash: &[u8; 32]| {
+            let mut body = MAGIC.to_vec();
+            body.push(FLAG_CONTENT_HASH);
+            body.extend_from_slice(hash);
+            body.extend_from_slice(&encode_stream(&data));
+            integrity::add_footer(&body)
+        };
+        let good = frame(blake3::hash(&data).as_bytes());
+        assert_eq!(feed(VerifyingDecoder::new(), &good, 1000).unwrap(), data);
+        let err = feed(VerifyingDecoder::new(), &frame(&[0; 32]), 1000).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(IntegrityError::ContentMismatch)
+        ));
+    }
+
+    #[test]
+    fn verifying_decoder_uses_the_frame_checksum() {
+        let data = sample();
+        for checksum in Checksum::ALL {
+            for input in [&data[..], b""] {
+                let sealed = integrity::add_footer_with(&encode_stream(input), checksum);
+                for chunk in [1, 5, 4096, sealed.len()] {
+                    let out = feed(VerifyingDecoder::new(), &sealed, chunk).unwrap();
+                    assert_eq!(out, input, "{checksum}, chunk {chunk}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn verifying_decoder_detects_corruption_at_the_end() {
+        let data = sample();
+        let mut sealed = integrity::add_footer(&encode_stream(&data));
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+
+        let mut out = Vec::new();
+        let mut decoder = VerifyingDecoder::new();
+        decoder.update(&sealed, &mut out).unwrap();
+        assert!(
+            !out.is_empty() && data.starts_with(&out),
+            "output is streamed before verification"
+        );
+        let err = decoder.finish(&mut out).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IntegrityError>(),
+            Some(IntegrityError::Mismatch { .. })
+        ));
+
+        let mut out = Vec::new();
+        let mut decoder = VerifyingDecoder::new().withhold_output(true);
+        decoder.update(&sealed, &mut out).unwrap();
+        assert!(decoder.finish(&mut out).is_err());
+        assert!(out.is_empty(), "withheld output is dropped on failure");
+    }
+
+    #[test]
+    fn verifying_decoder_checks_footer_kind() {
+        let key = [4u8; KEY_SIZE];
+        let stream = encode_stream(b"keyed or not");
+        let keyed = integrity::add_keyed_footer(&stream, &key);
+        let err = feed(VerifyingDecoder::new(), &keyed, 3).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IntegrityError>(),
+            Some(IntegrityError::KeyRequired)
+        ));
+        let plain = integrity::add_footer(&stream);
+        let err = feed(VerifyingDecoder::with_key(&key), &plain, 3).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IntegrityError>(),
+            Some(IntegrityError::NotKeyed)
+        ));
+        let err = feed(VerifyingDecoder::new(), &[0; HASH_SIZE - 1], 3).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IntegrityError>(),
+            Some(IntegrityError::TooSmall)
+        ));
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn verifying_decoder_strips_signature_trailer() {
+        use crate::signature::{sign, SigningKey};
+        let data = sample();
+        let sealed = integrity::add_footer(&encode_stream(&data));
+        let signed = sign(&sealed, &SigningKey::from_bytes(&[1; 32])).unwrap();
+        for chunk in [1, 50, signed.len()] {
+            assert_eq!(feed(VerifyingDecoder::new(), &signed, chunk).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_block_header_early() {
+        let mut input = Vec::new();
+        utils::push_varint_u64(&mut input, u64::MAX >> 8);
+        let mut decoder = StreamDecoder::new();
+        let err = decoder.update(&input, &mut Vec::new()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DecodeError>(),
+            Some(DecodeError::BlockTooLarge { .. })
+        ));
+    }
+}
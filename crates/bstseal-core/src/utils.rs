@@ -1,6 +1,24 @@
 //! Varint encoding and decoding utilities.
 
+use alloc::vec::Vec;
+
+/// Appends a u64 as a varint to `out` and returns the number of bytes written.
+pub fn push_varint_u64(out: &mut Vec<u8>, mut value: u64) -> usize {
+    let mut bytes_written = 0;
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        bytes_written += 1;
+        if value == 0 {
+            out.push(byte);
+            return bytes_written;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
 /// Writes a u64 as a varint to a writer.
+#[cfg(feature = "std")]
 pub fn write_varint_u64<W: std::io::Write>(w: &mut W, mut value: u64) -> std::io::Result<usize> {
     let mut bytes_written = 0;
     loop {
@@ -0,0 +1,222 @@
+//! License checks as applications see them. Unit tests inside the crate skip
+//! the check, so these run against the library as built.
+#![cfg(feature = "std")]
+
+use bstseal_core::encode::{decode_parallel, encode_parallel};
+
+#[cfg(feature = "licensing")]
+mod licensed {
+    use bstseal_core::license::{self, dev_issuer, LicenseError, Tier};
+    use std::sync::{Mutex, MutexGuard, Once};
+
+    /// Workers in the global pool, more than the smallest tier allows.
+    #[cfg(feature = "parallel")]
+    pub const GLOBAL_THREADS: usize = 8;
+
+    /// The license is process-wide, so tests take turns. Usage ledgers and
+    /// key files go to a throwaway home.
+    pub fn exclusive() -> MutexGuard<'static, ()> {
+        static LOCK: Mutex<()> = Mutex::new(());
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            let home = tempfile::tempdir().unwrap().keep();
+            std::env::set_var("HOME", &home);
+            std::env::set_var("BSTSEAL_USAGE_LEDGER", home.join("usage.ledger"));
+            std::env::remove_var("BSTSEAL_LICENSE");
+            #[cfg(feature = "parallel")]
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(GLOBAL_THREADS)
+                .build_global()
+                .unwrap();
+        });
+        LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Installs a key of `tier` signed by the development issuer.
+    pub fn install(tier: Tier) {
+        let key = dev_issuer::license(tier, 30);
+        assert_eq!(license::set_license_key(key), Ok(tier));
+    }
+
+    pub fn uninstall() {
+        assert_eq!(
+            license::license_manager().set_key(None),
+            Err(LicenseError::MissingKey)
+        );
+    }
+}
+
+#[cfg(feature = "licensing")]
+#[test]
+fn codec_requires_a_license_key() {
+    use bstseal_core::license::LicenseError;
+    let _guard = licensed::exclusive();
+    licensed::uninstall();
+    for err in [
+        encode_parallel(b"data").unwrap_err(),
+        decode_parallel(&[]).unwrap_err(),
+    ] {
+        assert!(matches!(
+            err.downcast_ref::<LicenseError>(),
+            Some(LicenseError::MissingKey)
+        ));
+    }
+}
+
+#[cfg(all(feature = "licensing", feature = "parallel"))]
+#[test]
+fn worker_threads_are_capped_by_tier() {
+    use bstseal_core::encode::worker_threads;
+    use bstseal_core::license::Tier;
+    let _guard = licensed::exclusive();
+    for (tier, threads) in [
+        (Tier::Solo, 4),
+        (Tier::Startup, licensed::GLOBAL_THREADS),
+        (Tier::Solo, 4),
+        (Tier::Enterprise, licensed::GLOBAL_THREADS),
+    ] {
+        licensed::install(tier);
+        assert_eq!(worker_threads().unwrap(), threads, "{tier}");
+    }
+    licensed::uninstall();
+    assert!(worker_threads().is_err());
+}
+
+#[cfg(feature = "licensing")]
+#[test]
+fn signing_requires_startup() {
+    use bstseal_core::license::{self, CapabilityError, Feature, Tier};
+    let _guard = licensed::exclusive();
+    licensed::install(Tier::Solo);
+    let err = license::require(Feature::Signing).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<CapabilityError>(),
+        Some(&CapabilityError::Feature {
+            feature: Feature::Signing,
+            tier: Tier::Solo,
+            required: Tier::Startup,
+        })
+    );
+    assert!(
+        err.to_string().contains("requires the startup tier"),
+        "{err}"
+    );
+    licensed::install(Tier::Startup);
+    license::require(Feature::Signing).unwrap();
+}
+
+#[cfg(feature = "licensing")]
+#[test]
+fn solid_archives_require_startup() {
+    use bstseal_core::archive::ArchiveWriter;
+    use bstseal_core::license::{CapabilityError, Feature, Tier};
+    use bstseal_core::seal::SealOptions;
+    let _guard = licensed::exclusive();
+    let pack = || {
+        let mut writer = ArchiveWriter::new(Vec::new(), SealOptions::default()).solid(1024);
+        writer.add("a.json", b"{}")?;
+        writer.add("b.json", b"[]")?;
+        writer.finish()
+    };
+
+    licensed::install(Tier::Solo);
+    let err = pack().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<CapabilityError>(),
+        Some(&CapabilityError::Feature {
+            feature: Feature::HighRatio,
+            tier: Tier::Solo,
+            required: Tier::Startup,
+        })
+    );
+    licensed::install(Tier::Startup);
+    pack().unwrap();
+}
+
+#[cfg(feature = "licensing")]
+#[test]
+fn archives_over_the_tier_limit_are_refused() {
+    use bstseal_core::archive::{ArchiveWriter, MAGIC_INDEX_END, MAGIC_STREAMED};
+    use bstseal_core::license::{CapabilityError, Tier};
+    use bstseal_core::seal::SealOptions;
+    use std::fs::{self, File};
+    use std::io::{Seek, SeekFrom, Write};
+    let _guard = licensed::exclusive();
+
+    // An empty streamed archive with a sparse gap just under the Solo limit
+    // of 2 GiB before its index.
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.bsa");
+    let index_offset: u64 = (2 << 30) - 8;
+    let mut file = File::create(&path).unwrap();
+    file.write_all(MAGIC_STREAMED).unwrap();
+    file.seek(SeekFrom::Start(index_offset)).unwrap();
+    file.write_all(&0u32.to_le_bytes()).unwrap();
+    file.write_all(&index_offset.to_le_bytes()).unwrap();
+    file.write_all(MAGIC_INDEX_END).unwrap();
+    drop(file);
+    let len = fs::metadata(&path).unwrap().len();
+
+    licensed::install(Tier::Solo);
+    let mut writer = ArchiveWriter::open(&path, SealOptions::default()).unwrap();
+    let err = writer.add("more.txt", b"one file too many").unwrap_err();
+    match err.downcast_ref::<CapabilityError>() {
+        Some(CapabilityError::ArchiveTooLarge {
+            tier: Tier::Solo,
+            required: Tier::Startup,
+            limit,
+            size,
+        }) => assert!(size > limit),
+        other => panic!("unexpected error {other:?}: {err}"),
+    }
+    drop(writer);
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+    licensed::install(Tier::Startup);
+    let mut writer = ArchiveWriter::open(&path, SealOptions::default()).unwrap();
+    writer.add("more.txt", b"one file too many").unwrap();
+    writer.finish().unwrap();
+}
+
+#[cfg(feature = "metering")]
+#[test]
+fn batches_record_one_line_per_operation() {
+    use bstseal_core::license::usage::{self, Ledger};
+    use bstseal_core::license::Tier;
+    let _guard = licensed::exclusive();
+    licensed::install(Tier::Startup);
+    let ledger = Ledger::locate().unwrap();
+    let before = ledger.records().unwrap().len();
+    {
+        let _batch = usage::batch();
+        let sealed: Vec<_> = [&b"first"[..], b"second", b"third"]
+            .iter()
+            .map(|data| encode_parallel(data).unwrap())
+            .collect();
+        for data in &sealed {
+            decode_parallel(data).unwrap();
+        }
+        assert_eq!(ledger.records().unwrap().len(), before);
+    }
+    let records = ledger.records().unwrap();
+    let added: Vec<_> = records[before..]
+        .iter()
+        .map(|record| (record.operation, record.bytes))
+        .collect();
+    assert_eq!(
+        added,
+        [
+            (usage::Operation::Encode, 16),
+            (usage::Operation::Decode, 16)
+        ]
+    );
+}
+
+#[cfg(not(feature = "licensing"))]
+#[test]
+fn codec_runs_without_a_license_key() {
+    std::env::remove_var("BSTSEAL_LICENSE");
+    let data = b"no license needed ".repeat(500);
+    let encoded = encode_parallel(&data).unwrap();
+    assert_eq!(decode_parallel(&encoded).unwrap(), data);
+}
@@ -16,6 +16,8 @@ typedef enum {
     BSTSEAL_INTEGRITY_FAIL = 4,
     BSTSEAL_ALLOC_FAIL = 5,
     BSTSEAL_LICENSE_ERROR = 6,
+    BSTSEAL_SIGNATURE_FAIL = 7,
+    BSTSEAL_SECRET_REQUIRED = 8,
 } bstseal_error;
 
 // Compresses `input[0..len)` into newly allocated buffer.
@@ -24,17 +26,45 @@ typedef enum {
 int bstseal_encode(const uint8_t* input, size_t len,
                    uint8_t** out_ptr, size_t* out_len);
 
-// Decompresses buffer produced by bstseal_encode.
+// Decompresses buffer produced by bstseal_encode and checks the content hash
+// of frames that carry one. Encrypted frames return BSTSEAL_SECRET_REQUIRED.
 int bstseal_decode(const uint8_t* input, size_t len,
                    uint8_t** out_ptr, size_t* out_len);
 
+// Like bstseal_encode, but seals the output with a keyed Blake3 MAC.
+// `key` must point to 32 bytes.
+int bstseal_encode_keyed(const uint8_t* input, size_t len, const uint8_t* key,
+                         uint8_t** out_ptr, size_t* out_len);
+
+// Verifies the keyed MAC and decompresses buffer produced by
+// bstseal_encode_keyed. Returns BSTSEAL_INTEGRITY_FAIL on a wrong key or a
+// buffer without keyed MAC.
+int bstseal_decode_keyed(const uint8_t* input, size_t len, const uint8_t* key,
+                         uint8_t** out_ptr, size_t* out_len);
+
+// Checks the Ed25519 signature trailer of `input[0..len)` against the 32-byte
+// `public_key`. Returns BSTSEAL_OK only for a valid signature by that key.
+// Decoding ignores the trailer, so call this first to reject unsigned data.
+int bstseal_verify_signature(const uint8_t* input, size_t len,
+                             const uint8_t* public_key);
+
 // Frees memory returned from encode/decode.
 void bstseal_free(void* ptr);
 
-// Sets license secret at runtime.
+// Sets the secret for legacy HMAC license keys; fails unless the library
+// was built with the `legacy-hmac` feature.
 int bstseal_set_license_secret(const char* secret);
-// Sets license key at runtime.
+// Sets or replaces the license key at runtime.
 int bstseal_set_license_key(const char* key);
+// Sets or replaces the signed license revocation list (file contents);
+// BSTSEAL_LICENSE_ERROR, keeping the previous list, if it does not verify.
+int bstseal_set_revocation_list(const char* list);
+// Re-checks the license key and revocation list now; BSTSEAL_OK if valid.
+// Keys are otherwise re-read every 60 seconds and expiry checked on every call.
+int bstseal_reload_license(void);
+// Re-checks the license every 60 seconds on a background thread, so expiry and
+// revocation are noticed while the library is idle. Repeated calls do nothing.
+void bstseal_watch_license(void);
 
 #ifdef __cplusplus
 } // extern "C"
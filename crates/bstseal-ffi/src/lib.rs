@@ -7,8 +7,11 @@
 //! • Caller must free returned buffers with `bstseal_free`.
 
 use bstseal_core::{
-    encode::{decode_parallel, encode_parallel},
-    integrity,
+    crypto::CryptoError,
+    encode::encode_parallel,
+    integrity::{self, IntegrityError},
+    seal::{self, OpenOptions},
+    signature::{self, VerifyingKey},
 };
 use libc::{c_int, c_void, c_char, free, malloc};
 use std::slice;
@@ -23,6 +26,9 @@ pub enum ErrorCode {
     IntegrityFail = 4,
     AllocFail = 5,
     LicenseError = 6,
+    SignatureFail = 7,
+    /// The frame is encrypted; these functions cannot decrypt it.
+    SecretRequired = 8,
 }
 
 unsafe fn alloc(len: usize) -> *mut u8 {
@@ -70,7 +76,8 @@ pub unsafe extern "C" fn bstseal_encode(
 }
 
 #[no_mangle]
-/// Verifies integrity footer and decompresses `input`.
+/// Verifies integrity footer and decompresses `input`, and checks the content
+/// hash of frames that carry one.
 ///
 /// On success returns [`ErrorCode::Ok`] and sets `out_ptr` / `out_len`.
 ///
@@ -87,25 +94,137 @@ pub unsafe extern "C" fn bstseal_decode(
     if input.is_null() || out_ptr.is_null() || out_len.is_null() {
         return ErrorCode::NullPointer as c_int;
     }
-    let data = slice::from_raw_parts(input, len);
-    let payload = match integrity::verify_footer(data) {
-        Ok(p) => p,
-        Err(_) => return ErrorCode::IntegrityFail as c_int,
-    };
-    let decoded = match decode_parallel(payload) {
-        Ok(d) => d,
-        Err(_) => return ErrorCode::DecodeFail as c_int,
+    open(slice::from_raw_parts(input, len), None, out_ptr, out_len)
+}
+
+/// Verifies and decodes a frame with [`seal::open`] into a buffer handed to
+/// the caller. A signature trailer is ignored.
+unsafe fn open(
+    data: &[u8],
+    mac_key: Option<&[u8; integrity::KEY_SIZE]>,
+    out_ptr: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    let options = OpenOptions {
+        mac_key,
+        ..Default::default()
     };
-    let buf = alloc(decoded.len());
+    match seal::open(data, &options) {
+        Ok(decoded) => copy_out(&decoded, out_ptr, out_len),
+        Err(err) => {
+            if let Some(CryptoError::SecretRequired) = err.downcast_ref() {
+                ErrorCode::SecretRequired as c_int
+            } else if err.is::<IntegrityError>() {
+                ErrorCode::IntegrityFail as c_int
+            } else {
+                ErrorCode::DecodeFail as c_int
+            }
+        }
+    }
+}
+
+/// Copies `data` into a `malloc`ed buffer handed to the caller.
+unsafe fn copy_out(data: &[u8], out_ptr: *mut *mut u8, out_len: *mut usize) -> c_int {
+    let buf = alloc(data.len());
     if buf.is_null() {
         return ErrorCode::AllocFail as c_int;
     }
-    std::ptr::copy_nonoverlapping(decoded.as_ptr(), buf, decoded.len());
+    std::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
     *out_ptr = buf;
-    *out_len = decoded.len();
+    *out_len = data.len();
     ErrorCode::Ok as c_int
 }
 
+#[no_mangle]
+/// Like [`bstseal_encode`], but seals the output with a keyed Blake3 MAC.
+///
+/// # Safety
+/// * `input` must point to `len` valid bytes.
+/// * `key` must point to [`integrity::KEY_SIZE`] (32) valid bytes.
+/// * `out_ptr` and `out_len` must be valid, non-null pointers.
+/// * Caller owns the returned buffer and must free it with [`bstseal_free`].
+pub unsafe extern "C" fn bstseal_encode_keyed(
+    input: *const u8,
+    len: usize,
+    key: *const u8,
+    out_ptr: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    if input.is_null() || key.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return ErrorCode::NullPointer as c_int;
+    }
+    let data = slice::from_raw_parts(input, len);
+    let key = &*(key as *const [u8; integrity::KEY_SIZE]);
+    let compressed = match encode_parallel(data) {
+        Ok(c) => c,
+        Err(_) => return ErrorCode::EncodeFail as c_int,
+    };
+    copy_out(
+        &integrity::add_keyed_footer(&compressed, key),
+        out_ptr,
+        out_len,
+    )
+}
+
+#[no_mangle]
+/// Verifies the keyed MAC written by [`bstseal_encode_keyed`] and
+/// decompresses `input`. Buffers without a keyed MAC are rejected with
+/// [`ErrorCode::IntegrityFail`].
+///
+/// # Safety
+/// * `input` must point to `len` valid bytes.
+/// * `key` must point to [`integrity::KEY_SIZE`] (32) valid bytes.
+/// * `out_ptr` and `out_len` must be valid, non-null pointers.
+/// * Caller owns the returned buffer and must free it with [`bstseal_free`].
+pub unsafe extern "C" fn bstseal_decode_keyed(
+    input: *const u8,
+    len: usize,
+    key: *const u8,
+    out_ptr: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    if input.is_null() || key.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return ErrorCode::NullPointer as c_int;
+    }
+    let key = &*(key as *const [u8; integrity::KEY_SIZE]);
+    open(
+        slice::from_raw_parts(input, len),
+        Some(key),
+        out_ptr,
+        out_len,
+    )
+}
+
+#[no_mangle]
+/// Checks the Ed25519 signature trailer of a sealed file or archive against
+/// `public_key`. Returns [`ErrorCode::Ok`] only for a valid signature by that
+/// key, [`ErrorCode::SignatureFail`] otherwise (unsigned, other key, forged).
+///
+/// [`bstseal_decode`] ignores the trailer, so call this first to reject
+/// unsigned or foreign data.
+///
+/// # Safety
+/// * `input` must point to `len` valid bytes.
+/// * `public_key` must point to 32 valid bytes.
+pub unsafe extern "C" fn bstseal_verify_signature(
+    input: *const u8,
+    len: usize,
+    public_key: *const u8,
+) -> c_int {
+    if input.is_null() || public_key.is_null() {
+        return ErrorCode::NullPointer as c_int;
+    }
+    let data = slice::from_raw_parts(input, len);
+    let key = match VerifyingKey::from_bytes(&*(public_key as *const [u8; 32])) {
+        Ok(k) => k,
+        Err(_) => return ErrorCode::SignatureFail as c_int,
+    };
+    match signature::verify_signature(data, &key) {
+        Ok(_) => ErrorCode::Ok as c_int,
+        Err(_) => ErrorCode::SignatureFail as c_int,
+    }
+}
+
 #[no_mangle]
 /// Frees a buffer allocated by [`bstseal_encode`] / [`bstseal_decode`].
 ///
@@ -118,8 +237,9 @@ pub unsafe extern "C" fn bstseal_free(ptr: *mut c_void) {
 }
 
 #[no_mangle]
-/// Sets license secret at runtime.
-/// Returns 0 on success.
+/// Sets the shared secret for legacy HMAC license keys at runtime.
+/// Returns 0 on success, or `LicenseError` when the library was built without
+/// the `legacy-hmac` feature.
 /// # Safety
 /// * `secret` must be a valid null-terminated UTF-8 string or NULL.
 pub unsafe extern "C" fn bstseal_set_license_secret(secret: *const c_char) -> c_int {
@@ -128,17 +248,21 @@ pub unsafe extern "C" fn bstseal_set_license_secret(secret: *const c_char) -> c_
     }
     let c_str = std::ffi::CStr::from_ptr(secret);
     match c_str.to_str() {
+        #[cfg(feature = "legacy-hmac")]
         Ok(s) => {
-            bstseal_core::license::set_license_secret(s.to_string());
+            let _ = bstseal_core::license::set_license_secret(s.to_string());
             ErrorCode::Ok as c_int
         }
+        #[cfg(not(feature = "legacy-hmac"))]
+        Ok(_) => ErrorCode::LicenseError as c_int,
         Err(_) => ErrorCode::LicenseError as c_int,
     }
 }
 
 #[no_mangle]
-/// Sets license key at runtime.
-/// Returns 0 on success.
+/// Sets or replaces the license key at runtime. The key is checked on the next
+/// operation, not here. Returns 0 on success. Without the `licensing` feature
+/// the key is ignored.
 /// # Safety
 /// * `key` must be a valid null-terminated UTF-8 string or NULL.
 pub unsafe extern "C" fn bstseal_set_license_key(key: *const c_char) -> c_int {
@@ -147,10 +271,140 @@ pub unsafe extern "C" fn bstseal_set_license_key(key: *const c_char) -> c_int {
     }
     let c_str = std::ffi::CStr::from_ptr(key);
     match c_str.to_str() {
-        Ok(k) => {
-            bstseal_core::license::set_license_key(k.to_string());
+        Ok(_k) => {
+            #[cfg(feature = "licensing")]
+            let _ = bstseal_core::license::set_license_key(_k.to_string());
+            ErrorCode::Ok as c_int
+        }
+        Err(_) => ErrorCode::LicenseError as c_int,
+    }
+}
+
+#[no_mangle]
+/// Sets or replaces the signed license revocation list (file contents) at
+/// runtime.
+/// Returns 0 on success, or `LicenseError` if the list does not verify, in
+/// which case the previous list stays active. Without the `licensing`
+/// feature the list is ignored.
+/// # Safety
+/// * `list` must be a valid null-terminated UTF-8 string or NULL.
+pub unsafe extern "C" fn bstseal_set_revocation_list(list: *const c_char) -> c_int {
+    if list.is_null() {
+        return ErrorCode::NullPointer as c_int;
+    }
+    let c_str = std::ffi::CStr::from_ptr(list);
+    match c_str.to_str() {
+        Ok(_list) => {
+            #[cfg(feature = "licensing")]
+            if !bstseal_core::license::set_revocation_list(_list) {
+                return ErrorCode::LicenseError as c_int;
+            }
             ErrorCode::Ok as c_int
         }
         Err(_) => ErrorCode::LicenseError as c_int,
     }
 }
+
+#[no_mangle]
+/// Re-reads the license key (runtime, env `BSTSEAL_LICENSE` or
+/// `~/.bstseal/license`) and revocation list and re-checks them now instead of
+/// at the next periodic check.
+/// Returns 0 if the license is valid. Always 0 without the `licensing` feature.
+pub extern "C" fn bstseal_reload_license() -> c_int {
+    #[cfg(feature = "licensing")]
+    if bstseal_core::license::reload_license().is_err() {
+        return ErrorCode::LicenseError as c_int;
+    }
+    ErrorCode::Ok as c_int
+}
+
+#[no_mangle]
+/// Re-checks the license every 60 seconds on a background thread, so expiry
+/// and revocation are noticed while the library is idle. Without it checks
+/// run on encode and decode only. Calling it again does nothing; without the
+/// `licensing` feature it does nothing at all.
+pub extern "C" fn bstseal_watch_license() {
+    #[cfg(feature = "licensing")]
+    bstseal_core::license::watch_license();
+}
+
+#[cfg(all(test, feature = "licensing"))]
+mod tests {
+    use super::*;
+    use bstseal_core::{
+        crypto::Secret,
+        frame::FrameHeader,
+        license::{self, dev_issuer, Tier},
+        seal::SealOptions,
+    };
+    use std::ffi::CString;
+
+    fn licensed() {
+        static ONCE: std::sync::Once = std::sync::Once::new();
+        ONCE.call_once(|| {
+            license::set_license_key(dev_issuer::license(Tier::Startup, 1)).unwrap();
+        });
+    }
+
+    fn decode(data: &[u8]) -> Result<Vec<u8>, c_int> {
+        let mut out_ptr = std::ptr::null_mut();
+        let mut out_len = 0;
+        let code = unsafe { bstseal_decode(data.as_ptr(), data.len(), &mut out_ptr, &mut out_len) };
+        if code != ErrorCode::Ok as c_int {
+            return Err(code);
+        }
+        let decoded = unsafe { slice::from_raw_parts(out_ptr, out_len) }.to_vec();
+        unsafe { bstseal_free(out_ptr as *mut c_void) };
+        Ok(decoded)
+    }
+
+    #[test]
+    fn decode_checks_the_content_hash() {
+        licensed();
+        let options = SealOptions {
+            content_hash: true,
+            ..Default::default()
+        };
+        let sealed = seal::seal(b"original", &options).unwrap();
+        assert_eq!(decode(&sealed).unwrap(), b"original");
+
+        // Same header, other payload, recomputed footer: only the content
+        // hash can tell.
+        let (_, header_len) = FrameHeader::parse(&sealed).unwrap().unwrap();
+        let mut forged = sealed[..header_len].to_vec();
+        forged.extend(encode_parallel(b"replaced").unwrap());
+        let forged = integrity::add_footer(&forged);
+        assert_eq!(decode(&forged), Err(ErrorCode::IntegrityFail as c_int));
+    }
+
+    #[test]
+    fn encrypted_frames_ask_for_a_secret() {
+        licensed();
+        let secret = Secret::raw([7; 32]);
+        let options = SealOptions {
+            encryption: Some(&secret),
+            ..Default::default()
+        };
+        let sealed = seal::seal(b"secret", &options).unwrap();
+        assert_eq!(decode(&sealed), Err(ErrorCode::SecretRequired as c_int));
+    }
+
+    #[test]
+    fn revocation_lists_that_do_not_verify_are_rejected() {
+        // The list is process-wide: revoke an id no other test uses.
+        let revoked = "3f6e9d2c-5a7b-4c1e-8d0f-2b4a6c8e0d1f";
+        let list = license::RevocationList::sign([revoked], &dev_issuer::signing_key());
+        let set = |list: &str| {
+            let list = CString::new(list).unwrap();
+            unsafe { bstseal_set_revocation_list(list.as_ptr()) }
+        };
+        assert_eq!(set(&list), ErrorCode::Ok as c_int);
+        let tampered = list.replace(revoked, "# removed");
+        assert_eq!(set(&tampered), ErrorCode::LicenseError as c_int);
+        assert_eq!(set("not a list"), ErrorCode::LicenseError as c_int);
+        assert_eq!(
+            unsafe { bstseal_set_revocation_list(std::ptr::null()) },
+            ErrorCode::NullPointer as c_int
+        );
+    }
+}
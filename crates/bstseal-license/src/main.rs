@@ -0,0 +1,291 @@
+//! Administrator tool for issuing and inspecting license keys.
+//!
+//! Keys are signed with the issuer's Ed25519 secret key, as written by
+//! `bstseal keygen` (64 hex digits or 32 raw bytes). Binaries built with
+//! `BSTSEAL_LICENSE_PUBKEY` set to its public half accept the keys.
+
+use anyhow::{bail, Context};
+use bstseal_core::license::{self, License, Tier};
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use clap::Parser;
+use ed25519_dalek::SigningKey;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Commands,
+}
+
+#[derive(Parser, Debug)]
+enum Commands {
+    /// Issues one license key and prints it
+    Issue {
+        /// Issuer secret key
+        #[clap(long)]
+        key: PathBuf,
+        /// solo, startup or enterprise
+        #[clap(long, value_parser = parse_tier)]
+        tier: Tier,
+        /// Expiry: RFC 3339 timestamp or YYYY-MM-DD (midnight UTC)
+        #[clap(long, value_parser = parse_expires)]
+        expires: DateTime<Utc>,
+        /// License UUID (default: a new random one)
+        #[clap(long, value_parser = parse_id)]
+        id: Option<String>,
+    },
+    /// Prints the fields of a license key and whether its signature verifies
+    Inspect {
+        license: String,
+        /// Issuer public key to check against (default: the compiled-in key)
+        #[clap(long)]
+        pubkey: Option<PathBuf>,
+    },
+    /// Issues a key per `id,tier,expires` CSV row (empty id: random) and
+    /// prints `id,tier,expires,key` rows
+    Batch {
+        /// Issuer secret key
+        #[clap(long)]
+        key: PathBuf,
+        /// CSV file, or `-` for stdin
+        csv: PathBuf,
+    },
+}
+
+fn main() -> anyhow::Result<()> {
+    match Cli::parse().command {
+        Commands::Issue {
+            key,
+            tier,
+            expires,
+            id,
+        } => {
+            let key = read_key(&key)?;
+            let id = id.map_or_else(random_id, Ok)?;
+            println!("{}", issue(id, tier, expires, &key)?);
+        }
+        Commands::Inspect { license, pubkey } => {
+            let issuer = match pubkey {
+                Some(path) => read_key_bytes(&path)?,
+                None => license::ISSUER_PUBLIC_KEY,
+            };
+            print!("{}", inspect(&license, &issuer)?);
+        }
+        Commands::Batch { key, csv } => {
+            let key = read_key(&key)?;
+            let stdout = io::stdout();
+            if csv == Path::new("-") {
+                batch(io::stdin().lock(), &key, stdout.lock())?;
+            } else {
+                let file =
+                    fs::File::open(&csv).with_context(|| format!("reading {}", csv.display()))?;
+                batch(BufReader::new(file), &key, stdout.lock())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Signs a key for `id`, refusing expiry dates in the past.
+fn issue(
+    id: String,
+    tier: Tier,
+    expires: DateTime<Utc>,
+    key: &SigningKey,
+) -> anyhow::Result<String> {
+    if expires <= Utc::now() {
+        bail!("expiry {} is in the past", rfc3339(&expires));
+    }
+    Ok(License { id, tier, expires }.sign(key))
+}
+
+/// Describes `license`, checking Ed25519 signatures against `issuer`.
+fn inspect(license: &str, issuer: &[u8; 32]) -> anyhow::Result<String> {
+    let fields = License::decode(license.trim())?;
+    let signature = match license::parse_license_with(license.trim(), issuer) {
+        Ok(_) => "valid".to_string(),
+        Err(e) => format!("INVALID ({e})"),
+    };
+    let state = if fields.is_expired() {
+        "expired".to_string()
+    } else {
+        format!("{} days left", fields.days_left())
+    };
+    Ok(format!(
+        "ID:        {}\nTier:      {}\nExpires:   {} ({state})\nSignature: {signature}\n",
+        fields.id,
+        fields.tier,
+        rfc3339(&fields.expires),
+    ))
+}
+
+/// Issues a key for every `id,tier,expires` row of `input`. Blank lines,
+/// `#` comments and an `id,...` header row are skipped.
+fn batch(input: impl BufRead, key: &SigningKey, mut out: impl Write) -> anyhow::Result<usize> {
+    writeln!(out, "id,tier,expires,key")?;
+    let mut issued = 0;
+    for (n, line) in input.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("id,") {
+            continue;
+        }
+        let row = || -> anyhow::Result<String> {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [id, tier, expires] = fields[..] else {
+                bail!("expected 3 columns (id,tier,expires)");
+            };
+            let id = match id {
+                "" => random_id()?,
+                id => parse_id(id)?,
+            };
+            let tier = parse_tier(tier)?;
+            let expires = parse_expires(expires)?;
+            let license = issue(id.clone(), tier, expires, key)?;
+            Ok(format!("{id},{tier},{},{license}", rfc3339(&expires)))
+        };
+        let row = row().with_context(|| format!("line {}", n + 1))?;
+        writeln!(out, "{row}")?;
+        issued += 1;
+    }
+    Ok(issued)
+}
+
+fn read_key(path: &Path) -> anyhow::Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&read_key_bytes(path)?))
+}
+
+/// Reads a 32-byte key stored raw or as 64 hex digits.
+fn read_key_bytes(path: &Path) -> anyhow::Result<[u8; 32]> {
+    let bytes = fs::read(path).with_context(|| format!("reading key file {}", path.display()))?;
+    let seed = match <[u8; 32]>::try_from(&bytes[..]) {
+        Ok(raw) => raw,
+        Err(_) => {
+            let text = bytes.trim_ascii();
+            let mut seed = [0u8; 32];
+            if text.len() != 64 || !text.is_ascii() {
+                bail!("{} is not a 32-byte key", path.display());
+            }
+            for (byte, pair) in seed.iter_mut().zip(text.chunks(2)) {
+                let pair = std::str::from_utf8(pair)?;
+                *byte = u8::from_str_radix(pair, 16)
+                    .with_context(|| format!("{} is not a 32-byte key", path.display()))?;
+            }
+            seed
+        }
+    };
+    Ok(seed)
+}
+
+/// Timestamps as written into keys: whole seconds, `Z` suffix.
+fn rfc3339(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn parse_tier(s: &str) -> anyhow::Result<Tier> {
+    Tier::PURCHASABLE
+        .into_iter()
+        .find(|tier| tier.as_str() == s)
+        .with_context(|| format!("unknown tier {s:?} (expected solo, startup or enterprise)"))
+}
+
+fn parse_expires(s: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_time(chrono::NaiveTime::MIN).and_utc());
+    }
+    Ok(DateTime::parse_from_rfc3339(s)
+        .with_context(|| format!("expiry {s:?} is neither RFC 3339 nor YYYY-MM-DD"))?
+        .with_timezone(&Utc))
+}
+
+/// Accepts canonical 8-4-4-4-12 hex UUIDs, lowercased.
+fn parse_id(s: &str) -> anyhow::Result<String> {
+    let groups: Vec<&str> = s.split('-').collect();
+    let lengths: Vec<usize> = groups.iter().map(|g| g.len()).collect();
+    if lengths != [8, 4, 4, 4, 12]
+        || !groups
+            .iter()
+            .all(|g| g.bytes().all(|b| b.is_ascii_hexdigit()))
+    {
+        bail!("{s:?} is not a UUID");
+    }
+    Ok(s.to_ascii_lowercase())
+}
+
+/// A random (version 4) UUID.
+fn random_id() -> anyhow::Result<String> {
+    let mut b = [0u8; 16];
+    getrandom::getrandom(&mut b)?;
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex: String = b.iter().map(|b| format!("{b:02x}")).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bstseal_core::license::dev_issuer::signing_key as dev_issuer;
+
+    #[test]
+    fn issued_keys_verify() {
+        let expires = parse_expires("2099-12-31").unwrap();
+        let id = random_id().unwrap();
+        assert_eq!(parse_id(&id).unwrap(), id);
+        let key = issue(id.clone(), Tier::Startup, expires, &dev_issuer()).unwrap();
+        assert_eq!(license::verify_license(&key).unwrap(), Tier::Startup);
+        let parsed = license::parse_license(&key).unwrap();
+        assert_eq!((&parsed.id, parsed.expires), (&id, expires));
+
+        let report = inspect(&key, &license::ISSUER_PUBLIC_KEY).unwrap();
+        assert!(report.contains("Tier:      startup"), "{report}");
+        assert!(report.contains("Signature: valid"), "{report}");
+        let other = SigningKey::from_bytes(&[5; 32]).verifying_key().to_bytes();
+        assert!(inspect(&key, &other)
+            .unwrap()
+            .contains("Signature: INVALID"));
+
+        assert!(issue(
+            id,
+            Tier::Solo,
+            parse_expires("2001-01-01").unwrap(),
+            &dev_issuer()
+        )
+        .is_err());
+        assert!(parse_tier("unknown").is_err());
+        assert!(parse_id("123e4567.e89b").is_err());
+    }
+
+    #[test]
+    fn batch_issues_a_key_per_row() {
+        let csv = "id,tier,expires\n\
+                   # comment\n\
+                   123E4567-E89B-12D3-A456-426614174000,solo,2099-01-01T00:00:00Z\n\
+                   \n\
+                   ,enterprise,2099-06-30\n";
+        let mut out = Vec::new();
+        assert_eq!(batch(csv.as_bytes(), &dev_issuer(), &mut out).unwrap(), 2);
+        let out = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = out.lines().collect();
+        assert_eq!(rows[0], "id,tier,expires,key");
+        assert!(rows[1].starts_with("123e4567-e89b-12d3-a456-426614174000,solo,"));
+        for (row, tier) in rows[1..].iter().zip([Tier::Solo, Tier::Enterprise]) {
+            let key = row.rsplit(',').next().unwrap();
+            assert_eq!(license::verify_license(key).unwrap(), tier);
+        }
+
+        let err = batch("x,solo,2099-01-01\n".as_bytes(), &dev_issuer(), Vec::new()).unwrap_err();
+        assert!(format!("{err:#}").starts_with("line 1: "), "{err:#}");
+        assert!(batch("a,b\n".as_bytes(), &dev_issuer(), Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! WebAssembly bindings for BST-SEAL.
+//!
+//! Built with `wasm-bindgen`; every function takes and returns `Uint8Array`.
+//! Decoding runs on the calling thread (the core `parallel` feature is off).
+//!
+//! ```js
+//! import init, { encode, decode, verify } from "bstseal-wasm";
+//! await init();
+//! const sealed = encode(new TextEncoder().encode("hello"));
+//! verify(sealed);          // true
+//! decode(sealed);          // Uint8Array [104, 101, 108, 108, 111]
+//! ```
+
+use bstseal_core::{
+    encode::{decode_parallel, encode_parallel},
+    integrity::{self, Checksum},
+};
+use wasm_bindgen::prelude::*;
+
+/// Compresses `data` and appends the integrity footer.
+#[wasm_bindgen]
+pub fn encode(data: &[u8]) -> Result<Vec<u8>, JsError> {
+    let compressed = encode_parallel(data).map_err(to_js)?;
+    Ok(integrity::add_footer(&compressed))
+}
+
+/// Like [`encode`], with the footer checksum named by `algorithm`
+/// (`blake3`, `blake3-128`, `xxh3-64`, `crc32c` or `sha256`).
+#[wasm_bindgen(js_name = encodeWithChecksum)]
+pub fn encode_with_checksum(data: &[u8], algorithm: &str) -> Result<Vec<u8>, JsError> {
+    let checksum = Checksum::from_name(algorithm)
+        .ok_or_else(|| JsError::new(&format!("unknown checksum algorithm {algorithm:?}")))?;
+    let compressed = encode_parallel(data).map_err(to_js)?;
+    Ok(integrity::add_footer_with(&compressed, checksum))
+}
+
+/// Verifies the integrity footer of `data` and decompresses it.
+#[wasm_bindgen]
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, JsError> {
+    let payload = integrity::verify(data, None)?;
+    decode_parallel(payload).map_err(to_js)
+}
+
+/// Returns `true` if the integrity footer of `data` matches its payload.
+#[wasm_bindgen]
+pub fn verify(data: &[u8]) -> bool {
+    integrity::verify(data, None).is_ok()
+}
+
+/// Sets the secret for legacy HMAC license keys at runtime. Returns `true`
+/// if the active license key now verifies.
+#[cfg(feature = "legacy-hmac")]
+#[wasm_bindgen(js_name = setLicenseSecret)]
+pub fn set_license_secret(secret: &str) -> bool {
+    bstseal_core::license::set_license_secret(secret).is_ok()
+}
+
+/// Sets or replaces the license key at runtime. Returns `true` if it verifies.
+#[cfg(feature = "licensing")]
+#[wasm_bindgen(js_name = setLicenseKey)]
+pub fn set_license_key(key: &str) -> bool {
+    bstseal_core::license::set_license_key(key).is_ok()
+}
+
+/// Re-checks the license key and revocation list now. Returns `true` if the
+/// license is valid.
+#[cfg(feature = "licensing")]
+#[wasm_bindgen(js_name = reloadLicense)]
+pub fn reload_license() -> bool {
+    bstseal_core::license::reload_license().is_ok()
+}
+
+/// Sets or replaces the signed license revocation list (file contents) at
+/// runtime. Returns `false`, keeping the previous list, if it does not
+/// verify.
+#[cfg(feature = "licensing")]
+#[wasm_bindgen(js_name = setRevocationList)]
+pub fn set_revocation_list(list: &str) -> bool {
+    bstseal_core::license::set_revocation_list(list)
+}
+
+fn to_js(err: anyhow::Error) -> JsError {
+    JsError::new(&format!("{err:#}"))
+}
@@ -0,0 +1,52 @@
+//! Headless tests, run in Node with
+//! `cargo test -p bstseal-wasm --target wasm32-unknown-unknown`
+//! (runner configured in `.cargo/config.toml`) or `wasm-pack test --node`.
+#![cfg(target_arch = "wasm32")]
+
+use bstseal_wasm::{decode, encode, encode_with_checksum, verify};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+/// Installs a key from the development issuer that test builds trust.
+#[cfg(feature = "licensing")]
+fn activate_license() {
+    use bstseal_core::license::{dev_issuer, Tier};
+    use std::sync::Once;
+
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        bstseal_wasm::set_license_key(&dev_issuer::license(Tier::Solo, 1));
+    });
+}
+
+/// Unlicensed builds need no key.
+#[cfg(not(feature = "licensing"))]
+fn activate_license() {}
+
+#[wasm_bindgen_test]
+fn roundtrip() {
+    activate_license();
+    let data = b"hello from wasm ".repeat(1000);
+    let sealed = encode(&data).unwrap();
+    assert!(sealed.len() < data.len());
+    assert!(verify(&sealed));
+    assert_eq!(decode(&sealed).unwrap(), data);
+}
+
+#[wasm_bindgen_test]
+fn detects_corruption() {
+    activate_license();
+    let mut sealed = encode(b"payload bytes").unwrap();
+    sealed[0] ^= 0x55;
+    assert!(!verify(&sealed));
+    assert!(decode(&sealed).is_err());
+}
+
+#[wasm_bindgen_test]
+fn small_checksums() {
+    activate_license();
+    let sealed = encode_with_checksum(b"hi", "crc32c").unwrap();
+    assert!(sealed.len() < encode(b"hi").unwrap().len());
+    assert!(verify(&sealed));
+    assert_eq!(decode(&sealed).unwrap(), b"hi");
+    assert!(encode_with_checksum(b"hi", "md5").is_err());
+}
@@ -0,0 +1,9 @@
+#![no_main]
+
+use bstseal_core::archive;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = archive::read_index(&mut &data[..]);
+    let _ = archive::ArchiveReader::new(std::io::Cursor::new(data));
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use bstseal_core::block_coder::{decode_block_with_limits, encode_block};
+use bstseal_core::DecodeLimits;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = DecodeLimits {
+        max_output_size: 1 << 20,
+        max_block_size: 1 << 20,
+    };
+    if let Ok(decoded) = decode_block_with_limits(data, &limits) {
+        // Anything the decoder accepts must survive a re-encode.
+        let reencoded = encode_block(&decoded).expect("encode");
+        let again = decode_block_with_limits(&reencoded, &limits).expect("roundtrip");
+        assert_eq!(again, decoded);
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use bstseal_core::encode::decode_parallel_with_limits;
+use bstseal_core::DecodeLimits;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = DecodeLimits {
+        max_output_size: 1 << 24,
+        max_block_size: 1 << 20,
+    };
+    let _ = decode_parallel_with_limits(data, &limits);
+});
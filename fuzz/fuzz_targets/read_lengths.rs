@@ -0,0 +1,11 @@
+#![no_main]
+
+use bstseal_core::huff::{self, CanonicalCode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = CanonicalCode::read_lengths(&mut std::io::Cursor::new(data));
+    // Also drive the bit decoder with whatever table was accepted.
+    let mut out = Vec::new();
+    let _ = huff::decode(data, &mut out, Some(data.len()));
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use bstseal_core::integrity::{add_footer, verify_footer};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(payload) = verify_footer(data) {
+        assert_eq!(add_footer(payload), data);
+    }
+});
@@ -1,33 +1,40 @@
 import { v4 as uuidv4 } from 'uuid';
-import { createHmac } from 'crypto';
+import { createPrivateKey, createPublicKey, sign, verify } from 'crypto';
+
+/** Domain separator signed in front of the license data (see license.rs). */
+const LICENSE_CONTEXT = Buffer.from('bstseal license v2\0');
+
+function signingKey() {
+  if (!process.env.LICENSE_SIGNING_KEY) {
+    throw new Error('LICENSE_SIGNING_KEY env variable not set');
+  }
+  return createPrivateKey(process.env.LICENSE_SIGNING_KEY);
+}
 
 /**
  * Generates a signed license string that can be later verified without DB lookup.
  * Format: <uuid>.<tier>.<expires_iso>.<signature>
  * expires_iso = ISO string (UTC) when license stops being valid (e.g. now+1y)
- * signature = base64url(HMAC_SHA256(uuid.tier.expires_iso, LICENSE_SECRET))
+ * signature = base64url(Ed25519(LICENSE_CONTEXT || uuid.tier.expires_iso))
+ * LICENSE_SIGNING_KEY holds the issuer's PKCS#8 PEM private key; binaries
+ * only embed its public half.
  */
 export function generateLicense(tier: string, validityDays = 365): string {
-  if (!process.env.LICENSE_SECRET) {
-    throw new Error('LICENSE_SECRET env variable not set');
-  }
+  const key = signingKey();
   const id = uuidv4();
   const expires = new Date(Date.now() + validityDays * 24 * 60 * 60 * 1000).toISOString();
   const data = `${id}.${tier}.${expires}`;
-  const sig = createHmac('sha256', process.env.LICENSE_SECRET)
-    .update(data)
-    .digest('base64url');
+  const sig = sign(null, Buffer.concat([LICENSE_CONTEXT, Buffer.from(data)]), key)
+    .toString('base64url');
   return `${data}.${sig}`;
 }
 
 export function verifyLicense(license: string): boolean {
-  if (!process.env.LICENSE_SECRET) return false;
+  if (!process.env.LICENSE_SIGNING_KEY) return false;
   const parts = license.split('.');
   if (parts.length < 4) return false;
-  const sigProvided = parts.pop()!; // last part
+  const sigProvided = Buffer.from(parts.pop()!, 'base64url'); // last part
   const data = parts.join('.');
-  const sigExpected = createHmac('sha256', process.env.LICENSE_SECRET)
-    .update(data)
-    .digest('base64url');
-  return sigProvided === sigExpected;
+  const publicKey = createPublicKey(signingKey());
+  return verify(null, Buffer.concat([LICENSE_CONTEXT, Buffer.from(data)]), publicKey, sigProvided);
 }